@@ -22,9 +22,15 @@ pub enum SegmentKind {
     Bss,
 }
 
+// Segment flags (the flags byte of the NV32 segment header). A segment without flags is
+// readable, writable and executable.
+pub const SEG_READ_ONLY: u8 = 0x1; // Stores into the segment fault
+pub const SEG_NO_EXEC: u8 = 0x2; // Instruction fetches from the segment fault
+
 #[derive(Debug, Clone)]
 pub struct NvSegment {
     pub kind: SegmentKind,
+    pub flags: u8,
     pub base_addr: u32,
     pub length_words: u32,
     pub words: Vec<u32>, // empty for BSS
@@ -76,6 +82,12 @@ enum Instruction {
     Jr   { rs: u8 },             // JR rs
     Jalr { rd: u8, rs: u8 },     // JALR rd, rs
 
+    // Special registers / exceptions
+    Mfsr { rd: u8, imm: Imm },   // MFSR rd, sreg
+    Mtsr { rs: u8, imm: Imm },   // MTSR rs, sreg
+    Eret,
+    Cache { op: Imm, base: u8, imm: Imm }, // CACHE op, imm(rs)
+
     // System / misc
     Nop,
     Halt,
@@ -118,10 +130,10 @@ pub fn assemble_nv32(source: &str) -> Result<Vec<NvSegment>, AsmError> {
 
         let (label_opt, rest) = split_label(&line)?;
 
-        if let Some(label) = label_opt {
-            if labels.insert(label.clone(), pc).is_some() {
-                return Err(AsmError::ParseError(format!("Duplicate label: {}", label)));
-            }
+        if let Some(label) = label_opt
+            && labels.insert(label.clone(), pc).is_some()
+        {
+            return Err(AsmError::ParseError(format!("Duplicate label: {}", label)));
         }
 
         let rest_trim = rest.trim();
@@ -214,20 +226,21 @@ pub fn assemble_nv32(source: &str) -> Result<Vec<NvSegment>, AsmError> {
     }
 
     // 2) Second pass: encode into sparse memory map
-    let mut mem = HashMap::<u32, u32>::new();
+    let mut mem = HashMap::<u32, (u32, bool)>::new(); // addr -> (word, is_code)
 
     // data
     for (addr, word) in data_words {
-        mem.insert(addr, word);
+        mem.insert(addr, (word, false));
     }
 
     // code
     for line in lines {
         let word = encode_instruction(line.instr, &labels, &equates, line.addr)?;
-        mem.insert(line.addr, word);
+        mem.insert(line.addr, (word, true));
     }
 
-    // 3) Build code/data segments by grouping contiguous addresses
+    // 3) Build code/data segments by grouping contiguous addresses of the same kind. Code
+    //    is read-only, data is not executable.
     let mut segments = Vec::<NvSegment>::new();
 
     let mut addrs: Vec<u32> = mem.keys().copied().collect();
@@ -236,15 +249,22 @@ pub fn assemble_nv32(source: &str) -> Result<Vec<NvSegment>, AsmError> {
     if !addrs.is_empty() {
         let mut cur_base = addrs[0];
         let mut cur_words = Vec::<u32>::new();
+        let mut cur_code = false;
         let mut prev_addr = addrs[0].wrapping_sub(4); // so first addr != prev+4
 
         for addr in addrs {
-            if addr != prev_addr.wrapping_add(4) {
+            let (w, is_code) = mem
+                .get(&addr)
+                .copied()
+                .expect("address disappeared from mem");
+
+            if addr != prev_addr.wrapping_add(4) || is_code != cur_code {
                 // flush previous segment if any
                 if !cur_words.is_empty() {
                     let len = cur_words.len() as u32;
                     segments.push(NvSegment {
                         kind: SegmentKind::CodeData,
+                        flags: segment_flags(cur_code),
                         base_addr: cur_base,
                         length_words: len,
                         words: cur_words,
@@ -252,12 +272,9 @@ pub fn assemble_nv32(source: &str) -> Result<Vec<NvSegment>, AsmError> {
                 }
                 cur_base = addr;
                 cur_words = Vec::new();
+                cur_code = is_code;
             }
 
-            let w = mem
-                .get(&addr)
-                .copied()
-                .expect("address disappeared from mem");
             cur_words.push(w);
             prev_addr = addr;
         }
@@ -265,6 +282,7 @@ pub fn assemble_nv32(source: &str) -> Result<Vec<NvSegment>, AsmError> {
         if !cur_words.is_empty() {
             segments.push(NvSegment {
                 kind: SegmentKind::CodeData,
+                flags: segment_flags(cur_code),
                 base_addr: cur_base,
                 length_words: cur_words.len() as u32,
                 words: cur_words,
@@ -276,6 +294,7 @@ pub fn assemble_nv32(source: &str) -> Result<Vec<NvSegment>, AsmError> {
     for (base, len_words) in bss_segments {
         segments.push(NvSegment {
             kind: SegmentKind::Bss,
+            flags: SEG_NO_EXEC,
             base_addr: base,
             length_words: len_words,
             words: Vec::new(),
@@ -288,6 +307,10 @@ pub fn assemble_nv32(source: &str) -> Result<Vec<NvSegment>, AsmError> {
     Ok(segments)
 }
 
+fn segment_flags(is_code: bool) -> u8 {
+    if is_code { SEG_READ_ONLY } else { SEG_NO_EXEC }
+}
+
 // -----------------------------
 // Helpers: comments / labels
 // -----------------------------
@@ -532,6 +555,30 @@ fn parse_instruction(line: &str, equates: &HashMap<String, u32>) -> Result<Vec<I
             Ok(vec![Instruction::Jalr { rd, rs }])
         }
 
+        // Special registers / exceptions
+        "mfsr" => {
+            // mfsr rd, sreg
+            let args = split_args(rest, 2)?;
+            let rd = parse_reg(args[0])?;
+            let imm = parse_imm_or_label(args[1], equates);
+            Ok(vec![Instruction::Mfsr { rd, imm }])
+        }
+        "mtsr" => {
+            // mtsr rs, sreg
+            let args = split_args(rest, 2)?;
+            let rs = parse_reg(args[0])?;
+            let imm = parse_imm_or_label(args[1], equates);
+            Ok(vec![Instruction::Mtsr { rs, imm }])
+        }
+        "eret" => Ok(vec![Instruction::Eret]),
+        "cache" => {
+            // cache op, imm(rs)
+            let args = split_args(rest, 2)?;
+            let op = parse_imm_or_label(args[0], equates);
+            let (base, imm) = parse_base_offset(args[1], equates)?;
+            Ok(vec![Instruction::Cache { op, base, imm }])
+        }
+
         // Pseudoinstructions
         "move" | "mv" => {
             // move rd, rs => addi rd, rs, 0
@@ -623,7 +670,7 @@ fn parse_instruction(line: &str, equates: &HashMap<String, u32>) -> Result<Vec<I
                 Imm::Label(name) => {
                     // Check if it's an equate with a known value
                     if let Some(&val) = equates.get(&name) {
-                        let upper = ((val as u32) >> 16) as i16;
+                        let upper = (val >> 16) as i16;
                         let lower = (val as u16) as i16;
                         Ok(vec![
                             Instruction::Lui { rd, imm: Imm::Value(upper) },
@@ -667,8 +714,19 @@ fn parse_ls(rest: &str, kind: LsKind, equates: &HashMap<String, u32>) -> Result<
     // Format: OP rd, imm(rs)  e.g. SB r3, 0(r1)
     let args = split_args(rest, 2)?;
     let rd = parse_reg(args[0])?;
+    let (base, imm) = parse_base_offset(args[1], equates)?;
 
-    let addr = args[1].trim();
+    Ok(match kind {
+        LsKind::Sb => Instruction::Sb { rd, base, imm },
+        LsKind::Sw => Instruction::Sw { rd, base, imm },
+        LsKind::Lw => Instruction::Lw { rd, base, imm },
+        LsKind::Lb => Instruction::Lb { rd, base, imm },
+    })
+}
+
+// imm(rs)
+fn parse_base_offset(addr: &str, equates: &HashMap<String, u32>) -> Result<(u8, Imm), AsmError> {
+    let addr = addr.trim();
     let (imm_str, reg_str) = if let Some(open) = addr.find('(') {
         let close = addr
             .find(')')
@@ -690,12 +748,7 @@ fn parse_ls(rest: &str, kind: LsKind, equates: &HashMap<String, u32>) -> Result<
         parse_imm_or_label(imm_str, equates)
     };
 
-    Ok(match kind {
-        LsKind::Sb => Instruction::Sb { rd, base, imm },
-        LsKind::Sw => Instruction::Sw { rd, base, imm },
-        LsKind::Lw => Instruction::Lw { rd, base, imm },
-        LsKind::Lb => Instruction::Lb { rd, base, imm },
-    })
+    Ok((base, imm))
 }
 
 // rd, rs
@@ -874,7 +927,7 @@ fn parse_imm(s: &str) -> Result<i16, AsmError> {
         let val = i32::from_str_radix(hex, 16)
             .map_err(|_| AsmError::InvalidImmediate(s.to_string()))?;
 
-        if val < 0 || val > 0xFFFF {
+        if !(0..=0xFFFF).contains(&val) {
             return Err(AsmError::InvalidImmediate(format!(
                 "Hex immediate out of 16-bit range: {}",
                 s
@@ -1066,6 +1119,27 @@ fn encode_instruction(
             Ok(enc_i(opcode::JALR, rd, rs, 0))
         }
 
+        // Special registers / exceptions
+        Instruction::Mfsr { rd, imm } => {
+            let v = resolve_imm(imm, labels, equates)?;
+            Ok(enc_i(opcode::MFSR, rd, 0, v))
+        }
+        Instruction::Mtsr { rs, imm } => {
+            // MTSR rs, sreg : the source register lives in the rd field, like SW
+            let v = resolve_imm(imm, labels, equates)?;
+            Ok(enc_i(opcode::MTSR, rs, 0, v))
+        }
+        Instruction::Eret => Ok(enc_i(opcode::ERET, 0, 0, 0)),
+        Instruction::Cache { op, base, imm } => {
+            // CACHE op, imm(rs) : the operation lives in the rd field
+            let op = resolve_imm(op, labels, equates)?;
+            if !(0..32).contains(&op) {
+                return Err(AsmError::InvalidImmediate(format!("Cache operation out of range: {}", op)));
+            }
+            let v = resolve_imm(imm, labels, equates)?;
+            Ok(enc_i(opcode::CACHE, op as u8, base, v))
+        }
+
         // System
         Instruction::Nop  => Ok(enc_i(opcode::NOP,  0, 0, 0)),
         Instruction::Halt => Ok(enc_i(opcode::HALT, 0, 0, 0)),
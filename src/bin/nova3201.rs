@@ -2,7 +2,12 @@ use std::env;
 use std::fs;
 use std::io::Read;
 use std::path::Path;
-use nova3201::bus::Bus;
+use nova3201::assembler::{SEG_NO_EXEC, SEG_READ_ONLY};
+use nova3201::bus::{Bus, ProtectedRange};
+use nova3201::cpu::branch::PredictorKind;
+use nova3201::cpu::pipeline::PipelineConfig;
+use nova3201::devices::rng::{RngSource, DEFAULT_SEED};
+use nova3201::devices::rtc::RtcClock;
 use nova3201::{Machine, NovaBus};
 use nova3201::BOOT_LOGO;
 
@@ -26,6 +31,7 @@ fn load_nv32<P: AsRef<Path>>(mach: &mut Machine, path: P) -> std::io::Result<()>
         f.read_exact(&mut sh)?;
 
         let kind = sh[0];
+        let flags = sh[1];
         let base_addr = u32::from_le_bytes(sh[4..8].try_into().unwrap());
         let size_words = u32::from_le_bytes(sh[8..12].try_into().unwrap());
 
@@ -36,13 +42,13 @@ fn load_nv32<P: AsRef<Path>>(mach: &mut Machine, path: P) -> std::io::Result<()>
                 let mut buf = vec![0u8; (size_words * 4) as usize];
                 f.read_exact(&mut buf)?;
 
-                for (i, chunk) in buf.chunks_exact(4).enumerate() {
-                    let w = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
-                    let addr = base_addr + (i as u32) * 4;
-                    mach.bus.write32(addr, w).map_err(|e| {
-                        std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to write to bus at 0x{:08X}: {}", addr, e))
-                    })?;
-                }
+                let words = buf
+                    .chunks_exact(4)
+                    .map(|chunk| u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+                    .collect::<Vec<_>>();
+                mach.try_load_program(base_addr, &words).map_err(|e| {
+                    std::io::Error::other(format!("Failed to load section at 0x{:08X}: {}", base_addr, e))
+                })?;
             }
             1 => {
                 println!("Zero-initializing section at 0x{:08X}, size {} words", base_addr, size_words);
@@ -50,7 +56,7 @@ fn load_nv32<P: AsRef<Path>>(mach: &mut Machine, path: P) -> std::io::Result<()>
                 for i in 0..size_words {
                     let addr = base_addr + i * 4;
                     mach.bus.write32(addr, 0).map_err(|e| {
-                        std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to write to bus at 0x{:08X}: {}", addr, e))
+                        std::io::Error::other(format!("Failed to write to bus at 0x{:08X}: {}", addr, e))
                     })?;
                 }
             }
@@ -58,28 +64,140 @@ fn load_nv32<P: AsRef<Path>>(mach: &mut Machine, path: P) -> std::io::Result<()>
                 return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Unknown section kind: {}", kind)));
             }
         }
+
+        // Protect the segment only after its contents have been written
+        if flags & (SEG_READ_ONLY | SEG_NO_EXEC) != 0 {
+            mach.bus.protect(ProtectedRange {
+                base: base_addr,
+                size: size_words * 4,
+                read_only: flags & SEG_READ_ONLY != 0,
+                no_exec: flags & SEG_NO_EXEC != 0,
+            });
+        }
     }
 
     Ok(())
 }
 
+const USAGE: &str = "Usage: nova3201 [--pipeline] [--predictor <kind>] [--resume <snapshot>] [--save-state <snapshot>] \
+                     [--seed <n> | --host-rng] [--host-clock] [--keys <file>] <program.nvb> [disk.img]";
+
+/// Removes `flag` and the value after it from the arguments. A flag without a value is a
+/// usage error.
+fn take_option(args: &mut Vec<String>, flag: &str) -> Option<String> {
+    let i = args.iter().position(|a| a == flag)?;
+    if args.get(i + 1).is_none_or(|value| value.starts_with("--")) {
+        eprintln!("{flag} needs a value\n{USAGE}");
+        std::process::exit(2);
+    }
+    args.remove(i);
+    Some(args.remove(i))
+}
+
 fn main() {
-    let path = env::args().nth(1).expect("Usage: nova3201 <program.nvb>");
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    // --pipeline times the program on a 5-stage pipeline and reports the hazards
+    let pipeline = args.iter().position(|a| a == "--pipeline").map(|i| args.remove(i)).is_some();
+    // --predictor <kind> models a branch predictor and reports its misprediction rates
+    let predictor = match take_option(&mut args, "--predictor").map(|kind| kind.parse::<PredictorKind>()) {
+        Some(Ok(kind)) => Some(kind),
+        Some(Err(e)) => {
+            eprintln!("{e}");
+            return;
+        }
+        None => None,
+    };
+    // --resume <snapshot> continues from a saved state, --save-state <snapshot> saves on exit
+    let resume = take_option(&mut args, "--resume");
+    let save_state = take_option(&mut args, "--save-state");
+    // --keys <file> types a key script instead of the host keyboard and runs headless: the
+    // terminal isn't touched and nothing waits for Enter
+    let keys = take_option(&mut args, "--keys");
+    // Runs are reproducible: the RNG is seeded (--seed <n>) unless --host-rng is given
+    let host_rng = args.iter().position(|a| a == "--host-rng").map(|i| args.remove(i)).is_some();
+    let seed = match take_option(&mut args, "--seed").map(|seed| seed.parse::<u64>()) {
+        Some(Ok(_)) if host_rng => {
+            eprintln!("--seed and --host-rng can't be used together");
+            return;
+        }
+        Some(Ok(seed)) => seed,
+        Some(Err(e)) => {
+            eprintln!("Invalid seed: {e}");
+            return;
+        }
+        None => DEFAULT_SEED,
+    };
+    // The RTC counts emulated cycles unless --host-clock is given
+    let host_clock = args.iter().position(|a| a == "--host-clock").map(|i| args.remove(i)).is_some();
+    let path = args.first().cloned().expect(USAGE);
 
     let mut mach = Machine::new();
+    mach.set_rng_source(if host_rng { RngSource::Host } else { RngSource::Seeded(seed) });
+    if host_clock {
+        mach.set_rtc_clock(RtcClock::Host);
+    }
+    // History isn't enabled yet, so the timing models can be set up
+    if pipeline {
+        mach.set_pipeline(Some(PipelineConfig::default())).expect("no history");
+    }
+    mach.set_branch_predictor(predictor).expect("no history");
 
-    let mut input = String::new();
-    std::io::stdin().read_line(&mut input).unwrap();
+    if let Some(disk) = args.get(1)
+        && let Err(e) = mach.attach_disk(disk)
+    {
+        eprintln!("Failed to attach disk image '{disk}': {e}");
+        return;
+    }
 
-    emulate(&mut mach, path);
+    if let Some(keys) = &keys {
+        if let Err(e) = mach.load_key_script(keys) {
+            eprintln!("Failed to load the key script '{keys}': {e}");
+            return;
+        }
+    } else {
+        let mut input = String::new();
+        std::io::stdin().read_line(&mut input).unwrap();
 
-    println!("Simulation ended. Press Enter to exit.");
-    let mut input = String::new();
-    std::io::stdin().read_line(&mut input).unwrap();
+        // Keys typed in this terminal go to the emulated keyboard
+        if let Err(e) = mach.attach_host_keyboard() {
+            eprintln!("Failed to attach the host keyboard: {e}");
+        }
+    }
+    emulate(&mut mach, path, resume.as_deref());
+    mach.detach_host_keyboard();
+
+    if let Some(snapshot) = &save_state {
+        match mach.save_state(snapshot) {
+            Ok(()) => println!("Saved state to '{snapshot}'"),
+            Err(e) => eprintln!("Failed to save state to '{snapshot}': {e}"),
+        }
+    }
+
+    for cpu in &mach.cpus {
+        if let Some(pipeline) = &cpu.pipeline {
+            print!("{}", pipeline.report());
+        }
+        if let Some(predictor) = &cpu.branch_predictor {
+            print!("{}", predictor.report());
+        }
+    }
+
+    // A program that powers off through the system control block decides the exit code. The
+    // host only keeps the low 8 bits, so a failure status like 256 exits with 1 instead of 0.
+    if let Some(status) = mach.exit_status() {
+        let code = if status != 0 && status & 0xFF == 0 { 1 } else { status & 0xFF };
+        std::process::exit(code as i32);
+    }
+
+    if keys.is_none() {
+        println!("Simulation ended. Press Enter to exit.");
+        let mut input = String::new();
+        std::io::stdin().read_line(&mut input).unwrap();
+    }
 }
 
 
-pub fn emulate(mach: &mut Machine, path: String) {
+pub fn emulate(mach: &mut Machine, path: String, resume: Option<&str>) {
     // Start by printing the boot logo
     uart_println(&mut mach.bus, BOOT_LOGO);
 
@@ -91,12 +209,26 @@ pub fn emulate(mach: &mut Machine, path: String) {
     }
     uart_println(&mut mach.bus, "[OK]\n");
 
+    // The snapshot overwrites the freshly loaded program with the saved memory
+    if let Some(snapshot) = resume {
+        uart_println(&mut mach.bus, "  - Resuming from snapshot: ");
+        if let Err(e) = mach.load_state(snapshot) {
+            uart_println(&mut mach.bus, &format!("  [ERR]: {e}\n"));
+            return;
+        }
+        uart_println(&mut mach.bus, "[OK]\n");
+    }
+
     uart_println(&mut mach.bus, "  - Starting simulation\n\n\n");
     // Run for some cycles
     for _ in 0..10_000 {
         // mach.inspect();
         mach.step();
-        if mach.cpu.halted {
+        if let Some(status) = mach.exit_status() {
+            uart_println(&mut mach.bus, &format!("\n\n\nPowered off, exit status {status}"));
+            return;
+        }
+        if mach.halted() {
             uart_println(&mut mach.bus, "\n\n\nCPU halted");
             return;
         }
@@ -106,10 +238,14 @@ pub fn emulate(mach: &mut Machine, path: String) {
 }
 
 fn uart_println(bus: &mut NovaBus, s: &str) {
+    let Some(uart) = bus.console() else {
+        return;
+    };
+
     for c in s.chars() {
         if c == '\n' {
-            bus.uart.write_tx(b'\r');
+            uart.write_tx(b'\r');
         }
-        bus.uart.write_tx(c as u8);
+        uart.write_tx(c as u8);
     }
 }
@@ -53,11 +53,11 @@ fn run(input: &Path, output: &Path) -> Result<(), String> {
     let segments = assemble_nv32(&src).map_err(display_asm_error)?;
 
     // -------- write NV32 file --------
-    if let Some(parent) = output.parent() {
-        if !parent.as_os_str().is_empty() {
-            fs::create_dir_all(parent)
-                .map_err(|e| format!("Failed to create directory '{}': {e}", parent.display()))?;
-        }
+    if let Some(parent) = output.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create directory '{}': {e}", parent.display()))?;
     }
 
     let mut f = fs::File::create(output)
@@ -100,7 +100,7 @@ fn write_nv32<W: Write>(w: &mut W, segments: &[NvSegment]) -> std::io::Result<()
             SegmentKind::Bss      => 1u8,
         };
 
-        let flags: u8 = 0;
+        let flags: u8 = seg.flags;
         let reserved: u16 = 0;
         let reserved2: u32 = 0;
 
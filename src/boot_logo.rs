@@ -1,4 +1,4 @@
-pub const BOOT_LOGO: &'static str = "
+pub const BOOT_LOGO: &str = "
 ************************************************************
 
         NOVA3201 System BIOS v0.1
@@ -1,9 +1,27 @@
+use crate::cpu::mmu::Access;
+use crate::devices::audio::Audio;
+use crate::devices::block::{self, BlockDevice, BlockRequest};
+use crate::devices::display::TextDisplay;
+use crate::devices::dma::{self, DmaController};
 use crate::devices::font::FontRam;
+use crate::devices::framebuffer::Framebuffer;
+use crate::devices::gpio::Gpio;
+use crate::devices::i2c::I2cController;
+use crate::devices::keyboard::Keyboard;
+use crate::devices::net::{self, Nic};
 use crate::devices::ram::Ram;
+use crate::devices::rng::Rng;
+use crate::devices::rtc::Rtc;
+use crate::devices::smp::{self, Smp};
+use crate::devices::spi::SpiController;
+use crate::devices::syscon::{self, SystemControl};
 use crate::devices::timer::Timer;
 use crate::devices::uart::pty_backend::PtyBackend;
-use crate::devices::uart::Uart;
+use crate::devices::uart::{Uart, UartBackend};
 use crate::devices::vram::Vram;
+use crate::devices::watchdog::Watchdog;
+use crate::machine::history::{Input, InputLog};
+use std::collections::BTreeMap;
 
 /// Errors that can occur during bus operations
 #[derive(Debug)]
@@ -11,6 +29,7 @@ pub enum BusError {
     Misaligned(u32),
     OutOfBounds(u32),
     DeviceFault(u32),
+    WriteProtected(u32),
 }
 
 impl std::fmt::Display for BusError {
@@ -19,6 +38,7 @@ impl std::fmt::Display for BusError {
             BusError::Misaligned(addr) => write!(f, "Misaligned access at address 0x{:08X}", addr),
             BusError::OutOfBounds(addr) => write!(f, "Out of bounds access at address 0x{:08X}", addr),
             BusError::DeviceFault(addr) => write!(f, "Device fault at address 0x{:08X}", addr),
+            BusError::WriteProtected(addr) => write!(f, "Write to protected memory at address 0x{:08X}", addr),
         }
     }
 }
@@ -31,6 +51,62 @@ pub trait Bus {
     fn read32(&mut self, addr: u32) -> Result<u32, Self::Error>;
     fn write8(&mut self, addr: u32, value: u8) -> Result<(), Self::Error>;
     fn write32(&mut self, addr: u32, value: u32) -> Result<(), Self::Error>;
+
+    /// Returns false when the memory attributes at `addr` forbid the access. The CPU checks
+    /// this before every access so it can raise an exception instead of a bus error.
+    fn permits(&self, _addr: u32, _access: Access) -> bool {
+        true
+    }
+
+    /// Returns false for addresses the CPU caches must not hold, e.g. device registers
+    fn cacheable(&self, _addr: u32) -> bool {
+        true
+    }
+}
+
+/// A memory range with restricted access, e.g. a code segment set up by the program loader
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProtectedRange {
+    pub base: u32,
+    /// Size in bytes
+    pub size: u32,
+    /// Stores into the range are rejected
+    pub read_only: bool,
+    /// Instruction fetches from the range are rejected
+    pub no_exec: bool,
+}
+
+impl ProtectedRange {
+    fn contains(&self, addr: u32) -> bool {
+        addr >= self.base && ((addr - self.base) as u64) < self.size as u64
+    }
+}
+
+/// A UART mapped onto the bus at its own base address
+pub struct UartPort {
+    /// Base address of the register block (TX at +0x0, STATUS at +0x4)
+    pub base: u32,
+    /// IRQ line this UART raises (0 = isa::cause::UART_IRQ)
+    pub irq_line: u32,
+    /// The UART itself
+    pub uart: Uart<Box<dyn UartBackend>>,
+}
+
+impl UartPort {
+    pub fn new<B: UartBackend + 'static>(base: u32, irq_line: u32, backend: B) -> Self {
+        assert!(base & 3 == 0, "UART base address must be word aligned");
+        assert!(irq_line < UART_IRQ_LINES, "UART IRQ line out of range");
+
+        Self {
+            base,
+            irq_line,
+            uart: Uart::new(Box::new(backend)),
+        }
+    }
+
+    fn contains(&self, addr: u32) -> bool {
+        addr >= self.base && addr - self.base < UART_BLOCK_SIZE
+    }
 }
 
 // Concrete implementation of the NovaBus
@@ -38,11 +114,35 @@ pub struct NovaBus {
     pub ram: Ram,          // General RAM
     pub vram: Vram,        // Video RAM
     pub font_ram: FontRam, // Character Font RAM
+    pub framebuffer: Framebuffer, // Pixel framebuffer
     pub timer1: Timer,     // Timer1
     pub timer2: Timer,     // Timer2 , just because
-    pub uart: Uart<PtyBackend>,        // Uart
+    pub rtc: Rtc,          // Real-time clock
+    pub gpio: Gpio,        // 32 pin GPIO block
+    pub block: BlockDevice, // Block storage
+    pub dma: DmaController, // DMA controller
+    pub rng: Rng,          // Hardware random number generator
+    pub watchdog: Watchdog, // Watchdog timer
+    pub syscon: SystemControl, // System control block
+    pub spi: SpiController, // SPI master
+    pub i2c: I2cController, // I2C master
+    pub keyboard: Keyboard, // PS/2 style keyboard
+    pub audio: Audio,      // Audio output
+    pub net: Nic,          // Network interface
+    pub smp: Smp,          // Inter-processor interrupts and spinlocks
+    pub display: TextDisplay, // Text mode display (renders VRAM and FONT RAM)
+    pub uarts: Vec<UartPort>, // Uarts, the first one is the console
+    pub(crate) protected: Vec<ProtectedRange>, // Read-only / no-execute ranges
+    pub(crate) reset_reason: u32, // Why the machine was last reset
+    pub(crate) inputs: Option<InputLog>, // Host inputs, recorded for replay when history is on
+    pub(crate) mmio_written: BTreeMap<u32, u32>, // Last word written to each MMIO register, for byte stores
 }
 
+// Reset reasons
+pub const RESET_POWER_ON: u32 = 0; // Cold start
+pub const RESET_WATCHDOG: u32 = 1; // The watchdog expired
+pub const RESET_SOFT: u32 = 2; // The program requested a reset through the system control block
+
 const RAM_BASE: u32 = 0x0000_0000;
 const RAM_SIZE: u32 = 1024 * 1024;
 const RAM_END: u32 = RAM_BASE + RAM_SIZE - 1;
@@ -57,6 +157,9 @@ const FONT_SIZE: u32 = 0x0000_1000;
 #[allow(unused)]
 const FONT_END: u32 = FONT_BASE + FONT_SIZE - 1;
 
+const FB_BASE: u32 = 0x8010_0000;
+const FB_SIZE: u32 = 0x0010_0000;
+
 const MMIO_BASE: u32 = 0x8000_2100;
 const MMIO_END: u32 = 0x8000_22FF;
 
@@ -66,15 +169,200 @@ const TIMER1_PERIOD: u32 = 0x8000_2104; // R/W
 const TIMER1_COUNT: u32 = 0x8000_2108; // R
 const TIMER1_RESET: u32 = 0x8000_210C; // W
 const TIMER1_ACK: u32 = 0x8000_2110; // W
+const TIMER1_PRESCALER: u32 = 0x8000_2114; // R/W
+const TIMER1_STATUS: u32 = 0x8000_2118; // R/W1C
+const TIMER1_COMPARE: u32 = 0x8000_211C; // R/W
 
 const TIMER2_CTRL: u32 = 0x8000_2120; // R/W
 const TIMER2_PERIOD: u32 = 0x8000_2124; // R/W
 const TIMER2_COUNT: u32 = 0x8000_2128; // R
 const TIMER2_RESET: u32 = 0x8000_212C; // W
 const TIMER2_ACK: u32 = 0x8000_2130; // W
+const TIMER2_PRESCALER: u32 = 0x8000_2134; // R/W
+const TIMER2_STATUS: u32 = 0x8000_2138; // R/W1C
+const TIMER2_COMPARE: u32 = 0x8000_213C; // R/W
+
+const RTC_SECONDS: u32 = 0x8000_2140; // R/W  - Reading latches RTC_SUBSEC
+const RTC_SUBSEC: u32 = 0x8000_2144; // R    - Microseconds, latched on RTC_SECONDS read
+const RTC_ALARM: u32 = 0x8000_2148; // R/W
+const RTC_CTRL: u32 = 0x8000_214C; // R/W
+const RTC_STATUS: u32 = 0x8000_2150; // R/W1C
+
+const DISPLAY_MODE: u32 = 0x8000_2160; // R/W
+const DISPLAY_CURSOR: u32 = 0x8000_2164; // R/W  - x in bits 0-7, y in bits 8-15
+const DISPLAY_CURSOR_CTRL: u32 = 0x8000_2168; // R/W
+const DISPLAY_BLANK: u32 = 0x8000_216C; // R/W  - Palette index shown when the display is off
+const DISPLAY_PAL_INDEX: u32 = 0x8000_2170; // R/W
+const DISPLAY_PAL_DATA: u32 = 0x8000_2174; // R/W  - 0x00RRGGBB of the selected palette entry
+const DISPLAY_INFO: u32 = 0x8000_2178; // R    - columns in bits 0-7, rows in bits 8-15
+
+const FB_CTRL: u32 = 0x8000_2180; // R/W
+const FB_WIDTH: u32 = 0x8000_2184; // R/W
+const FB_HEIGHT: u32 = 0x8000_2188; // R/W
+const FB_FORMAT: u32 = 0x8000_218C; // R/W
+const FB_VSYNC_PERIOD: u32 = 0x8000_2190; // R/W  - Cycles between vsyncs, 0 = off
+const FB_STATUS: u32 = 0x8000_2194; // R/W1C
+const FB_PAL_INDEX: u32 = 0x8000_2198; // R/W
+const FB_PAL_DATA: u32 = 0x8000_219C; // R/W  - 0x00RRGGBB of the selected palette entry
+const FB_FRAME: u32 = 0x8000_21A0; // R    - Number of vsyncs so far
+
+const BLK_CTRL: u32 = 0x8000_21C0; // R/W
+const BLK_STATUS: u32 = 0x8000_21C4; // R/W1C
+const BLK_CMD: u32 = 0x8000_21C8; // W
+const BLK_LBA: u32 = 0x8000_21CC; // R/W
+const BLK_COUNT: u32 = 0x8000_21D0; // R/W  - Number of sectors
+const BLK_BUF_ADDR: u32 = 0x8000_21D4; // R/W
+const BLK_SECTOR_SIZE: u32 = 0x8000_21D8; // R
+const BLK_CAPACITY: u32 = 0x8000_21DC; // R    - Number of sectors on the disk
+const BLK_ERROR: u32 = 0x8000_21E0; // R
 
-const UART_TX: u32 = 0x8000_2200; // W    - Only low 8 bits used
-const UART_STATUS: u32 = 0x8000_2204; // R/W
+const WDT_CTRL: u32 = 0x8000_21A4; // R/W
+const WDT_TIMEOUT: u32 = 0x8000_21A8; // R/W  - Cycles, writing restarts the countdown
+const WDT_KICK: u32 = 0x8000_21AC; // W    - Any write restarts the countdown
+const WDT_COUNT: u32 = 0x8000_21B0; // R    - Cycles left until expiry
+const WDT_STATUS: u32 = 0x8000_21B4; // R/W1C
+const WDT_RESET_REASON: u32 = 0x8000_21B8; // R    - Why the machine was last reset
+
+const RNG_DATA: u32 = 0x8000_21E4; // R    - Reading consumes the word
+const RNG_STATUS: u32 = 0x8000_21E8; // R
+const RNG_SEED: u32 = 0x8000_21EC; // W    - Restarts the sequence from the written seed
+
+// DMA channel register blocks, one every DMA_CHANNEL_STRIDE bytes
+const DMA_BASE: u32 = 0x8000_2280;
+const DMA_CHANNEL_STRIDE: u32 = 0x20;
+
+// DMA channel registers, relative to the channel block
+const DMA_SRC: u32 = 0x00; // R/W
+const DMA_DST: u32 = 0x04; // R/W
+const DMA_LEN: u32 = 0x08; // R/W  - Number of units (bytes or words)
+const DMA_CTRL: u32 = 0x0C; // R/W  - Setting START kicks off the transfer
+const DMA_STATUS: u32 = 0x10; // R/W1C
+const DMA_REMAINING: u32 = 0x14; // R
+
+// GPIO lives in its own window, as documented in docs/abi.md
+const GPIO_BASE: u32 = 0x2000_0300;
+const GPIO_SIZE: u32 = 0x0000_0100;
+
+// GPIO registers, relative to GPIO_BASE
+const GPIO_DIR: u32 = 0x00; // R/W  - 0 = input, 1 = output
+const GPIO_OUT: u32 = 0x04; // R/W
+const GPIO_IN: u32 = 0x08; // R    - Pin levels
+const GPIO_IRQ_ENABLE: u32 = 0x0C; // R/W
+const GPIO_IRQ_TYPE: u32 = 0x10; // R/W  - 0 = level, 1 = edge
+const GPIO_IRQ_POLARITY: u32 = 0x14; // R/W  - 0 = low/falling, 1 = high/rising
+const GPIO_IRQ_ANY_EDGE: u32 = 0x18; // R/W  - 1 = edge IRQ on both edges
+const GPIO_IRQ_STATUS: u32 = 0x1C; // R/W1C
+const GPIO_OUT_SET: u32 = 0x20; // W
+const GPIO_OUT_CLR: u32 = 0x24; // W
+const GPIO_OUT_TOGGLE: u32 = 0x28; // W
+
+// System control block, right after the DMA channels
+const SYS_BASE: u32 = 0x8000_2300;
+const SYS_SIZE: u32 = 0x0000_0020;
+
+// System control registers, relative to SYS_BASE
+const SYS_BOARD_ID: u32 = 0x00; // R
+const SYS_RAM_SIZE: u32 = 0x04; // R    - Bytes
+const SYS_RESET_REASON: u32 = 0x08; // R    - Why the machine was last reset
+const SYS_RESET: u32 = 0x0C; // W    - Soft reset, bit 0 = preserve RAM
+const SYS_POWEROFF: u32 = 0x10; // W    - Power off, the value is the exit status
+
+// SPI master
+const SPI_BASE: u32 = 0x8000_2320;
+const SPI_SIZE: u32 = 0x0000_0020;
+
+// SPI registers, relative to SPI_BASE
+const SPI_CTRL: u32 = 0x00; // R/W
+const SPI_CS: u32 = 0x04; // R/W  - Selected chip, 0xFF = none
+const SPI_DATA: u32 = 0x08; // R/W  - Write sends a byte, read returns the received byte
+const SPI_STATUS: u32 = 0x0C; // R
+
+// I2C master
+const I2C_BASE: u32 = 0x8000_2340;
+const I2C_SIZE: u32 = 0x0000_0020;
+
+// I2C registers, relative to I2C_BASE
+const I2C_CTRL: u32 = 0x00; // R/W
+const I2C_DATA: u32 = 0x04; // R/W  - Address or data byte to send, received byte
+const I2C_CMD: u32 = 0x08; // W
+const I2C_STATUS: u32 = 0x0C; // R
+
+// Keyboard controller
+const KBD_BASE: u32 = 0x8000_2360;
+const KBD_SIZE: u32 = 0x0000_0010;
+
+// Keyboard registers, relative to KBD_BASE
+const KBD_DATA: u32 = 0x00; // R    - Pops the next scan code
+const KBD_STATUS: u32 = 0x04; // R/W1C
+const KBD_CTRL: u32 = 0x08; // R/W
+
+// Audio output
+const AUDIO_BASE: u32 = 0x8000_2370;
+const AUDIO_SIZE: u32 = 0x0000_0020;
+
+// Audio registers, relative to AUDIO_BASE
+const AUDIO_CTRL: u32 = 0x00; // R/W
+const AUDIO_RATE: u32 = 0x04; // R/W  - Sample rate in Hz
+const AUDIO_FORMAT: u32 = 0x08; // R/W
+const AUDIO_DATA: u32 = 0x0C; // W    - Queues a sample, DMA it here with a fixed destination
+const AUDIO_STATUS: u32 = 0x10; // R/W1C
+const AUDIO_LEVEL: u32 = 0x14; // R    - Frames waiting in the FIFO
+
+// Network interface
+const NET_BASE: u32 = 0x8000_2390;
+const NET_SIZE: u32 = 0x0000_0040;
+
+// Network registers, relative to NET_BASE
+const NET_CTRL: u32 = 0x00; // R/W
+const NET_STATUS: u32 = 0x04; // R/W1C
+const NET_TX_RING: u32 = 0x08; // R/W  - Address of the TX descriptor ring
+const NET_TX_COUNT: u32 = 0x0C; // R/W  - Number of TX descriptors
+const NET_TX_INDEX: u32 = 0x10; // R    - TX descriptor the NIC looks at next
+const NET_TX_POLL: u32 = 0x14; // W    - Any write sends the frames the NIC owns
+const NET_RX_RING: u32 = 0x18; // R/W  - Address of the RX descriptor ring
+const NET_RX_COUNT: u32 = 0x1C; // R/W  - Number of RX descriptors
+const NET_RX_INDEX: u32 = 0x20; // R    - RX descriptor the NIC fills next
+const NET_RX_DROPPED: u32 = 0x24; // R    - Frames dropped for lack of RX descriptors
+const NET_MAC_LO: u32 = 0x28; // R    - Station address bytes 0-3
+const NET_MAC_HI: u32 = 0x2C; // R    - Station address bytes 4-5
+
+// Multi-core support
+const SMP_BASE: u32 = 0x8000_23D0;
+const SMP_SIZE: u32 = 0x0000_0040;
+
+// Multi-core registers, relative to SMP_BASE
+const SMP_HART_COUNT: u32 = 0x00; // R    - Number of cores
+const SMP_IPI_PENDING: u32 = 0x04; // R    - Pending IPIs, bit n = hart n
+const SMP_IPI_SET: u32 = 0x08; // W    - Raises an IPI on the harts whose bits are set
+const SMP_IPI_CLEAR: u32 = 0x0C; // W    - Acknowledges the IPIs whose bits are set
+const SMP_LOCK_STATE: u32 = 0x10; // R    - Held spinlocks, bit n = lock n
+const SMP_LOCK_BASE: u32 = 0x20; // R/W  - Spinlock n at SMP_LOCK_BASE + 4 * n, read takes, write releases
+
+// Memory and device windows at fixed addresses, UARTs must stay clear of them
+const FIXED_WINDOWS: [(&str, u32, u32); 14] = [
+    ("RAM", RAM_BASE, RAM_SIZE),
+    ("VRAM", VRAM_BASE, VRAM_SIZE),
+    ("font RAM", FONT_BASE, FONT_SIZE),
+    ("framebuffer", FB_BASE, FB_SIZE),
+    ("device registers", MMIO_BASE, RNG_SEED + 4 - MMIO_BASE),
+    ("DMA channels", DMA_BASE, DMA_CHANNEL_STRIDE * dma::CHANNEL_COUNT as u32),
+    ("GPIO block", GPIO_BASE, GPIO_SIZE),
+    ("system control block", SYS_BASE, SYS_SIZE),
+    ("SPI master", SPI_BASE, SPI_SIZE),
+    ("I2C master", I2C_BASE, I2C_SIZE),
+    ("keyboard controller", KBD_BASE, KBD_SIZE),
+    ("audio output", AUDIO_BASE, AUDIO_SIZE),
+    ("network interface", NET_BASE, NET_SIZE),
+    ("SMP block", SMP_BASE, SMP_SIZE),
+];
+
+// UART register block, relative to the base of each UART
+pub const UART0_BASE: u32 = 0x8000_2200; // Default console UART
+pub const UART_BLOCK_SIZE: u32 = 0x10;
+pub const UART_IRQ_LINES: u32 = 8; // Number of UART IRQ lines the CPU knows about
+
+const UART_TX: u32 = 0x0; // W    - Only low 8 bits used
+const UART_STATUS: u32 = 0x4; // R/W
 
 impl Default for NovaBus {
     fn default() -> Self {
@@ -83,27 +371,778 @@ impl Default for NovaBus {
 }
 
 impl NovaBus {
+    /// Creates a bus with a single PTY backed console UART at `UART0_BASE`
     pub fn new() -> Self {
+        Self::with_uarts(vec![Self::console_uart()])
+    }
+
+    /// The default console: a PTY backed UART at `UART0_BASE` on IRQ line 0
+    pub fn console_uart() -> UartPort {
         let (backend, slave_path) = PtyBackend::new().expect("Failed to create PTY backend for UART");
 
         println!("UART slave device created at: {}", slave_path);
         println!("You can connect to it using a terminal emulator (e.g., minicom, screen).");
         println!("Waiting for connection...");
 
+        UartPort::new(UART0_BASE, 0, backend)
+    }
+
+    /// Creates a bus with the given UARTs. The first UART is used as the console.
+    ///
+    /// Panics when two UART register blocks overlap, or a UART overlaps memory or another
+    /// device.
+    pub fn with_uarts(uarts: Vec<UartPort>) -> Self {
+        for port in &uarts {
+            let end = port.base as u64 + UART_BLOCK_SIZE as u64;
+            if let Some((name, ..)) = FIXED_WINDOWS
+                .iter()
+                .find(|&&(_, base, size)| (port.base as u64) < base as u64 + size as u64 && (base as u64) < end)
+            {
+                panic!("UART at 0x{:08X} overlaps the {name}", port.base);
+            }
+        }
+        for (i, a) in uarts.iter().enumerate() {
+            for b in &uarts[i + 1..] {
+                assert!(
+                    !a.contains(b.base) && !b.contains(a.base),
+                    "UARTs at 0x{:08X} and 0x{:08X} overlap",
+                    a.base,
+                    b.base
+                );
+            }
+        }
+
+        let mut font_ram = FontRam::new(FONT_SIZE as usize);
+        font_ram.load_builtin_font();
+
         Self {
             ram: Ram::new(RAM_SIZE as usize),
             vram: Vram::new(VRAM_SIZE as usize),
-            font_ram: FontRam::new(FONT_SIZE as usize),
+            font_ram,
+            framebuffer: Framebuffer::new(FB_SIZE as usize),
             timer1: Timer::new(),
             timer2: Timer::new(),
-            uart: Uart::new(backend),
+            rtc: Rtc::default(),
+            gpio: Gpio::new(),
+            block: BlockDevice::new(),
+            dma: DmaController::new(),
+            rng: Rng::default(),
+            watchdog: Watchdog::new(),
+            syscon: SystemControl::new(),
+            spi: SpiController::new(),
+            i2c: I2cController::new(),
+            keyboard: Keyboard::new(),
+            audio: Audio::new(),
+            net: Nic::new(),
+            smp: Smp::default(),
+            display: TextDisplay::new(),
+            uarts,
+            protected: Vec::new(),
+            reset_reason: RESET_POWER_ON,
+            inputs: None,
+            mmio_written: BTreeMap::new(),
         }
     }
 
+    /// Puts all devices back into their power-on state. UART backends, the attached disk,
+    /// SPI and I2C slaves, the network backend, protected ranges, the (battery backed) RTC
+    /// and the RNG source survive the reset. RAM is cleared unless `preserve_ram` is set.
+    pub fn reset(&mut self, reason: u32, preserve_ram: bool) {
+        println!("[bus] reset reason={} preserve_ram={}", reason, preserve_ram);
+
+        if !preserve_ram {
+            self.ram = Ram::new(RAM_SIZE as usize);
+        }
+        self.vram = Vram::new(VRAM_SIZE as usize);
+        self.font_ram = FontRam::new(FONT_SIZE as usize);
+        self.font_ram.load_builtin_font();
+        self.framebuffer = Framebuffer::new(FB_SIZE as usize);
+        self.timer1 = Timer::new();
+        self.timer2 = Timer::new();
+        self.gpio.reset();
+        self.block.reset();
+        self.dma.reset();
+        self.watchdog = Watchdog::new();
+        self.syscon = SystemControl::new();
+        self.spi.reset();
+        self.i2c.reset();
+        self.keyboard = Keyboard::new();
+        self.audio.reset();
+        self.net.reset();
+        self.smp.reset();
+        self.display = TextDisplay::new();
+        for port in self.uarts.iter_mut() {
+            port.uart.reset();
+        }
+        self.mmio_written.clear();
+
+        self.reset_reason = reason;
+    }
+
+    /// Why the machine was last reset, see the RESET_* constants
+    pub fn reset_reason(&self) -> u32 {
+        self.reset_reason
+    }
+
+    /// Restricts access to a memory range. Stores into read-only ranges fail with
+    /// `BusError::WriteProtected`, for every bus master.
+    pub fn protect(&mut self, range: ProtectedRange) {
+        println!(
+            "[bus] protect 0x{:08X}..0x{:08X} read_only={} no_exec={}",
+            range.base,
+            range.base as u64 + range.size as u64,
+            range.read_only,
+            range.no_exec
+        );
+        self.protected.push(range);
+    }
+
+    /// Removes all protected ranges
+    pub fn clear_protection(&mut self) {
+        self.protected.clear();
+    }
+
+    pub fn protected_ranges(&self) -> &[ProtectedRange] {
+        &self.protected
+    }
+
+    /// Returns the console UART (the first configured UART), if any
+    pub fn console(&mut self) -> Option<&mut Uart<Box<dyn UartBackend>>> {
+        self.uarts.first_mut().map(|port| &mut port.uart)
+    }
+
     fn in_range(addr: u32, base: u32, size: u32) -> bool {
         addr >= base && addr < base + size
     }
 
+    fn is_mmio(&self, addr: u32) -> bool {
+        (MMIO_BASE..=MMIO_END).contains(&addr)
+            || Self::in_range(addr, GPIO_BASE, GPIO_SIZE)
+            || Self::in_range(addr, SYS_BASE, SYS_SIZE)
+            || Self::in_range(addr, SPI_BASE, SPI_SIZE)
+            || Self::in_range(addr, I2C_BASE, I2C_SIZE)
+            || Self::in_range(addr, KBD_BASE, KBD_SIZE)
+            || Self::in_range(addr, AUDIO_BASE, AUDIO_SIZE)
+            || Self::in_range(addr, NET_BASE, NET_SIZE)
+            || Self::in_range(addr, SMP_BASE, SMP_SIZE)
+            || self.uart_port(addr).is_some()
+    }
+
+    fn uart_port(&self, addr: u32) -> Option<usize> {
+        self.uarts.iter().position(|port| port.contains(addr))
+    }
+
+    // --- Block device helpers ------------------------------------------------
+
+    /// Carries out a block device transfer, moving the sectors directly between the disk
+    /// image and the bus.
+    pub fn block_transfer(&mut self, req: BlockRequest) {
+        let result = match req.cmd {
+            block::CMD_READ => self.block_read(req),
+            block::CMD_WRITE => self.block_write(req),
+            block::CMD_FLUSH => self.block_flush(),
+            _ => Err(block::ERR_BAD_COMMAND),
+        };
+
+        self.block.finish(result);
+    }
+
+    fn block_read(&mut self, req: BlockRequest) -> Result<(), u32> {
+        let data = self.block_read_sectors(req.lba, req.count)?;
+
+        for (i, &byte) in data.iter().enumerate() {
+            let addr = req.buf_addr.wrapping_add(i as u32);
+            self.write8(addr, byte).map_err(|_| block::ERR_BUS)?;
+        }
+
+        Ok(())
+    }
+
+    fn block_write(&mut self, req: BlockRequest) -> Result<(), u32> {
+        let len = req.count.saturating_mul(block::SECTOR_SIZE);
+        let mut data = Vec::with_capacity(len as usize);
+
+        for i in 0..len {
+            let addr = req.buf_addr.wrapping_add(i);
+            data.push(self.read8(addr).map_err(|_| block::ERR_BUS)?);
+        }
+
+        self.block_write_sectors(req.lba, &data)
+    }
+
+    /// Reads sectors from the disk image. The image is a host input: a replayed cycle gets
+    /// the data it read the first time, even when later cycles wrote over it.
+    fn block_read_sectors(&mut self, lba: u32, count: u32) -> Result<Vec<u8>, u32> {
+        match self.inputs.as_mut() {
+            None => self.block.read_sectors(lba, count),
+            Some(log) if log.live() => {
+                let data = self.block.read_sectors(lba, count);
+                log.record(Input::BlockRead { data: data.clone() });
+                data
+            }
+            Some(log) => {
+                let logged = log.replay(|input| match input {
+                    Input::BlockRead { data } => Some(data.clone()),
+                    _ => None,
+                });
+                logged.unwrap_or(Err(block::ERR_IO))
+            }
+        }
+    }
+
+    /// Writes sectors to the disk image. Replayed cycles don't touch the image again and get
+    /// the outcome of the first run.
+    fn block_write_sectors(&mut self, lba: u32, data: &[u8]) -> Result<(), u32> {
+        self.block_host_io(|block| block.write_sectors(lba, data))
+    }
+
+    /// Flushes the disk image, unless the cycle is replayed
+    fn block_flush(&mut self) -> Result<(), u32> {
+        self.block_host_io(|block| block.flush())
+    }
+
+    fn block_host_io(&mut self, io: impl FnOnce(&mut BlockDevice) -> Result<(), u32>) -> Result<(), u32> {
+        match self.inputs.as_mut() {
+            None => io(&mut self.block),
+            Some(log) if log.live() => {
+                let result = io(&mut self.block);
+                log.record(Input::BlockDone { result });
+                result
+            }
+            Some(log) => {
+                let logged = log.replay(|input| match *input {
+                    Input::BlockDone { result } => Some(result),
+                    _ => None,
+                });
+                logged.unwrap_or(Err(block::ERR_IO))
+            }
+        }
+    }
+
+    // --- Network helpers -----------------------------------------------------
+
+    /// Sends the frames of the TX ring after a TX poll, and writes a received frame into the
+    /// RX ring. Received frames are host inputs; replayed cycles don't send frames again.
+    pub fn net_tick(&mut self) {
+        if self.net.take_tx_poll() {
+            self.net_transmit();
+        }
+
+        let frame = match self.inputs.as_mut() {
+            None => self.net.recv(),
+            Some(log) if log.live() => {
+                let frame = self.net.recv();
+                if let Some(frame) = &frame {
+                    log.record(Input::NetRx { frame: frame.clone() });
+                }
+                frame
+            }
+            Some(log) => log.replay(|input| match input {
+                Input::NetRx { frame } => Some(frame.clone()),
+                _ => None,
+            }),
+        };
+        if let Some(frame) = frame {
+            self.net_receive(&frame);
+        }
+    }
+
+    fn net_transmit(&mut self) {
+        // At most one pass over the ring, even when the NIC owns every descriptor
+        for _ in 0..self.net.tx.count {
+            let Some(desc) = self.net.tx.desc_addr() else {
+                return;
+            };
+            match self.net_send_desc(desc) {
+                Ok(true) => {
+                    self.net.tx.advance();
+                    self.net.raise_status(net::STATUS_TX_DONE);
+                }
+                Ok(false) => return,
+                Err(_) => {
+                    self.net.raise_status(net::STATUS_ERROR);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Sends the frame of a TX descriptor. Returns false when the NIC doesn't own it.
+    fn net_send_desc(&mut self, desc: u32) -> Result<bool, BusError> {
+        let flags = self.read32(desc.wrapping_add(4))?;
+        if flags & net::DESC_OWN == 0 {
+            return Ok(false);
+        }
+
+        let len = flags & net::DESC_LEN_MASK;
+        let mut done = flags & !net::DESC_OWN;
+        if len as usize > net::MAX_FRAME_SIZE {
+            done |= net::DESC_ERROR;
+        } else {
+            let buf = self.read32(desc)?;
+            let mut frame = Vec::with_capacity(len as usize);
+            for i in 0..len {
+                frame.push(self.read8(buf.wrapping_add(i))?);
+            }
+            if self.inputs.as_ref().is_none_or(|log| log.live()) {
+                self.net.send(&frame);
+            }
+        }
+
+        self.write32(desc.wrapping_add(4), done)?;
+        Ok(true)
+    }
+
+    fn net_receive(&mut self, frame: &[u8]) {
+        let Some(desc) = self.net.rx.desc_addr() else {
+            self.net.drop_rx();
+            return;
+        };
+
+        match self.net_fill_desc(desc, frame) {
+            Ok(true) => {
+                self.net.rx.advance();
+                self.net.raise_status(net::STATUS_RX_DONE);
+            }
+            Ok(false) => self.net.drop_rx(),
+            Err(_) => self.net.raise_status(net::STATUS_ERROR),
+        }
+    }
+
+    /// Writes a received frame into an RX descriptor. Frames larger than the buffer are
+    /// truncated and flagged. Returns false when the NIC doesn't own the descriptor.
+    fn net_fill_desc(&mut self, desc: u32, frame: &[u8]) -> Result<bool, BusError> {
+        let flags = self.read32(desc.wrapping_add(4))?;
+        if flags & net::DESC_OWN == 0 {
+            return Ok(false);
+        }
+
+        let buf = self.read32(desc)?;
+        let size = (flags & net::DESC_LEN_MASK) as usize;
+        let len = frame.len().min(size);
+        for (i, &byte) in frame[..len].iter().enumerate() {
+            self.write8(buf.wrapping_add(i as u32), byte)?;
+        }
+
+        let mut done = len as u32;
+        if len < frame.len() {
+            done |= net::DESC_ERROR;
+        }
+        self.write32(desc.wrapping_add(4), done)?;
+        Ok(true)
+    }
+
+    // --- DMA helpers ---------------------------------------------------------
+
+    /// Moves one DMA unit over the bus. Returns true when the DMA controller used the bus this
+    /// cycle, in which case the CPU has to wait.
+    pub fn dma_tick(&mut self) -> bool {
+        let Some(xfer) = self.dma.next_transfer() else {
+            return false;
+        };
+
+        let ok = if xfer.word {
+            self.read32(xfer.src).and_then(|v| self.write32(xfer.dst, v)).is_ok()
+        } else {
+            self.read8(xfer.src).and_then(|v| self.write8(xfer.dst, v)).is_ok()
+        };
+
+        self.dma.complete(xfer, ok);
+        true
+    }
+
+    fn dma_channel(addr: u32) -> Option<(usize, u32)> {
+        let size = DMA_CHANNEL_STRIDE * dma::CHANNEL_COUNT as u32;
+        if !Self::in_range(addr, DMA_BASE, size) {
+            return None;
+        }
+
+        let off = addr - DMA_BASE;
+        Some(((off / DMA_CHANNEL_STRIDE) as usize, off % DMA_CHANNEL_STRIDE))
+    }
+
+    fn dma_read32(&mut self, addr: u32) -> Result<u32, BusError> {
+        let Some((idx, reg)) = Self::dma_channel(addr) else {
+            return Err(BusError::OutOfBounds(addr));
+        };
+        let ch = &self.dma.channels[idx];
+
+        match reg {
+            DMA_SRC => Ok(ch.src()),
+            DMA_DST => Ok(ch.dst()),
+            DMA_LEN => Ok(ch.length()),
+            DMA_CTRL => Ok(ch.ctrl()),
+            DMA_STATUS => Ok(ch.status()),
+            DMA_REMAINING => Ok(ch.remaining()),
+            _ => Err(BusError::OutOfBounds(addr)),
+        }
+    }
+
+    fn dma_write32(&mut self, addr: u32, value: u32) -> Result<(), BusError> {
+        let Some((idx, reg)) = Self::dma_channel(addr) else {
+            return Err(BusError::OutOfBounds(addr));
+        };
+        let ch = &mut self.dma.channels[idx];
+
+        match reg {
+            DMA_SRC => ch.set_src(value),
+            DMA_DST => ch.set_dst(value),
+            DMA_LEN => ch.set_length(value),
+            DMA_CTRL => ch.set_ctrl(value),
+            DMA_STATUS => ch.clear_status(value),
+            DMA_REMAINING => {}
+            _ => return Err(BusError::OutOfBounds(addr)),
+        }
+        Ok(())
+    }
+
+    // --- System control helpers ----------------------------------------------
+
+    fn sys_read32(&mut self, addr: u32) -> Result<u32, BusError> {
+        match addr - SYS_BASE {
+            SYS_BOARD_ID => Ok(syscon::BOARD_ID),
+            SYS_RAM_SIZE => Ok(RAM_SIZE),
+            SYS_RESET_REASON => Ok(self.reset_reason),
+            SYS_RESET | SYS_POWEROFF => Ok(0),
+            _ => Err(BusError::OutOfBounds(addr)),
+        }
+    }
+
+    fn sys_write32(&mut self, addr: u32, value: u32) -> Result<(), BusError> {
+        match addr - SYS_BASE {
+            SYS_RESET => self.syscon.reset(value),
+            SYS_POWEROFF => self.syscon.power_off(value),
+            SYS_BOARD_ID | SYS_RAM_SIZE | SYS_RESET_REASON => {}
+            _ => return Err(BusError::OutOfBounds(addr)),
+        }
+        Ok(())
+    }
+
+    // --- Peripheral register helpers -----------------------------------------
+
+    fn spi_read32(&mut self, addr: u32) -> Result<u32, BusError> {
+        match addr - SPI_BASE {
+            SPI_CTRL => Ok(self.spi.ctrl()),
+            SPI_CS => Ok(self.spi.cs()),
+            SPI_DATA => Ok(self.spi.read_data()),
+            SPI_STATUS => Ok(self.spi.status()),
+            _ => Err(BusError::OutOfBounds(addr)),
+        }
+    }
+
+    fn spi_write32(&mut self, addr: u32, value: u32) -> Result<(), BusError> {
+        match addr - SPI_BASE {
+            SPI_CTRL => self.spi.set_ctrl(value),
+            SPI_CS => self.spi.set_cs(value),
+            SPI_DATA => self.spi.write_data(value),
+            SPI_STATUS => {}
+            _ => return Err(BusError::OutOfBounds(addr)),
+        }
+        Ok(())
+    }
+
+    fn i2c_read32(&mut self, addr: u32) -> Result<u32, BusError> {
+        match addr - I2C_BASE {
+            I2C_CTRL => Ok(self.i2c.ctrl()),
+            I2C_DATA => Ok(self.i2c.data()),
+            I2C_CMD => Ok(0),
+            I2C_STATUS => Ok(self.i2c.status()),
+            _ => Err(BusError::OutOfBounds(addr)),
+        }
+    }
+
+    fn i2c_write32(&mut self, addr: u32, value: u32) -> Result<(), BusError> {
+        match addr - I2C_BASE {
+            I2C_CTRL => self.i2c.set_ctrl(value),
+            I2C_DATA => self.i2c.set_data(value),
+            I2C_CMD => self.i2c.command(value),
+            I2C_STATUS => {}
+            _ => return Err(BusError::OutOfBounds(addr)),
+        }
+        Ok(())
+    }
+
+    fn kbd_read32(&mut self, addr: u32) -> Result<u32, BusError> {
+        match addr - KBD_BASE {
+            KBD_DATA => Ok(self.keyboard.read_data()),
+            KBD_STATUS => Ok(self.keyboard.status()),
+            KBD_CTRL => Ok(self.keyboard.ctrl()),
+            _ => Err(BusError::OutOfBounds(addr)),
+        }
+    }
+
+    fn kbd_write32(&mut self, addr: u32, value: u32) -> Result<(), BusError> {
+        match addr - KBD_BASE {
+            KBD_DATA => {}
+            KBD_STATUS => self.keyboard.clear_status(value),
+            KBD_CTRL => self.keyboard.set_ctrl(value),
+            _ => return Err(BusError::OutOfBounds(addr)),
+        }
+        Ok(())
+    }
+
+    fn audio_read32(&mut self, addr: u32) -> Result<u32, BusError> {
+        match addr - AUDIO_BASE {
+            AUDIO_CTRL => Ok(self.audio.ctrl()),
+            AUDIO_RATE => Ok(self.audio.rate()),
+            AUDIO_FORMAT => Ok(self.audio.format()),
+            AUDIO_DATA => Ok(0),
+            AUDIO_STATUS => Ok(self.audio.status()),
+            AUDIO_LEVEL => Ok(self.audio.level()),
+            _ => Err(BusError::OutOfBounds(addr)),
+        }
+    }
+
+    fn audio_write32(&mut self, addr: u32, value: u32) -> Result<(), BusError> {
+        match addr - AUDIO_BASE {
+            AUDIO_CTRL => self.audio.set_ctrl(value),
+            AUDIO_RATE => self.audio.set_rate(value),
+            AUDIO_FORMAT => self.audio.set_format(value),
+            AUDIO_DATA => self.audio.write_data(value),
+            AUDIO_STATUS => self.audio.clear_status(value),
+            AUDIO_LEVEL => {}
+            _ => return Err(BusError::OutOfBounds(addr)),
+        }
+        Ok(())
+    }
+
+    fn net_read32(&mut self, addr: u32) -> Result<u32, BusError> {
+        match addr - NET_BASE {
+            NET_CTRL => Ok(self.net.ctrl()),
+            NET_STATUS => Ok(self.net.status()),
+            NET_TX_RING => Ok(self.net.tx.base),
+            NET_TX_COUNT => Ok(self.net.tx.count),
+            NET_TX_INDEX => Ok(self.net.tx.index),
+            NET_TX_POLL => Ok(0),
+            NET_RX_RING => Ok(self.net.rx.base),
+            NET_RX_COUNT => Ok(self.net.rx.count),
+            NET_RX_INDEX => Ok(self.net.rx.index),
+            NET_RX_DROPPED => Ok(self.net.rx_dropped()),
+            NET_MAC_LO => Ok(self.net.mac_lo()),
+            NET_MAC_HI => Ok(self.net.mac_hi()),
+            _ => Err(BusError::OutOfBounds(addr)),
+        }
+    }
+
+    fn net_write32(&mut self, addr: u32, value: u32) -> Result<(), BusError> {
+        match addr - NET_BASE {
+            NET_CTRL => self.net.set_ctrl(value),
+            NET_STATUS => self.net.clear_status(value),
+            NET_TX_RING => self.net.set_tx_ring(value, self.net.tx.count),
+            NET_TX_COUNT => self.net.set_tx_ring(self.net.tx.base, value),
+            NET_TX_POLL => self.net.poll_tx(),
+            NET_RX_RING => self.net.set_rx_ring(value, self.net.rx.count),
+            NET_RX_COUNT => self.net.set_rx_ring(self.net.rx.base, value),
+            NET_TX_INDEX | NET_RX_INDEX | NET_RX_DROPPED | NET_MAC_LO | NET_MAC_HI => {}
+            _ => return Err(BusError::OutOfBounds(addr)),
+        }
+        Ok(())
+    }
+
+    fn smp_lock(reg: u32) -> Option<usize> {
+        let lock = reg.checked_sub(SMP_LOCK_BASE)? / 4;
+        (lock < smp::SPINLOCK_COUNT as u32).then_some(lock as usize)
+    }
+
+    fn smp_read32(&mut self, addr: u32) -> Result<u32, BusError> {
+        match addr - SMP_BASE {
+            SMP_HART_COUNT => Ok(self.smp.harts()),
+            SMP_IPI_PENDING => Ok(self.smp.pending()),
+            SMP_IPI_SET | SMP_IPI_CLEAR => Ok(0),
+            SMP_LOCK_STATE => Ok(self.smp.locks()),
+            reg => match Self::smp_lock(reg) {
+                Some(lock) => Ok(self.smp.try_lock(lock)),
+                None => Err(BusError::OutOfBounds(addr)),
+            },
+        }
+    }
+
+    fn smp_write32(&mut self, addr: u32, value: u32) -> Result<(), BusError> {
+        match addr - SMP_BASE {
+            SMP_IPI_SET => self.smp.raise(value),
+            SMP_IPI_CLEAR => self.smp.clear(value),
+            SMP_HART_COUNT | SMP_IPI_PENDING | SMP_LOCK_STATE => {}
+            reg => match Self::smp_lock(reg) {
+                Some(lock) => self.smp.unlock(lock),
+                None => return Err(BusError::OutOfBounds(addr)),
+            },
+        }
+        Ok(())
+    }
+
+    // --- GPIO helpers --------------------------------------------------------
+
+    fn gpio_read32(&mut self, addr: u32) -> Result<u32, BusError> {
+        match addr - GPIO_BASE {
+            GPIO_DIR => Ok(self.gpio.dir()),
+            GPIO_OUT => Ok(self.gpio.out()),
+            GPIO_IN => Ok(self.gpio.levels()),
+            GPIO_IRQ_ENABLE => Ok(self.gpio.irq_enable()),
+            GPIO_IRQ_TYPE => Ok(self.gpio.irq_type()),
+            GPIO_IRQ_POLARITY => Ok(self.gpio.irq_polarity()),
+            GPIO_IRQ_ANY_EDGE => Ok(self.gpio.irq_any_edge()),
+            GPIO_IRQ_STATUS => Ok(self.gpio.irq_status()),
+            GPIO_OUT_SET | GPIO_OUT_CLR | GPIO_OUT_TOGGLE => Ok(0),
+            _ => Err(BusError::OutOfBounds(addr)),
+        }
+    }
+
+    fn gpio_write32(&mut self, addr: u32, value: u32) -> Result<(), BusError> {
+        match addr - GPIO_BASE {
+            GPIO_DIR => self.gpio.set_dir(value),
+            GPIO_OUT => self.gpio.set_out(value),
+            GPIO_IN => {}
+            GPIO_IRQ_ENABLE => self.gpio.set_irq_enable(value),
+            GPIO_IRQ_TYPE => self.gpio.set_irq_type(value),
+            GPIO_IRQ_POLARITY => self.gpio.set_irq_polarity(value),
+            GPIO_IRQ_ANY_EDGE => self.gpio.set_irq_any_edge(value),
+            GPIO_IRQ_STATUS => self.gpio.clear_irq_status(value),
+            GPIO_OUT_SET => self.gpio.set_out_bits(value),
+            GPIO_OUT_CLR => self.gpio.clear_out_bits(value),
+            GPIO_OUT_TOGGLE => self.gpio.toggle_out_bits(value),
+            _ => return Err(BusError::OutOfBounds(addr)),
+        }
+        Ok(())
+    }
+
+    // --- Host input helpers --------------------------------------------------
+    //
+    // Without an input log the devices talk to the host directly. With one, host inputs are
+    // recorded on the first run through a cycle and taken from the log when it is replayed.
+
+    /// Advances the RTC. An alarm driven by the host clock is a host input.
+    pub fn rtc_tick(&mut self) {
+        let Some(log) = self.inputs.as_mut().filter(|_| self.rtc.host_clock()) else {
+            self.rtc.tick();
+            return;
+        };
+
+        self.rtc.advance();
+        let due = if log.live() {
+            let due = self.rtc.poll_alarm();
+            if due {
+                log.record(Input::RtcAlarm);
+            }
+            due
+        } else {
+            log.replay(|input| (*input == Input::RtcAlarm).then_some(())).is_some()
+        };
+        if due {
+            self.rtc.raise_alarm();
+        }
+    }
+
+    /// Reads the RTC seconds register, latching the sub-seconds
+    fn rtc_read_seconds(&mut self) -> u32 {
+        let Some(log) = self.inputs.as_mut().filter(|_| self.rtc.host_clock()) else {
+            return self.rtc.read_seconds();
+        };
+
+        let now = if log.live() {
+            let now = self.rtc.now_us();
+            log.record(Input::HostClock { us: now });
+            now
+        } else {
+            let logged = log.replay(|input| match *input {
+                Input::HostClock { us } => Some(us),
+                _ => None,
+            });
+            // Reading the host clock now would make the replay depend on when it runs
+            logged.or_else(|| log.last_clock()).unwrap_or(0)
+        };
+        self.rtc.read_seconds_at(now)
+    }
+
+    /// Advances the RNG. Words from the host source are host inputs.
+    pub fn rng_tick(&mut self) {
+        let Some(log) = self.inputs.as_mut().filter(|_| self.rng.host_source()) else {
+            self.rng.tick();
+            return;
+        };
+
+        if !self.rng.advance() {
+            return;
+        }
+        let word = if log.live() {
+            let word = self.rng.generate();
+            log.record(Input::HostRandom { word });
+            word
+        } else {
+            let logged = log.replay(|input| match *input {
+                Input::HostRandom { word } => Some(word),
+                _ => None,
+            });
+            logged.unwrap_or(0)
+        };
+        self.rng.fill(word);
+    }
+
+    // --- UART helpers --------------------------------------------------------
+
+    /// Polls the UART backends. Returns the asserted UART IRQ lines.
+    pub fn uart_tick(&mut self) -> u32 {
+        let mut uart_irq = 0;
+        for (i, port) in self.uarts.iter_mut().enumerate() {
+            match self.inputs.as_mut() {
+                None => port.uart.tick(),
+                Some(log) if log.live() => {
+                    if let Some(byte) = port.uart.poll_backend() {
+                        log.record(Input::UartRx { port: i, byte });
+                        port.uart.receive(byte);
+                    }
+                }
+                Some(log) => {
+                    let logged = log.replay(|input| match *input {
+                        Input::UartRx { port, byte } if port == i => Some(byte),
+                        _ => None,
+                    });
+                    if let Some(byte) = logged {
+                        port.uart.receive(byte);
+                    }
+                }
+            }
+            if port.uart.irq() {
+                uart_irq |= 1 << port.irq_line;
+            }
+        }
+        uart_irq
+    }
+
+
+    fn uart_read32(&mut self, addr: u32) -> Result<u32, BusError> {
+        let Some(idx) = self.uart_port(addr) else {
+            return Err(BusError::OutOfBounds(addr));
+        };
+        let port = &mut self.uarts[idx];
+
+        match addr - port.base {
+            UART_STATUS => Ok(port.uart.status()),
+            UART_TX => Ok(0),
+            _ => Err(BusError::OutOfBounds(addr)),
+        }
+    }
+
+    fn uart_write32(&mut self, addr: u32, value: u32) -> Result<(), BusError> {
+        let Some(idx) = self.uart_port(addr) else {
+            return Err(BusError::OutOfBounds(addr));
+        };
+        let port = &mut self.uarts[idx];
+
+        match addr - port.base {
+            UART_STATUS => {
+                // usually STATUS is read-only; you might ignore writes or use for clears
+                Ok(())
+            }
+            UART_TX => {
+                // normally you'd only use store8 here, but define behavior anyway:
+                let byte = (value & 0xFF) as u8;
+                port.uart.write_tx(byte);
+                Ok(())
+            }
+            _ => Err(BusError::OutOfBounds(addr)),
+        }
+    }
+
     // --- MMIO helpers --------------------------------------------------------
 
     fn mmio_read32(&mut self, addr: u32) -> Result<u32, BusError> {
@@ -113,16 +1152,74 @@ impl NovaBus {
             TIMER1_COUNT => Ok(self.timer1.count()),
             TIMER1_ACK => Ok(0),
             TIMER1_RESET => Ok(0),
+            TIMER1_PRESCALER => Ok(self.timer1.prescaler()),
+            TIMER1_STATUS => Ok(self.timer1.status()),
+            TIMER1_COMPARE => Ok(self.timer1.compare()),
 
             TIMER2_CTRL => Ok(self.timer2.ctrl()),
             TIMER2_PERIOD => Ok(self.timer2.period()),
             TIMER2_COUNT => Ok(self.timer2.count()),
             TIMER2_ACK => Ok(0),
             TIMER2_RESET => Ok(0),
+            TIMER2_PRESCALER => Ok(self.timer2.prescaler()),
+            TIMER2_STATUS => Ok(self.timer2.status()),
+            TIMER2_COMPARE => Ok(self.timer2.compare()),
 
-            UART_STATUS => Ok(self.uart.status()),
-            UART_TX => Ok(0),
-            _ => Err(BusError::OutOfBounds(addr)),
+            RTC_SECONDS => Ok(self.rtc_read_seconds()),
+            RTC_SUBSEC => Ok(self.rtc.latched_subseconds()),
+            RTC_ALARM => Ok(self.rtc.alarm()),
+            RTC_CTRL => Ok(self.rtc.ctrl()),
+            RTC_STATUS => Ok(self.rtc.status()),
+
+            DISPLAY_MODE => Ok(self.display.mode()),
+            DISPLAY_CURSOR => Ok(self.display.cursor()),
+            DISPLAY_CURSOR_CTRL => Ok(self.display.cursor_ctrl()),
+            DISPLAY_BLANK => Ok(self.display.blank()),
+            DISPLAY_PAL_INDEX => Ok(self.display.palette_index()),
+            DISPLAY_PAL_DATA => Ok(self.display.palette_data()),
+            DISPLAY_INFO => Ok(self.display.info()),
+
+            FB_CTRL => Ok(self.framebuffer.ctrl()),
+            FB_WIDTH => Ok(self.framebuffer.width()),
+            FB_HEIGHT => Ok(self.framebuffer.height()),
+            FB_FORMAT => Ok(self.framebuffer.format()),
+            FB_VSYNC_PERIOD => Ok(self.framebuffer.vsync_period()),
+            FB_STATUS => Ok(self.framebuffer.status()),
+            FB_PAL_INDEX => Ok(self.framebuffer.palette_index()),
+            FB_PAL_DATA => Ok(self.framebuffer.palette_data()),
+            FB_FRAME => Ok(self.framebuffer.frame()),
+
+            BLK_CTRL => Ok(self.block.ctrl()),
+            BLK_STATUS => Ok(self.block.status()),
+            BLK_CMD => Ok(0),
+            BLK_LBA => Ok(self.block.lba()),
+            BLK_COUNT => Ok(self.block.count()),
+            BLK_BUF_ADDR => Ok(self.block.buf_addr()),
+            BLK_SECTOR_SIZE => Ok(block::SECTOR_SIZE),
+            BLK_CAPACITY => Ok(self.block.capacity()),
+            BLK_ERROR => Ok(self.block.error()),
+
+            WDT_CTRL => Ok(self.watchdog.ctrl()),
+            WDT_TIMEOUT => Ok(self.watchdog.timeout()),
+            WDT_KICK => Ok(0),
+            WDT_COUNT => Ok(self.watchdog.count()),
+            WDT_STATUS => Ok(self.watchdog.status()),
+            WDT_RESET_REASON => Ok(self.reset_reason),
+
+            RNG_DATA => Ok(self.rng.read_data()),
+            RNG_STATUS => Ok(self.rng.status()),
+            RNG_SEED => Ok(0),
+
+            _ if Self::dma_channel(addr).is_some() => self.dma_read32(addr),
+            _ if Self::in_range(addr, GPIO_BASE, GPIO_SIZE) => self.gpio_read32(addr),
+            _ if Self::in_range(addr, SYS_BASE, SYS_SIZE) => self.sys_read32(addr),
+            _ if Self::in_range(addr, SPI_BASE, SPI_SIZE) => self.spi_read32(addr),
+            _ if Self::in_range(addr, I2C_BASE, I2C_SIZE) => self.i2c_read32(addr),
+            _ if Self::in_range(addr, KBD_BASE, KBD_SIZE) => self.kbd_read32(addr),
+            _ if Self::in_range(addr, AUDIO_BASE, AUDIO_SIZE) => self.audio_read32(addr),
+            _ if Self::in_range(addr, NET_BASE, NET_SIZE) => self.net_read32(addr),
+            _ if Self::in_range(addr, SMP_BASE, SMP_SIZE) => self.smp_read32(addr),
+            _ => self.uart_read32(addr),
         }
     }
 
@@ -145,6 +1242,18 @@ impl NovaBus {
                 self.timer1.reset();
                 Ok(())
             }
+            TIMER1_PRESCALER => {
+                self.timer1.set_prescaler(value);
+                Ok(())
+            }
+            TIMER1_STATUS => {
+                self.timer1.clear_status(value);
+                Ok(())
+            }
+            TIMER1_COMPARE => {
+                self.timer1.set_compare(value);
+                Ok(())
+            }
 
             TIMER2_CTRL => {
                 self.timer2.set_ctrl(value);
@@ -163,18 +1272,157 @@ impl NovaBus {
                 self.timer2.reset();
                 Ok(())
             }
+            TIMER2_PRESCALER => {
+                self.timer2.set_prescaler(value);
+                Ok(())
+            }
+            TIMER2_STATUS => {
+                self.timer2.clear_status(value);
+                Ok(())
+            }
+            TIMER2_COMPARE => {
+                self.timer2.set_compare(value);
+                Ok(())
+            }
 
-            UART_STATUS => {
-                // usually STATUS is read-only; you might ignore writes or use for clears
+            RTC_SECONDS => {
+                self.rtc.set_seconds(value);
                 Ok(())
             }
-            UART_TX => {
-                // normally you'd only use store8 here, but define behavior anyway:
-                let byte = (value & 0xFF) as u8;
-                self.uart.write_tx(byte);
+            RTC_SUBSEC => Ok(()),
+            RTC_ALARM => {
+                self.rtc.set_alarm(value);
                 Ok(())
             }
-            _ => Err(BusError::OutOfBounds(addr)),
+            RTC_CTRL => {
+                self.rtc.set_ctrl(value);
+                Ok(())
+            }
+            RTC_STATUS => {
+                self.rtc.clear_status(value);
+                Ok(())
+            }
+
+            DISPLAY_MODE => {
+                self.display.set_mode(value);
+                Ok(())
+            }
+            DISPLAY_CURSOR => {
+                self.display.set_cursor(value);
+                Ok(())
+            }
+            DISPLAY_CURSOR_CTRL => {
+                self.display.set_cursor_ctrl(value);
+                Ok(())
+            }
+            DISPLAY_BLANK => {
+                self.display.set_blank(value);
+                Ok(())
+            }
+            DISPLAY_PAL_INDEX => {
+                self.display.set_palette_index(value);
+                Ok(())
+            }
+            DISPLAY_PAL_DATA => {
+                self.display.set_palette_data(value);
+                Ok(())
+            }
+            DISPLAY_INFO => Ok(()),
+
+            FB_CTRL => {
+                self.framebuffer.set_ctrl(value);
+                Ok(())
+            }
+            FB_WIDTH => {
+                self.framebuffer.set_width(value);
+                Ok(())
+            }
+            FB_HEIGHT => {
+                self.framebuffer.set_height(value);
+                Ok(())
+            }
+            FB_FORMAT => {
+                self.framebuffer.set_format(value);
+                Ok(())
+            }
+            FB_VSYNC_PERIOD => {
+                self.framebuffer.set_vsync_period(value);
+                Ok(())
+            }
+            FB_STATUS => {
+                self.framebuffer.clear_status(value);
+                Ok(())
+            }
+            FB_PAL_INDEX => {
+                self.framebuffer.set_palette_index(value);
+                Ok(())
+            }
+            FB_PAL_DATA => {
+                self.framebuffer.set_palette_data(value);
+                Ok(())
+            }
+            FB_FRAME => Ok(()),
+
+            BLK_CTRL => {
+                self.block.set_ctrl(value);
+                Ok(())
+            }
+            BLK_STATUS => {
+                self.block.clear_status(value);
+                Ok(())
+            }
+            BLK_CMD => {
+                self.block.command(value);
+                Ok(())
+            }
+            BLK_LBA => {
+                self.block.set_lba(value);
+                Ok(())
+            }
+            BLK_COUNT => {
+                self.block.set_count(value);
+                Ok(())
+            }
+            BLK_BUF_ADDR => {
+                self.block.set_buf_addr(value);
+                Ok(())
+            }
+            BLK_SECTOR_SIZE | BLK_CAPACITY | BLK_ERROR => Ok(()),
+
+            WDT_CTRL => {
+                self.watchdog.set_ctrl(value);
+                Ok(())
+            }
+            WDT_TIMEOUT => {
+                self.watchdog.set_timeout(value);
+                Ok(())
+            }
+            WDT_KICK => {
+                self.watchdog.kick();
+                Ok(())
+            }
+            WDT_STATUS => {
+                self.watchdog.clear_status(value);
+                Ok(())
+            }
+            WDT_COUNT | WDT_RESET_REASON => Ok(()),
+
+            RNG_SEED => {
+                self.rng.seed(value as u64);
+                Ok(())
+            }
+            RNG_DATA | RNG_STATUS => Ok(()),
+
+            _ if Self::dma_channel(addr).is_some() => self.dma_write32(addr, value),
+            _ if Self::in_range(addr, GPIO_BASE, GPIO_SIZE) => self.gpio_write32(addr, value),
+            _ if Self::in_range(addr, SYS_BASE, SYS_SIZE) => self.sys_write32(addr, value),
+            _ if Self::in_range(addr, SPI_BASE, SPI_SIZE) => self.spi_write32(addr, value),
+            _ if Self::in_range(addr, I2C_BASE, I2C_SIZE) => self.i2c_write32(addr, value),
+            _ if Self::in_range(addr, KBD_BASE, KBD_SIZE) => self.kbd_write32(addr, value),
+            _ if Self::in_range(addr, AUDIO_BASE, AUDIO_SIZE) => self.audio_write32(addr, value),
+            _ if Self::in_range(addr, NET_BASE, NET_SIZE) => self.net_write32(addr, value),
+            _ if Self::in_range(addr, SMP_BASE, SMP_SIZE) => self.smp_write32(addr, value),
+            _ => self.uart_write32(addr, value),
         }
     }
 
@@ -186,21 +1434,61 @@ impl NovaBus {
     }
 
     fn mmio_write8(&mut self, addr: u32, value: u8) -> Result<(), BusError> {
-        match addr {
-            UART_TX => {
-                self.uart.write_tx(value);
-                Ok(())
-            }
-            // for others, you could do read-modify-write on the 32-bit reg if you want:
-            _ => {
-                let aligned = addr & !3;
-                let shift = (addr & 3) * 8;
-                let mask = !(0xFFu32 << shift);
-                let mut word = self.mmio_read32(aligned)?;
-                word = (word & mask) | ((value as u32) << shift);
-                self.mmio_write32(aligned, word)
+        if let Some(idx) = self.uart_port(addr) {
+            let port = &mut self.uarts[idx];
+            if addr - port.base == UART_TX {
+                port.uart.write_tx(value);
+                return Ok(());
             }
         }
+
+        // Registers aren't read back: reads can have side effects and a W1C status reads back
+        // the bits that are pending, not the ones to clear
+        let aligned = addr & !3;
+        let shift = (addr & 3) * 8;
+        if Self::mmio_read_has_side_effects(aligned) {
+            return Err(BusError::DeviceFault(addr));
+        }
+        if Self::mmio_acts_on_ones(aligned) {
+            return self.mmio_write32(aligned, (value as u32) << shift);
+        }
+
+        let mask = !(0xFFu32 << shift);
+        let last = self.mmio_written.get(&aligned).copied().unwrap_or(0);
+        let word = (last & mask) | ((value as u32) << shift);
+        self.mmio_write32(aligned, word)?;
+        self.mmio_written.insert(aligned, word);
+        Ok(())
+    }
+
+    /// Registers whose reads change the device state. They only take word stores.
+    fn mmio_read_has_side_effects(addr: u32) -> bool {
+        matches!(addr, RTC_SECONDS | RNG_DATA)
+            || addr == KBD_BASE + KBD_DATA
+            || (Self::in_range(addr, SMP_BASE, SMP_SIZE) && Self::smp_lock(addr - SMP_BASE).is_some())
+    }
+
+    /// Registers where only the bits written as 1 do something: W1C status registers and the
+    /// GPIO and IPI set/clear registers. A byte store leaves the other bytes 0.
+    fn mmio_acts_on_ones(addr: u32) -> bool {
+        if let Some((_, reg)) = Self::dma_channel(addr) {
+            return reg == DMA_STATUS;
+        }
+        matches!(
+            addr,
+            TIMER1_STATUS | TIMER2_STATUS | RTC_STATUS | FB_STATUS | BLK_STATUS | WDT_STATUS
+        ) || [
+            GPIO_BASE + GPIO_IRQ_STATUS,
+            GPIO_BASE + GPIO_OUT_SET,
+            GPIO_BASE + GPIO_OUT_CLR,
+            GPIO_BASE + GPIO_OUT_TOGGLE,
+            KBD_BASE + KBD_STATUS,
+            AUDIO_BASE + AUDIO_STATUS,
+            NET_BASE + NET_STATUS,
+            SMP_BASE + SMP_IPI_SET,
+            SMP_BASE + SMP_IPI_CLEAR,
+        ]
+        .contains(&addr)
     }
 }
 
@@ -222,7 +1510,12 @@ impl Bus for NovaBus {
             return self.font_ram.read8(off);
         }
 
-        if (MMIO_BASE..=MMIO_END).contains(&addr) {
+        if Self::in_range(addr, FB_BASE, FB_SIZE) {
+            let off = addr - FB_BASE;
+            return self.framebuffer.read8(off);
+        }
+
+        if self.is_mmio(addr) {
             return self.mmio_read8(addr);
         }
 
@@ -246,7 +1539,22 @@ impl Bus for NovaBus {
             return Ok(u32::from_le_bytes([b0, b1, b2, b3]));
         }
 
-        if (MMIO_BASE..=MMIO_END).contains(&addr) {
+        if Self::in_range(addr, VRAM_BASE, VRAM_SIZE) {
+            let off = addr - VRAM_BASE;
+            return self.vram.read32(off);
+        }
+
+        if Self::in_range(addr, FONT_BASE, FONT_SIZE) {
+            let off = addr - FONT_BASE;
+            return self.font_ram.read32(off);
+        }
+
+        if Self::in_range(addr, FB_BASE, FB_SIZE) {
+            let off = addr - FB_BASE;
+            return self.framebuffer.read32(off);
+        }
+
+        if self.is_mmio(addr) {
             return self.mmio_read32(addr);
         }
 
@@ -254,6 +1562,10 @@ impl Bus for NovaBus {
     }
 
     fn write8(&mut self, addr: u32, value: u8) -> Result<(), BusError> {
+        if !self.permits(addr, Access::Store) {
+            return Err(BusError::WriteProtected(addr));
+        }
+
         if addr <= RAM_END {
             self.ram.write8(addr, value)?;
             return Ok(());
@@ -271,7 +1583,13 @@ impl Bus for NovaBus {
             return Ok(());
         }
 
-        if (MMIO_BASE..=MMIO_END).contains(&addr) {
+        if Self::in_range(addr, FB_BASE, FB_SIZE) {
+            let off = addr - FB_BASE;
+            self.framebuffer.write8(off, value)?;
+            return Ok(());
+        }
+
+        if self.is_mmio(addr) {
             return self.mmio_write8(addr, value);
         }
 
@@ -283,16 +1601,51 @@ impl Bus for NovaBus {
             return Err(BusError::Misaligned(addr));
         }
 
+        if !self.permits(addr, Access::Store) {
+            return Err(BusError::WriteProtected(addr));
+        }
+
         if addr <= RAM_END {
             self.ram.write32(addr, value)?;
             return Ok(());
         }
 
-        if (MMIO_BASE..=MMIO_END).contains(&addr) {
+        if Self::in_range(addr, VRAM_BASE, VRAM_SIZE) {
+            let off = addr - VRAM_BASE;
+            self.vram.write32(off, value)?;
+            return Ok(());
+        }
+
+        if Self::in_range(addr, FONT_BASE, FONT_SIZE) {
+            let off = addr - FONT_BASE;
+            self.font_ram.write32(off, value)?;
+            return Ok(());
+        }
+
+        if Self::in_range(addr, FB_BASE, FB_SIZE) {
+            let off = addr - FB_BASE;
+            self.framebuffer.write32(off, value)?;
+            return Ok(());
+        }
+
+        if self.is_mmio(addr) {
             self.mmio_write32(addr, value)?;
+            self.mmio_written.insert(addr, value);
             return Ok(())
         }
 
         Err(BusError::OutOfBounds(addr))
     }
+
+    fn permits(&self, addr: u32, access: Access) -> bool {
+        self.protected.iter().filter(|r| r.contains(addr)).all(|r| match access {
+            Access::Fetch => !r.no_exec,
+            Access::Load => true,
+            Access::Store => !r.read_only,
+        })
+    }
+
+    fn cacheable(&self, addr: u32) -> bool {
+        !self.is_mmio(addr)
+    }
 }
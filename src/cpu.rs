@@ -1,15 +1,29 @@
 use crate::bus::Bus;
 use crate::cpu::isa::op_str;
+use crate::cpu::branch::BranchModel;
+use crate::cpu::cache::Cache;
+use crate::cpu::mmu::{Access, Mmu};
+use crate::cpu::mpu::Mpu;
+use crate::cpu::pipeline::{Pipeline, Retire};
 use crate::machine::IrqLines;
+use crate::machine::snapshot::{Snapshot, StateReader, StateWriter};
+use std::io;
 use std::fmt::{Debug, Formatter};
 
+pub mod branch;
+pub mod cache;
 pub mod isa;
+pub mod mmu;
+pub mod mpu;
+pub mod pipeline;
 
 // Special register (SR) flags
 pub const SR_EI: u32 = 1 << 0; // Exception In Progress
 pub const SR_U: u32 = 1 << 2; // User Mode
 pub const SR_IE: u32 = 1 << 4; // Interrupt Enable
 
+const RESET_SR: u32 = SR_IE; // Interrupts are enabled out of reset
+
 pub const LINK_REGISTER: usize = 31; // Where the CPU wil store return addresses
 
 const RESET_VECTOR: u32 = 0x0000_0000; // Reset vector where the CPU starts execution
@@ -26,8 +40,30 @@ pub struct Cpu {
     epc: u32,
     /// Cause of the last exception
     cause: u32,
+    /// Status register at the time of the last exception
+    esr: u32,
+    /// Faulting virtual address of the last page fault
+    badvaddr: u32,
+    /// Number of this core
+    hart_id: u32,
     /// Is the CPU halted
     pub halted: bool,
+    /// Memory management unit
+    pub mmu: Mmu,
+    pub mpu: Mpu,
+    /// Instruction cache, None = fetches take no extra cycles
+    pub icache: Option<Cache>,
+    /// Data cache, None = loads and stores take no extra cycles
+    pub dcache: Option<Cache>,
+    /// Pipeline timing model, None = every instruction takes a single cycle
+    pub pipeline: Option<Pipeline>,
+    /// Branch predictor, None = conditional branches aren't predicted (the pipeline assumes
+    /// not taken)
+    pub branch_predictor: Option<BranchModel>,
+    /// Cycles left until the last instruction is done (memory latency, pipeline hazards)
+    stall: u32,
+    /// Total cycles spent stalled
+    stall_cycles: u64,
 }
 
 impl Cpu {
@@ -46,9 +82,26 @@ impl Cpu {
     pub fn cause(&self) -> u32 {
         self.cause
     }
+    pub fn esr(&self) -> u32 {
+        self.esr
+    }
+    pub fn badvaddr(&self) -> u32 {
+        self.badvaddr
+    }
     pub fn halted(&self) -> bool {
         self.halted
     }
+    /// True when the next step executes an instruction or takes an exception, i.e. the core
+    /// is neither halted nor stalled
+    pub fn ready(&self) -> bool {
+        !self.halted && self.stall == 0
+    }
+    pub fn hart_id(&self) -> u32 {
+        self.hart_id
+    }
+    pub fn stall_cycles(&self) -> u64 {
+        self.stall_cycles
+    }
 }
 
 pub struct Instruction {
@@ -113,13 +166,29 @@ impl Default for Cpu {
 
 impl Cpu {
     pub fn new() -> Self {
+        Self::with_hart_id(0)
+    }
+
+    /// Creates a core of a multi-core machine
+    pub fn with_hart_id(hart_id: u32) -> Self {
         Self {
             regs: [0; 32],
             pc: RESET_VECTOR,
-            sr: 0,
+            sr: RESET_SR,
             epc: 0,
             cause: 0,
+            esr: 0,
+            badvaddr: 0,
+            hart_id,
             halted: false,
+            mmu: Mmu::new(),
+            mpu: Mpu::new(),
+            icache: None,
+            dcache: None,
+            pipeline: None,
+            branch_predictor: None,
+            stall: 0,
+            stall_cycles: 0,
         }
     }
 
@@ -128,33 +197,105 @@ impl Cpu {
         (x as i16) as i32 as u32
     }
 
+    /// Translates a virtual address through the MMU and checks the physical address against
+    /// the MPU and the protected ranges of the bus. On failure the exception cause is returned.
+    fn translate<B: Bus>(&mut self, bus: &mut B, vaddr: u32, access: Access, user: bool) -> Result<u32, u32> {
+        let paddr = self.mmu.translate(bus, vaddr, access, user)?;
+        self.mpu.check(paddr, access, user)?;
+        if !bus.permits(paddr, access) {
+            return Err(access.access_fault_cause());
+        }
+        Ok(paddr)
+    }
+
+    fn add_stall(&mut self, cycles: u32) {
+        self.stall += cycles;
+        self.stall_cycles += cycles as u64;
+    }
+
+    /// Runs an access through the instruction or data cache and stalls for its latency.
+    /// Uncacheable addresses bypass the caches.
+    fn cache_access<B: Bus>(&mut self, bus: &B, paddr: u32, access: Access) {
+        if !bus.cacheable(paddr) {
+            return;
+        }
+        let cycles = match access {
+            Access::Fetch => self.icache.as_mut().map_or(0, |c| c.read(paddr)),
+            Access::Load => self.dcache.as_mut().map_or(0, |c| c.read(paddr)),
+            Access::Store => self.dcache.as_mut().map_or(0, |c| c.write(paddr)),
+        };
+        self.add_stall(cycles);
+    }
+
+    /// Carries out a cache maintenance operation, see `isa::cache_op`. Returns the cycles
+    /// taken; operations on a missing cache do nothing.
+    fn maintain_cache(&mut self, op: u32, paddr: u32) -> u32 {
+        let icache = self.icache.as_mut();
+        let dcache = self.dcache.as_mut();
+        match op {
+            isa::cache_op::ICACHE_INVALIDATE_ALL => icache.map_or(0, |c| {
+                c.invalidate_all();
+                0
+            }),
+            isa::cache_op::ICACHE_INVALIDATE_LINE => icache.map_or(0, |c| {
+                c.invalidate_line(paddr);
+                0
+            }),
+            isa::cache_op::DCACHE_INVALIDATE_ALL => dcache.map_or(0, |c| {
+                c.invalidate_all();
+                0
+            }),
+            isa::cache_op::DCACHE_INVALIDATE_LINE => dcache.map_or(0, |c| {
+                c.invalidate_line(paddr);
+                0
+            }),
+            isa::cache_op::DCACHE_CLEAN_ALL => dcache.map_or(0, |c| c.clean_all()),
+            isa::cache_op::DCACHE_CLEAN_LINE => dcache.map_or(0, |c| c.clean_line(paddr)),
+            isa::cache_op::DCACHE_FLUSH_ALL => dcache.map_or(0, |c| c.flush_all()),
+            isa::cache_op::DCACHE_FLUSH_LINE => dcache.map_or(0, |c| c.flush_line(paddr)),
+            _ => 0,
+        }
+    }
+
     pub fn step<B: Bus>(&mut self, bus: &mut B, irq: &IrqLines) -> Result<(), B::Error> {
         if self.halted {
             // CPU is halted; do nothing
             return Ok(());
         }
 
+        // The last instruction is still waiting for the memory system or the pipeline
+        if self.stall > 0 {
+            self.stall -= 1;
+            return Ok(());
+        }
+
         // Store next register states
         let mut next_regs = self.regs;
         let mut next_pc = self.pc;
         let mut next_sr = self.sr;
         let mut next_epc = self.epc;
         let mut next_cause = self.cause;
+        let mut next_esr = self.esr;
+        let mut next_badvaddr = self.badvaddr;
         let mut next_halted = self.halted;
 
         let mut take_exception = false;
         let mut exc_cause = 0;
         let mut exc_pc = self.pc;
 
-        // Fetch instruction
-        let raw = bus.read32(self.pc)?;
+        let user = self.sr & SR_U != 0;
 
-        // Decode instruction
-        let instr = Instruction::decode(raw);
-        // println!("[{:08X}] Instr: {:?} (raw: {:08X})", self.pc, instr, raw);
+        // The NMI is taken even while handling an exception. It then overwrites EPC, ESR and
+        // CAUSE, so the interrupted handler can't be resumed; ESR has SR_EI set in that case.
+        if irq.nmi {
+            take_exception = true;
+            exc_cause = isa::cause::WATCHDOG_NMI;
+            exc_pc = self.pc;
+        }
 
-        // Check IRQ lines for pending interrupts
-        if !take_exception {
+        // Check IRQ lines for pending interrupts, unless we are already handling an exception
+        // or interrupts are disabled
+        if !take_exception && self.sr & SR_EI == 0 && self.sr & SR_IE != 0 {
             if irq.timer1 {
                 take_exception = true;
                 exc_cause = isa::cause::TIMER1_IRQ;
@@ -163,20 +304,71 @@ impl Cpu {
                 take_exception = true;
                 exc_cause = isa::cause::TIMER2_IRQ;
                 exc_pc = self.pc;
-            } else if irq.uart {
+            } else if irq.uart != 0 {
+                // Lowest UART IRQ line wins
                 take_exception = true;
-                exc_cause = isa::cause::UART_IRQ;
+                exc_cause = isa::cause::UART_IRQ + irq.uart.trailing_zeros();
+                exc_pc = self.pc;
+            } else if irq.rtc {
+                take_exception = true;
+                exc_cause = isa::cause::RTC_IRQ;
+                exc_pc = self.pc;
+            } else if irq.vsync {
+                take_exception = true;
+                exc_cause = isa::cause::VSYNC_IRQ;
+                exc_pc = self.pc;
+            } else if irq.gpio {
+                take_exception = true;
+                exc_cause = isa::cause::GPIO_IRQ;
+                exc_pc = self.pc;
+            } else if irq.block {
+                take_exception = true;
+                exc_cause = isa::cause::BLOCK_IRQ;
+                exc_pc = self.pc;
+            } else if irq.dma {
+                take_exception = true;
+                exc_cause = isa::cause::DMA_IRQ;
+                exc_pc = self.pc;
+            } else if irq.keyboard {
+                take_exception = true;
+                exc_cause = isa::cause::KEYBOARD_IRQ;
+                exc_pc = self.pc;
+            } else if irq.audio {
+                take_exception = true;
+                exc_cause = isa::cause::AUDIO_IRQ;
+                exc_pc = self.pc;
+            } else if irq.net {
+                take_exception = true;
+                exc_cause = isa::cause::NET_IRQ;
+                exc_pc = self.pc;
+            } else if irq.ipi {
+                take_exception = true;
+                exc_cause = isa::cause::IPI_IRQ;
                 exc_pc = self.pc;
             }
         }
 
-        // Handle any exceptions
-        if take_exception {
-            next_epc = exc_pc;
-            next_cause = exc_cause;
-            next_pc = EXCEPTION_VECTOR;
-            next_sr = self.sr & !SR_IE; // Disable interrupts
-        } else {
+        // Fetch instruction
+        let mut instr = Instruction::nop();
+        if !take_exception {
+            match self.translate(bus, self.pc, Access::Fetch, user) {
+                Ok(paddr) => {
+                    self.cache_access(bus, paddr, Access::Fetch);
+                    let raw = bus.read32(paddr)?;
+
+                    // Decode instruction
+                    instr = Instruction::decode(raw);
+                    // println!("[{:08X}] Instr: {:?} (raw: {:08X})", self.pc, instr, raw);
+                }
+                Err(cause) => {
+                    take_exception = true;
+                    exc_cause = cause;
+                    next_badvaddr = self.pc;
+                }
+            }
+        }
+
+        if !take_exception {
             // println!("[{:08X}] Instr: {:?}", self.pc, instr);
             // Execute instruction
             match instr.opcode {
@@ -333,10 +525,20 @@ impl Cpu {
                     let imm = Self::sign_extend_16(instr.imm16);
                     let addr = rs_val.wrapping_add(imm);
 
-                    let value = bus.read32(addr)?;
-
-                    next_regs[instr.rd] = value;
-                    next_pc = next_pc.wrapping_add(4);
+                    match self.translate(bus, addr, Access::Load, user) {
+                        Ok(paddr) => {
+                            self.cache_access(bus, paddr, Access::Load);
+                            let value = bus.read32(paddr)?;
+
+                            next_regs[instr.rd] = value;
+                            next_pc = next_pc.wrapping_add(4);
+                        }
+                        Err(cause) => {
+                            take_exception = true;
+                            exc_cause = cause;
+                            next_badvaddr = addr;
+                        }
+                    }
                 }
                 isa::opcode::SW => {
                     // Mem[rs + imm16] = rd
@@ -344,9 +546,19 @@ impl Cpu {
                     let imm = Self::sign_extend_16(instr.imm16);
                     let addr = rs_val.wrapping_add(imm);
 
-                    let value = self.regs[instr.rd];
-                    bus.write32(addr, value)?;
-                    next_pc = next_pc.wrapping_add(4);
+                    match self.translate(bus, addr, Access::Store, user) {
+                        Ok(paddr) => {
+                            self.cache_access(bus, paddr, Access::Store);
+                            let value = self.regs[instr.rd];
+                            bus.write32(paddr, value)?;
+                            next_pc = next_pc.wrapping_add(4);
+                        }
+                        Err(cause) => {
+                            take_exception = true;
+                            exc_cause = cause;
+                            next_badvaddr = addr;
+                        }
+                    }
                 }
                 isa::opcode::LB => {
                     // rd = sign-extended Mem[rs + imm16]
@@ -354,9 +566,19 @@ impl Cpu {
                     let imm = Self::sign_extend_16(instr.imm16);
 
                     let addr = rs_val.wrapping_add(imm);
-                    let byte = bus.read8(addr)?;
-                    next_regs[instr.rd] = (byte as i8) as i32 as u32; // sign-extend
-                    next_pc = next_pc.wrapping_add(4);
+                    match self.translate(bus, addr, Access::Load, user) {
+                        Ok(paddr) => {
+                            self.cache_access(bus, paddr, Access::Load);
+                            let byte = bus.read8(paddr)?;
+                            next_regs[instr.rd] = (byte as i8) as i32 as u32; // sign-extend
+                            next_pc = next_pc.wrapping_add(4);
+                        }
+                        Err(cause) => {
+                            take_exception = true;
+                            exc_cause = cause;
+                            next_badvaddr = addr;
+                        }
+                    }
                 }
                 isa::opcode::SB => {
                     // Mem[rs + imm16] = least-significant byte of rd
@@ -364,10 +586,20 @@ impl Cpu {
                     let imm = Self::sign_extend_16(instr.imm16);
                     let addr = rs_val.wrapping_add(imm);
 
-                    let rd_val = self.regs[instr.rd];
-                    let byte = (rd_val & 0xFF) as u8;
-                    bus.write8(addr, byte)?;
-                    next_pc = next_pc.wrapping_add(4);
+                    match self.translate(bus, addr, Access::Store, user) {
+                        Ok(paddr) => {
+                            self.cache_access(bus, paddr, Access::Store);
+                            let rd_val = self.regs[instr.rd];
+                            let byte = (rd_val & 0xFF) as u8;
+                            bus.write8(paddr, byte)?;
+                            next_pc = next_pc.wrapping_add(4);
+                        }
+                        Err(cause) => {
+                            take_exception = true;
+                            exc_cause = cause;
+                            next_badvaddr = addr;
+                        }
+                    }
                 }
 
                 // -----------------------------
@@ -448,6 +680,86 @@ impl Cpu {
                     next_pc = rs_val;
                 }
 
+                // -----------------------------
+                // Special registers / exceptions (privileged)
+                isa::opcode::MFSR | isa::opcode::MTSR | isa::opcode::ERET | isa::opcode::CACHE if user => {
+                    take_exception = true;
+                    exc_cause = isa::cause::ILLEGAL_OP;
+                }
+                isa::opcode::MFSR => {
+                    // rd = special[imm16]
+                    next_regs[instr.rd] = match instr.imm16 {
+                        isa::sreg::SR => self.sr,
+                        isa::sreg::EPC => self.epc,
+                        isa::sreg::CAUSE => self.cause,
+                        isa::sreg::BADVADDR => self.badvaddr,
+                        isa::sreg::ESR => self.esr,
+                        isa::sreg::PTBR => self.mmu.ptbr(),
+                        isa::sreg::MMU_CTRL => self.mmu.ctrl(),
+                        isa::sreg::MPU_CTRL => self.mpu.ctrl(),
+                        isa::sreg::MPU_INDEX => self.mpu.index(),
+                        isa::sreg::MPU_BASE => self.mpu.base(),
+                        isa::sreg::MPU_SIZE => self.mpu.size(),
+                        isa::sreg::MPU_ATTR => self.mpu.attr(),
+                        isa::sreg::HARTID => self.hart_id,
+                        _ => 0,
+                    };
+                    next_pc = next_pc.wrapping_add(4);
+                }
+                isa::opcode::MTSR => {
+                    // special[imm16] = rd
+                    let rd_val = self.regs[instr.rd];
+                    match instr.imm16 {
+                        isa::sreg::SR => next_sr = rd_val,
+                        isa::sreg::EPC => next_epc = rd_val,
+                        isa::sreg::CAUSE => next_cause = rd_val,
+                        isa::sreg::BADVADDR => next_badvaddr = rd_val,
+                        isa::sreg::ESR => next_esr = rd_val,
+                        isa::sreg::PTBR => self.mmu.set_ptbr(rd_val),
+                        isa::sreg::MMU_CTRL => self.mmu.set_ctrl(rd_val),
+                        isa::sreg::TLB_FLUSH => self.mmu.flush(),
+                        isa::sreg::MPU_CTRL => self.mpu.set_ctrl(rd_val),
+                        isa::sreg::MPU_INDEX => self.mpu.set_index(rd_val),
+                        isa::sreg::MPU_BASE => self.mpu.set_base(rd_val),
+                        isa::sreg::MPU_SIZE => self.mpu.set_size(rd_val),
+                        isa::sreg::MPU_ATTR => self.mpu.set_attr(rd_val),
+                        _ => {}
+                    }
+                    next_pc = next_pc.wrapping_add(4);
+                }
+                isa::opcode::ERET => {
+                    // pc = epc, sr = esr
+                    next_pc = self.epc;
+                    next_sr = self.esr;
+                }
+                isa::opcode::CACHE => {
+                    // cache operation rd on the line holding Mem[rs + imm16]
+                    let op = instr.rd as u32;
+                    let rs_val = self.regs[instr.rs];
+                    let imm = Self::sign_extend_16(instr.imm16);
+                    let addr = rs_val.wrapping_add(imm);
+
+                    if op > isa::cache_op::DCACHE_FLUSH_LINE {
+                        take_exception = true;
+                        exc_cause = isa::cause::ILLEGAL_OP;
+                    } else {
+                        // Whole-cache operations ignore the address
+                        let paddr = if op & 1 == 0 { Ok(0) } else { self.translate(bus, addr, Access::Load, user) };
+                        match paddr {
+                            Ok(paddr) => {
+                                let cycles = self.maintain_cache(op, paddr);
+                                self.add_stall(cycles);
+                                next_pc = next_pc.wrapping_add(4);
+                            }
+                            Err(cause) => {
+                                take_exception = true;
+                                exc_cause = cause;
+                                next_badvaddr = addr;
+                            }
+                        }
+                    }
+                }
+
                 // -----------------------------
                 // System / Misc Operations
                 isa::opcode::NOP => {
@@ -457,13 +769,46 @@ impl Cpu {
                     next_halted = true;
                 }
                 _ => {
-                    next_epc = self.pc;
-                    next_cause = isa::cause::ILLEGAL_OP;
-                    next_pc = EXCEPTION_VECTOR;
+                    take_exception = true;
+                    exc_cause = isa::cause::ILLEGAL_OP;
                 }
             }
         }
 
+        // Handle any exceptions. The faulting instruction has no effect, the handler runs in
+        // kernel mode with interrupts disabled and ERET restores the saved status register.
+        if take_exception {
+            next_regs = self.regs;
+            next_epc = exc_pc;
+            next_cause = exc_cause;
+            next_esr = self.sr;
+            next_pc = EXCEPTION_VECTOR;
+            next_sr = (self.sr | SR_EI) & !(SR_IE | SR_U);
+        }
+
+        // Conditional branches that executed train the predictor
+        let mispredicted = match (self.branch_predictor.as_mut(), instr.opcode) {
+            (Some(predictor), isa::opcode::BEQ | isa::opcode::BNE | isa::opcode::BLT | isa::opcode::BGE)
+                if !take_exception =>
+            {
+                let taken = next_pc != self.pc.wrapping_add(4);
+                Some(predictor.resolve(self.pc, taken, next_pc))
+            }
+            _ => None,
+        };
+
+        if let Some(pipeline) = self.pipeline.as_mut() {
+            let retire = if take_exception {
+                Retire::Exception
+            } else if next_pc != self.pc.wrapping_add(4) {
+                Retire::Redirected
+            } else {
+                Retire::Sequential
+            };
+            let cycles = pipeline.retire(self.pc, &instr, retire, mispredicted);
+            self.add_stall(cycles);
+        }
+
         // Ensure R0 is always zero
         next_regs[0] = 0; // R0 is always zero
 
@@ -473,8 +818,42 @@ impl Cpu {
         self.sr = next_sr;
         self.epc = next_epc;
         self.cause = next_cause;
+        self.esr = next_esr;
+        self.badvaddr = next_badvaddr;
         self.halted = next_halted;
 
         Ok(())
     }
 }
+
+/// Architectural state only: the caches, the pipeline and the branch predictor restart cold
+impl Snapshot for Cpu {
+    fn save(&self, w: &mut StateWriter) {
+        for reg in self.regs {
+            w.u32(reg);
+        }
+        for value in [self.pc, self.sr, self.epc, self.cause, self.esr, self.badvaddr] {
+            w.u32(value);
+        }
+        w.bool(self.halted);
+        self.mmu.save(w);
+        self.mpu.save(w);
+    }
+
+    fn restore(&mut self, r: &mut StateReader) -> io::Result<()> {
+        for reg in self.regs.iter_mut() {
+            *reg = r.u32()?;
+        }
+        self.pc = r.u32()?;
+        self.sr = r.u32()?;
+        self.epc = r.u32()?;
+        self.cause = r.u32()?;
+        self.esr = r.u32()?;
+        self.badvaddr = r.u32()?;
+        self.halted = r.bool()?;
+        self.mmu.restore(r)?;
+        self.mpu.restore(r)?;
+        self.stall = 0;
+        Ok(())
+    }
+}
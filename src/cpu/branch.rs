@@ -0,0 +1,323 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::str::FromStr;
+
+/// What a predictor expects a conditional branch to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Prediction {
+    pub taken: bool,
+    /// Predicted target, None when the predictor doesn't know it (it is then taken from the
+    /// decoded instruction)
+    pub target: Option<u32>,
+}
+
+/// A branch prediction model. `predict` is asked before every conditional branch executes,
+/// `update` is told the outcome right after.
+pub trait BranchPredictor: Send {
+    fn name(&self) -> &str;
+    fn predict(&mut self, pc: u32) -> Prediction;
+    fn update(&mut self, pc: u32, taken: bool, target: u32);
+}
+
+/// Saturating 2-bit counter states, 0 is strongly not taken and >= WEAKLY_TAKEN predicts taken
+const WEAKLY_NOT_TAKEN: u8 = 1;
+const WEAKLY_TAKEN: u8 = 2;
+const STRONGLY_TAKEN: u8 = 3;
+
+fn train(counter: &mut u8, taken: bool) {
+    *counter = if taken {
+        (*counter + 1).min(STRONGLY_TAKEN)
+    } else {
+        counter.saturating_sub(1)
+    };
+}
+
+/// Word index of a branch, instructions are word aligned
+fn pc_index(pc: u32) -> u32 {
+    pc >> 2
+}
+
+/// Always predicts not taken, like a pipeline without a predictor
+#[derive(Default)]
+pub struct NotTaken;
+
+impl BranchPredictor for NotTaken {
+    fn name(&self) -> &str {
+        "static not-taken"
+    }
+
+    fn predict(&mut self, _pc: u32) -> Prediction {
+        Prediction { taken: false, target: None }
+    }
+
+    fn update(&mut self, _pc: u32, _taken: bool, _target: u32) {}
+}
+
+/// Table of 2-bit saturating counters indexed by the branch address
+pub struct TwoBit {
+    counters: Vec<u8>,
+}
+
+impl TwoBit {
+    /// `entries` is rounded up to a power of two
+    pub fn new(entries: usize) -> Self {
+        Self {
+            counters: vec![WEAKLY_NOT_TAKEN; entries.max(1).next_power_of_two()],
+        }
+    }
+
+    fn index(&self, pc: u32) -> usize {
+        pc_index(pc) as usize & (self.counters.len() - 1)
+    }
+}
+
+impl BranchPredictor for TwoBit {
+    fn name(&self) -> &str {
+        "2-bit counters"
+    }
+
+    fn predict(&mut self, pc: u32) -> Prediction {
+        let taken = self.counters[self.index(pc)] >= WEAKLY_TAKEN;
+        Prediction { taken, target: None }
+    }
+
+    fn update(&mut self, pc: u32, taken: bool, _target: u32) {
+        let index = self.index(pc);
+        train(&mut self.counters[index], taken);
+    }
+}
+
+/// 2-bit counters indexed by the branch address XOR the global branch history
+pub struct Gshare {
+    counters: Vec<u8>,
+    /// Outcomes of the last branches, the most recent in bit 0
+    history: u32,
+    history_bits: u32,
+}
+
+impl Gshare {
+    /// `entries` is rounded up to a power of two, at most 32 history bits are kept
+    pub fn new(entries: usize, history_bits: u32) -> Self {
+        Self {
+            counters: vec![WEAKLY_NOT_TAKEN; entries.max(1).next_power_of_two()],
+            history: 0,
+            history_bits: history_bits.min(32),
+        }
+    }
+
+    fn index(&self, pc: u32) -> usize {
+        (pc_index(pc) ^ self.history) as usize & (self.counters.len() - 1)
+    }
+}
+
+impl BranchPredictor for Gshare {
+    fn name(&self) -> &str {
+        "gshare"
+    }
+
+    fn predict(&mut self, pc: u32) -> Prediction {
+        let taken = self.counters[self.index(pc)] >= WEAKLY_TAKEN;
+        Prediction { taken, target: None }
+    }
+
+    fn update(&mut self, pc: u32, taken: bool, _target: u32) {
+        let index = self.index(pc);
+        train(&mut self.counters[index], taken);
+
+        let mask = 1u32.checked_shl(self.history_bits).map_or(u32::MAX, |bit| bit - 1);
+        self.history = ((self.history << 1) | taken as u32) & mask;
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct BtbEntry {
+    valid: bool,
+    pc: u32,
+    target: u32,
+    counter: u8,
+}
+
+/// Direct mapped branch target buffer with a 2-bit counter per entry. Branches that miss in
+/// the buffer are predicted not taken; taken branches are allocated.
+pub struct Btb {
+    entries: Vec<BtbEntry>,
+}
+
+impl Btb {
+    /// `entries` is rounded up to a power of two
+    pub fn new(entries: usize) -> Self {
+        Self {
+            entries: vec![BtbEntry::default(); entries.max(1).next_power_of_two()],
+        }
+    }
+
+    fn index(&self, pc: u32) -> usize {
+        pc_index(pc) as usize & (self.entries.len() - 1)
+    }
+}
+
+impl BranchPredictor for Btb {
+    fn name(&self) -> &str {
+        "branch target buffer"
+    }
+
+    fn predict(&mut self, pc: u32) -> Prediction {
+        let entry = self.entries[self.index(pc)];
+        if entry.valid && entry.pc == pc {
+            Prediction {
+                taken: entry.counter >= WEAKLY_TAKEN,
+                target: Some(entry.target),
+            }
+        } else {
+            Prediction { taken: false, target: None }
+        }
+    }
+
+    fn update(&mut self, pc: u32, taken: bool, target: u32) {
+        let index = self.index(pc);
+        let entry = &mut self.entries[index];
+
+        if entry.valid && entry.pc == pc {
+            train(&mut entry.counter, taken);
+            if taken {
+                entry.target = target;
+            }
+        } else if taken {
+            *entry = BtbEntry {
+                valid: true,
+                pc,
+                target,
+                counter: WEAKLY_TAKEN,
+            };
+        }
+    }
+}
+
+/// The built-in predictors, with their table sizes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PredictorKind {
+    NotTaken,
+    TwoBit { entries: usize },
+    Gshare { entries: usize, history_bits: u32 },
+    Btb { entries: usize },
+}
+
+impl PredictorKind {
+    pub fn build(self) -> Box<dyn BranchPredictor> {
+        match self {
+            PredictorKind::NotTaken => Box::new(NotTaken),
+            PredictorKind::TwoBit { entries } => Box::new(TwoBit::new(entries)),
+            PredictorKind::Gshare { entries, history_bits } => Box::new(Gshare::new(entries, history_bits)),
+            PredictorKind::Btb { entries } => Box::new(Btb::new(entries)),
+        }
+    }
+}
+
+impl FromStr for PredictorKind {
+    type Err = String;
+
+    /// Parses `not-taken`, `2bit`, `gshare` or `btb`, with default table sizes
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "not-taken" => Ok(PredictorKind::NotTaken),
+            "2bit" => Ok(PredictorKind::TwoBit { entries: 512 }),
+            "gshare" => Ok(PredictorKind::Gshare { entries: 1024, history_bits: 10 }),
+            "btb" => Ok(PredictorKind::Btb { entries: 64 }),
+            _ => Err(format!("unknown branch predictor '{s}', expected not-taken, 2bit, gshare or btb")),
+        }
+    }
+}
+
+/// Outcomes of a single branch
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BranchProfile {
+    pub count: u64,
+    pub taken: u64,
+    pub mispredicted: u64,
+}
+
+impl BranchProfile {
+    pub fn misprediction_rate(&self) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        self.mispredicted as f64 / self.count as f64
+    }
+}
+
+/// A predictor hooked to a core, with the statistics of its predictions
+pub struct BranchModel {
+    predictor: Box<dyn BranchPredictor>,
+    total: BranchProfile,
+    profile: BTreeMap<u32, BranchProfile>,
+}
+
+impl BranchModel {
+    pub fn new(predictor: Box<dyn BranchPredictor>) -> Self {
+        Self {
+            predictor,
+            total: BranchProfile::default(),
+            profile: BTreeMap::new(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        self.predictor.name()
+    }
+    /// Outcomes of all branches together
+    pub fn total(&self) -> &BranchProfile {
+        &self.total
+    }
+    /// Outcomes per branch address
+    pub fn profile(&self) -> &BTreeMap<u32, BranchProfile> {
+        &self.profile
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.total = BranchProfile::default();
+        self.profile.clear();
+    }
+
+    /// Predicts the branch at `pc`, trains the predictor with the outcome and counts it.
+    /// Returns true when the prediction was wrong.
+    pub fn resolve(&mut self, pc: u32, taken: bool, target: u32) -> bool {
+        let prediction = self.predictor.predict(pc);
+        let mispredicted = prediction.taken != taken || (taken && prediction.target.is_some_and(|t| t != target));
+        self.predictor.update(pc, taken, target);
+
+        for p in [&mut self.total, self.profile.entry(pc).or_default()] {
+            p.count += 1;
+            p.taken += taken as u64;
+            p.mispredicted += mispredicted as u64;
+        }
+        mispredicted
+    }
+
+    /// Misprediction rates, overall and per branch
+    pub fn report(&self) -> String {
+        let t = &self.total;
+        let mut out = String::new();
+
+        let _ = writeln!(
+            out,
+            "Branch predictor ({}): {} branches, {} mispredicted ({:.1}%)",
+            self.name(),
+            t.count,
+            t.mispredicted,
+            t.misprediction_rate() * 100.0
+        );
+        let _ = writeln!(out, "  PC            COUNT      TAKEN MISPREDICTED");
+        for (pc, p) in &self.profile {
+            let _ = writeln!(
+                out,
+                "  {:08X} {:>10} {:>10} {:>12} ({:.1}%)",
+                pc,
+                p.count,
+                p.taken,
+                p.mispredicted,
+                p.misprediction_rate() * 100.0
+            );
+        }
+        out
+    }
+}
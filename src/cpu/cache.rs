@@ -0,0 +1,299 @@
+/// What happens to stores
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WritePolicy {
+    /// Stores allocate a line and mark it dirty, memory is updated when the line is evicted
+    WriteBack,
+    /// Stores go to memory right away, a store miss doesn't allocate a line
+    WriteThrough,
+}
+
+/// Geometry and timing of a cache
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheConfig {
+    /// Capacity in bytes
+    pub size: u32,
+    /// Lines per set, 1 = direct mapped
+    pub ways: u32,
+    /// Line size in bytes
+    pub line_size: u32,
+    pub write_policy: WritePolicy,
+    /// Extra cycles taken by a hit
+    pub hit_latency: u32,
+    /// Extra cycles taken to fill a line from memory
+    pub miss_penalty: u32,
+    /// Extra cycles taken to write a dirty line (write-back) or a store (write-through)
+    /// to memory
+    pub write_penalty: u32,
+}
+
+impl Default for CacheConfig {
+    /// 4 KiB, 2 way set associative, 16 byte lines, write-back
+    fn default() -> Self {
+        Self {
+            size: 4096,
+            ways: 2,
+            line_size: 16,
+            write_policy: WritePolicy::WriteBack,
+            hit_latency: 0,
+            miss_penalty: 10,
+            write_penalty: 10,
+        }
+    }
+}
+
+impl CacheConfig {
+    /// Checks that the geometry describes a whole number of sets. Sizes must be powers of two.
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.size.is_power_of_two() || !self.ways.is_power_of_two() || !self.line_size.is_power_of_two() {
+            return Err("cache size, ways and line size must be powers of two".to_string());
+        }
+        if self.line_size < 4 {
+            return Err("cache lines must hold at least one word".to_string());
+        }
+        if self.size < self.ways * self.line_size {
+            return Err(format!(
+                "a {} byte cache can't hold {} ways of {} byte lines",
+                self.size, self.ways, self.line_size
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn sets(&self) -> u32 {
+        self.size / (self.ways * self.line_size)
+    }
+}
+
+/// Access counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub reads: u64,
+    pub writes: u64,
+    pub read_misses: u64,
+    pub write_misses: u64,
+    /// Dirty lines written back to memory
+    pub writebacks: u64,
+    /// Extra cycles spent in this cache
+    pub cycles: u64,
+}
+
+impl CacheStats {
+    pub fn accesses(&self) -> u64 {
+        self.reads + self.writes
+    }
+    pub fn misses(&self) -> u64 {
+        self.read_misses + self.write_misses
+    }
+    pub fn hits(&self) -> u64 {
+        self.accesses() - self.misses()
+    }
+    /// Hits per access, 0 when the cache wasn't used
+    pub fn hit_rate(&self) -> f64 {
+        if self.accesses() == 0 {
+            return 0.0;
+        }
+        self.hits() as f64 / self.accesses() as f64
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Line {
+    tag: u32,
+    valid: bool,
+    dirty: bool,
+    /// Access stamp for LRU replacement
+    last_used: u64,
+}
+
+/// Set associative cache model with LRU replacement.
+///
+/// Only tags are modelled: data always comes from the bus, so the cache never returns stale
+/// values and maintenance operations only change the timing and the counters. Each access
+/// returns the extra cycles it takes.
+pub struct Cache {
+    config: CacheConfig,
+    /// `ways` lines per set, set after set
+    lines: Vec<Line>,
+    offset_bits: u32,
+    index_bits: u32,
+    /// Advances on every access, for LRU
+    clock: u64,
+    stats: CacheStats,
+}
+
+impl Cache {
+    pub fn new(config: CacheConfig) -> Result<Self, String> {
+        config.validate()?;
+
+        Ok(Self {
+            config,
+            lines: vec![Line::default(); (config.sets() * config.ways) as usize],
+            offset_bits: config.line_size.trailing_zeros(),
+            index_bits: config.sets().trailing_zeros(),
+            clock: 0,
+            stats: CacheStats::default(),
+        })
+    }
+
+    pub fn config(&self) -> &CacheConfig {
+        &self.config
+    }
+    pub fn stats(&self) -> &CacheStats {
+        &self.stats
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = CacheStats::default();
+    }
+
+    fn index(&self, addr: u32) -> usize {
+        ((addr >> self.offset_bits) & ((1 << self.index_bits) - 1)) as usize
+    }
+
+    fn tag(&self, addr: u32) -> u32 {
+        addr.checked_shr(self.offset_bits + self.index_bits).unwrap_or(0)
+    }
+
+    fn set_mut(&mut self, addr: u32) -> &mut [Line] {
+        let ways = self.config.ways as usize;
+        let start = self.index(addr) * ways;
+        &mut self.lines[start..start + ways]
+    }
+
+    /// Way holding `addr`, if it is cached
+    fn find(&mut self, addr: u32) -> Option<&mut Line> {
+        let tag = self.tag(addr);
+        self.set_mut(addr).iter_mut().find(|line| line.valid && line.tag == tag)
+    }
+
+    fn charge(&mut self, cycles: u32) -> u32 {
+        self.stats.cycles += cycles as u64;
+        cycles
+    }
+
+    /// Brings the line holding `addr` in, evicting the least recently used way. Returns the
+    /// cycles taken.
+    fn fill(&mut self, addr: u32, dirty: bool) -> u32 {
+        let tag = self.tag(addr);
+        let clock = self.clock;
+        let write_penalty = self.config.write_penalty;
+        let mut cycles = self.config.miss_penalty;
+
+        let set = self.set_mut(addr);
+        let victim = set
+            .iter_mut()
+            .min_by_key(|line| if line.valid { line.last_used } else { 0 })
+            .expect("cache sets have at least one way");
+        let writeback = victim.valid && victim.dirty;
+        *victim = Line {
+            tag,
+            valid: true,
+            dirty,
+            last_used: clock,
+        };
+
+        if writeback {
+            self.stats.writebacks += 1;
+            cycles += write_penalty;
+        }
+        cycles
+    }
+
+    /// Models a load or an instruction fetch
+    pub fn read(&mut self, addr: u32) -> u32 {
+        self.clock += 1;
+        self.stats.reads += 1;
+        let clock = self.clock;
+
+        let cycles = match self.find(addr) {
+            Some(line) => {
+                line.last_used = clock;
+                self.config.hit_latency
+            }
+            None => {
+                self.stats.read_misses += 1;
+                self.fill(addr, false)
+            }
+        };
+        self.charge(cycles)
+    }
+
+    /// Models a store
+    pub fn write(&mut self, addr: u32) -> u32 {
+        self.clock += 1;
+        self.stats.writes += 1;
+        let clock = self.clock;
+        let policy = self.config.write_policy;
+
+        let cycles = match (self.find(addr), policy) {
+            (Some(line), WritePolicy::WriteBack) => {
+                line.last_used = clock;
+                line.dirty = true;
+                self.config.hit_latency
+            }
+            (Some(line), WritePolicy::WriteThrough) => {
+                line.last_used = clock;
+                self.config.hit_latency + self.config.write_penalty
+            }
+            (None, WritePolicy::WriteBack) => {
+                self.stats.write_misses += 1;
+                self.fill(addr, true)
+            }
+            (None, WritePolicy::WriteThrough) => {
+                self.stats.write_misses += 1;
+                self.config.write_penalty
+            }
+        };
+        self.charge(cycles)
+    }
+
+    /// Drops every line, dirty ones included
+    pub fn invalidate_all(&mut self) {
+        self.lines.fill(Line::default());
+    }
+
+    /// Drops the line holding `addr`, even when it is dirty
+    pub fn invalidate_line(&mut self, addr: u32) {
+        if let Some(line) = self.find(addr) {
+            *line = Line::default();
+        }
+    }
+
+    /// Writes every dirty line back to memory. Returns the cycles taken.
+    pub fn clean_all(&mut self) -> u32 {
+        let mut dirty = 0;
+        for line in self.lines.iter_mut().filter(|line| line.valid && line.dirty) {
+            line.dirty = false;
+            dirty += 1;
+        }
+        self.stats.writebacks += dirty as u64;
+        self.charge(dirty * self.config.write_penalty)
+    }
+
+    /// Writes the line holding `addr` back to memory if it is dirty. Returns the cycles taken.
+    pub fn clean_line(&mut self, addr: u32) -> u32 {
+        let Some(line) = self.find(addr) else {
+            return 0;
+        };
+        if !line.dirty {
+            return 0;
+        }
+        line.dirty = false;
+        self.stats.writebacks += 1;
+        self.charge(self.config.write_penalty)
+    }
+
+    /// Writes back and drops every line. Returns the cycles taken.
+    pub fn flush_all(&mut self) -> u32 {
+        let cycles = self.clean_all();
+        self.invalidate_all();
+        cycles
+    }
+
+    /// Writes back and drops the line holding `addr`. Returns the cycles taken.
+    pub fn flush_line(&mut self, addr: u32) -> u32 {
+        let cycles = self.clean_line(addr);
+        self.invalidate_line(addr);
+        cycles
+    }
+}
@@ -11,12 +11,75 @@ pub mod cause {
     pub const BREAKPOINT: u32 = 0x03;
     /// System call invoked
     pub const SYSTEM_CALL: u32 = 0x04;
+    /// Page fault on instruction fetch (faulting address in BADVADDR)
+    pub const PAGE_FAULT_FETCH: u32 = 0x05;
+    /// Page fault on load (faulting address in BADVADDR)
+    pub const PAGE_FAULT_LOAD: u32 = 0x06;
+    /// Page fault on store (faulting address in BADVADDR)
+    pub const PAGE_FAULT_STORE: u32 = 0x07;
+    /// Protection violation (MPU or protected segment) on instruction fetch (faulting address in BADVADDR)
+    pub const ACCESS_FAULT_FETCH: u32 = 0x08;
+    /// Protection violation (MPU or protected segment) on load (faulting address in BADVADDR)
+    pub const ACCESS_FAULT_LOAD: u32 = 0x09;
+    /// Protection violation (MPU or protected segment) on store (faulting address in BADVADDR)
+    pub const ACCESS_FAULT_STORE: u32 = 0x0A;
 
     /// Timer interrupt
     pub const TIMER1_IRQ: u32 = 0x100;
     pub const TIMER2_IRQ: u32 = 0x101;
-    /// UART interrupt
+    /// UART interrupt, UART IRQ line n raises UART_IRQ + n
     pub const UART_IRQ: u32 = 0x102;
+    /// RTC alarm interrupt (follows the 8 UART IRQ lines)
+    pub const RTC_IRQ: u32 = 0x10A;
+    /// Framebuffer vsync interrupt
+    pub const VSYNC_IRQ: u32 = 0x10B;
+    /// GPIO pin interrupt
+    pub const GPIO_IRQ: u32 = 0x10C;
+    /// Block device command completion interrupt
+    pub const BLOCK_IRQ: u32 = 0x10D;
+    /// DMA transfer completion interrupt (any channel)
+    pub const DMA_IRQ: u32 = 0x10E;
+    /// Watchdog expiry, non-maskable
+    pub const WATCHDOG_NMI: u32 = 0x10F;
+    /// Keyboard scan code available
+    pub const KEYBOARD_IRQ: u32 = 0x110;
+    /// Audio FIFO at most half full
+    pub const AUDIO_IRQ: u32 = 0x111;
+    /// Network frame received or sent
+    pub const NET_IRQ: u32 = 0x112;
+    /// Inter-processor interrupt
+    pub const IPI_IRQ: u32 = 0x113;
+}
+
+// Special register numbers, used by MFSR and MTSR
+pub mod sreg {
+    pub const SR: u16 = 0; // Status register
+    pub const EPC: u16 = 1; // Exception program counter
+    pub const CAUSE: u16 = 2; // Cause of the last exception
+    pub const BADVADDR: u16 = 3; // Faulting virtual address of the last page fault
+    pub const ESR: u16 = 4; // SR at the time of the last exception, restored by ERET
+    pub const PTBR: u16 = 5; // Page table base register
+    pub const MMU_CTRL: u16 = 6; // MMU control
+    pub const TLB_FLUSH: u16 = 7; // Write: invalidate the whole TLB
+    pub const MPU_CTRL: u16 = 8; // MPU control
+    pub const MPU_INDEX: u16 = 9; // MPU region selected by MPU_BASE, MPU_SIZE and MPU_ATTR
+    pub const MPU_BASE: u16 = 10; // Base address of the selected MPU region
+    pub const MPU_SIZE: u16 = 11; // Size in bytes of the selected MPU region
+    pub const MPU_ATTR: u16 = 12; // Attributes of the selected MPU region
+    pub const HARTID: u16 = 13; // Read-only: number of the core, 0 is the boot core
+}
+
+// Cache maintenance operations, used by CACHE. Odd operations work on the line holding the
+// address, even ones on the whole cache.
+pub mod cache_op {
+    pub const ICACHE_INVALIDATE_ALL: u32 = 0;
+    pub const ICACHE_INVALIDATE_LINE: u32 = 1;
+    pub const DCACHE_INVALIDATE_ALL: u32 = 2; // Dirty lines are dropped
+    pub const DCACHE_INVALIDATE_LINE: u32 = 3;
+    pub const DCACHE_CLEAN_ALL: u32 = 4; // Write dirty lines back, keep them cached
+    pub const DCACHE_CLEAN_LINE: u32 = 5;
+    pub const DCACHE_FLUSH_ALL: u32 = 6; // Clean, then invalidate
+    pub const DCACHE_FLUSH_LINE: u32 = 7;
 }
 
 pub mod opcode {
@@ -59,6 +122,12 @@ pub mod opcode {
     pub const JR: u8 = 0x2A; // Jump register
     pub const JALR: u8 = 0x2B; // Jump and link register
 
+    // Special registers / exceptions (privileged)
+    pub const MFSR: u8 = 0x30; // Move from special register
+    pub const MTSR: u8 = 0x31; // Move to special register
+    pub const ERET: u8 = 0x32; // Return from exception
+    pub const CACHE: u8 = 0x33; // Cache maintenance, operation in rd, address in rs + imm16
+
     // System / misc
     pub const NOP: u8 = 0x3E;
     pub const HALT: u8 = 0x3F;
@@ -95,6 +164,10 @@ pub fn op_str(opcode: u8) -> &'static str {
         opcode::JAL => "JAL",
         opcode::JR => "JR",
         opcode::JALR => "JALR",
+        opcode::MFSR => "MFSR",
+        opcode::MTSR => "MTSR",
+        opcode::ERET => "ERET",
+        opcode::CACHE => "CACHE",
         opcode::NOP => "NOP",
         opcode::HALT => "HALT",
         _ => "UNKNOWN",
@@ -0,0 +1,206 @@
+use crate::bus::Bus;
+use crate::cpu::isa;
+use crate::machine::snapshot::{Snapshot, StateReader, StateWriter};
+use std::io;
+
+// MMU control register
+pub const MMU_ENABLED: u32 = 0x1; // 0 = physical addressing, 1 = translate through page tables
+
+// Page table entry bits. Both levels use the same layout: bits 31-12 hold the physical page
+// number, the low bits hold the flags. First level entries only need VALID and point to a
+// second level table.
+pub const PTE_VALID: u32 = 1 << 0;
+pub const PTE_READ: u32 = 1 << 1;
+pub const PTE_WRITE: u32 = 1 << 2;
+pub const PTE_EXEC: u32 = 1 << 3;
+pub const PTE_USER: u32 = 1 << 4;
+
+pub const PAGE_SHIFT: u32 = 12;
+pub const PAGE_SIZE: u32 = 1 << PAGE_SHIFT;
+const PAGE_MASK: u32 = PAGE_SIZE - 1;
+const PPN_MASK: u32 = !PAGE_MASK;
+
+pub const TLB_ENTRIES: usize = 16;
+
+/// Kind of memory access being translated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Fetch,
+    Load,
+    Store,
+}
+
+impl Access {
+    fn fault_cause(self) -> u32 {
+        match self {
+            Access::Fetch => isa::cause::PAGE_FAULT_FETCH,
+            Access::Load => isa::cause::PAGE_FAULT_LOAD,
+            Access::Store => isa::cause::PAGE_FAULT_STORE,
+        }
+    }
+
+    /// Cause raised when the access violates memory protection (MPU or protected ranges)
+    pub fn access_fault_cause(self) -> u32 {
+        match self {
+            Access::Fetch => isa::cause::ACCESS_FAULT_FETCH,
+            Access::Load => isa::cause::ACCESS_FAULT_LOAD,
+            Access::Store => isa::cause::ACCESS_FAULT_STORE,
+        }
+    }
+
+    fn required_flag(self) -> u32 {
+        match self {
+            Access::Fetch => PTE_EXEC,
+            Access::Load => PTE_READ,
+            Access::Store => PTE_WRITE,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct TlbEntry {
+    valid: bool,
+    /// Virtual page number
+    vpn: u32,
+    /// Leaf page table entry (physical page number and flags)
+    pte: u32,
+}
+
+/// Memory management unit with a two level page table (10 + 10 bit index, 4 KiB pages),
+/// walked in hardware and cached in a small fully associative TLB.
+pub struct Mmu {
+    /// Control register
+    ctrl: u32,
+    /// Physical address of the first level page table (4 KiB aligned)
+    ptbr: u32,
+    /// Translation cache
+    tlb: [TlbEntry; TLB_ENTRIES],
+    /// Next TLB entry to replace (round-robin)
+    next_victim: usize,
+    /// TLB hits so far
+    hits: u64,
+    /// TLB misses (page walks) so far
+    misses: u64,
+}
+
+impl Default for Mmu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Mmu {
+    pub fn new() -> Self {
+        Self {
+            ctrl: 0,
+            ptbr: 0,
+            tlb: [TlbEntry::default(); TLB_ENTRIES],
+            next_victim: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    pub fn ctrl(&self) -> u32 {
+        self.ctrl
+    }
+    pub fn ptbr(&self) -> u32 {
+        self.ptbr
+    }
+    pub fn enabled(&self) -> bool {
+        self.ctrl & MMU_ENABLED != 0
+    }
+    pub fn tlb_hits(&self) -> u64 {
+        self.hits
+    }
+    pub fn tlb_misses(&self) -> u64 {
+        self.misses
+    }
+
+    pub fn set_ctrl(&mut self, ctrl: u32) {
+        self.ctrl = ctrl;
+        self.flush();
+    }
+
+    pub fn set_ptbr(&mut self, ptbr: u32) {
+        self.ptbr = ptbr & PPN_MASK;
+        self.flush();
+    }
+
+    /// Invalidates all TLB entries
+    pub fn flush(&mut self) {
+        for entry in self.tlb.iter_mut() {
+            entry.valid = false;
+        }
+    }
+
+    /// Translates a virtual address. On failure the page fault cause is returned; bus errors
+    /// during the page walk are reported as page faults as well.
+    pub fn translate<B: Bus>(&mut self, bus: &mut B, vaddr: u32, access: Access, user: bool) -> Result<u32, u32> {
+        if !self.enabled() {
+            return Ok(vaddr);
+        }
+
+        let vpn = vaddr >> PAGE_SHIFT;
+        let pte = match self.lookup(vpn) {
+            Some(pte) => {
+                self.hits += 1;
+                pte
+            }
+            None => {
+                self.misses += 1;
+                let pte = self.walk(bus, vaddr).ok_or(access.fault_cause())?;
+                self.insert(vpn, pte);
+                pte
+            }
+        };
+
+        if pte & access.required_flag() == 0 || (user && pte & PTE_USER == 0) {
+            return Err(access.fault_cause());
+        }
+
+        Ok((pte & PPN_MASK) | (vaddr & PAGE_MASK))
+    }
+
+    fn lookup(&self, vpn: u32) -> Option<u32> {
+        self.tlb.iter().find(|e| e.valid && e.vpn == vpn).map(|e| e.pte)
+    }
+
+    fn insert(&mut self, vpn: u32, pte: u32) {
+        self.tlb[self.next_victim] = TlbEntry { valid: true, vpn, pte };
+        self.next_victim = (self.next_victim + 1) % TLB_ENTRIES;
+    }
+
+    /// Walks the page tables and returns the leaf entry, if the mapping is valid
+    fn walk<B: Bus>(&self, bus: &mut B, vaddr: u32) -> Option<u32> {
+        let l1_index = vaddr >> 22;
+        let l2_index = (vaddr >> PAGE_SHIFT) & 0x3FF;
+
+        let l1 = bus.read32(self.ptbr.wrapping_add(l1_index * 4)).ok()?;
+        if l1 & PTE_VALID == 0 {
+            return None;
+        }
+
+        let l2 = bus.read32((l1 & PPN_MASK).wrapping_add(l2_index * 4)).ok()?;
+        if l2 & PTE_VALID == 0 {
+            return None;
+        }
+
+        Some(l2)
+    }
+}
+
+/// The TLB isn't saved, it refills from the page tables
+impl Snapshot for Mmu {
+    fn save(&self, w: &mut StateWriter) {
+        w.u32(self.ctrl);
+        w.u32(self.ptbr);
+    }
+
+    fn restore(&mut self, r: &mut StateReader) -> io::Result<()> {
+        self.ctrl = r.u32()?;
+        self.ptbr = r.u32()?;
+        self.flush();
+        Ok(())
+    }
+}
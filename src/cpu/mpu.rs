@@ -0,0 +1,168 @@
+use crate::cpu::mmu::Access;
+use crate::machine::snapshot::{Snapshot, StateReader, StateWriter, invalid};
+use std::io;
+
+// MPU control register
+pub const MPU_ENABLED: u32 = 0x1; // 0 = no checks, 1 = check every access against the regions
+pub const PRIV_DEFAULT: u32 = 0x2; // 0 = kernel faults outside all regions, 1 = kernel may access it
+
+// Region attribute bits, laid out like the page table entry flags
+pub const REGION_ENABLED: u32 = 1 << 0;
+pub const REGION_READ: u32 = 1 << 1;
+pub const REGION_WRITE: u32 = 1 << 2;
+pub const REGION_EXEC: u32 = 1 << 3;
+pub const REGION_USER: u32 = 1 << 4;
+
+pub const REGION_COUNT: usize = 8;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MpuRegion {
+    /// First address of the region
+    pub base: u32,
+    /// Size of the region in bytes
+    pub size: u32,
+    /// Attribute bits
+    pub attr: u32,
+}
+
+impl MpuRegion {
+    fn contains(&self, addr: u32) -> bool {
+        self.attr & REGION_ENABLED != 0
+            && addr >= self.base
+            && (addr as u64) < self.base as u64 + self.size as u64
+    }
+}
+
+/// Memory protection unit for firmware that runs without paging. Checks physical addresses
+/// against a small set of programmable regions. When regions overlap the highest numbered
+/// one wins, so a small guard region can be placed over a larger one.
+pub struct Mpu {
+    /// Control register
+    ctrl: u32,
+    /// Region selected for the BASE, SIZE and ATTR registers
+    index: u32,
+    regions: [MpuRegion; REGION_COUNT],
+    /// Number of violations so far
+    faults: u64,
+}
+
+impl Default for Mpu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Mpu {
+    pub fn new() -> Self {
+        Self {
+            ctrl: 0,
+            index: 0,
+            regions: [MpuRegion::default(); REGION_COUNT],
+            faults: 0,
+        }
+    }
+
+    pub fn ctrl(&self) -> u32 {
+        self.ctrl
+    }
+    pub fn enabled(&self) -> bool {
+        self.ctrl & MPU_ENABLED != 0
+    }
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+    pub fn regions(&self) -> &[MpuRegion; REGION_COUNT] {
+        &self.regions
+    }
+    pub fn faults(&self) -> u64 {
+        self.faults
+    }
+
+    /// Base of the selected region
+    pub fn base(&self) -> u32 {
+        self.regions[self.index as usize].base
+    }
+    /// Size of the selected region
+    pub fn size(&self) -> u32 {
+        self.regions[self.index as usize].size
+    }
+    /// Attributes of the selected region
+    pub fn attr(&self) -> u32 {
+        self.regions[self.index as usize].attr
+    }
+
+    pub fn set_ctrl(&mut self, ctrl: u32) {
+        self.ctrl = ctrl;
+    }
+    pub fn set_index(&mut self, index: u32) {
+        self.index = index % REGION_COUNT as u32;
+    }
+    pub fn set_base(&mut self, base: u32) {
+        self.regions[self.index as usize].base = base;
+    }
+    pub fn set_size(&mut self, size: u32) {
+        self.regions[self.index as usize].size = size;
+    }
+    pub fn set_attr(&mut self, attr: u32) {
+        self.regions[self.index as usize].attr = attr;
+    }
+
+    /// Sets up a region directly, e.g. from the host before the program starts
+    pub fn set_region(&mut self, index: usize, region: MpuRegion) {
+        self.regions[index] = region;
+    }
+
+    /// Checks an access. On a violation the fault cause is returned.
+    pub fn check(&mut self, addr: u32, access: Access, user: bool) -> Result<(), u32> {
+        if !self.enabled() {
+            return Ok(());
+        }
+
+        let allowed = match self.regions.iter().rev().find(|r| r.contains(addr)) {
+            Some(region) => {
+                let flag = match access {
+                    Access::Fetch => REGION_EXEC,
+                    Access::Load => REGION_READ,
+                    Access::Store => REGION_WRITE,
+                };
+                region.attr & flag != 0 && (!user || region.attr & REGION_USER != 0)
+            }
+            None => !user && self.ctrl & PRIV_DEFAULT != 0,
+        };
+
+        if allowed {
+            return Ok(());
+        }
+
+        println!("[mpu] {:?} violation at 0x{:08X} (user={})", access, addr, user);
+        self.faults += 1;
+
+        Err(access.access_fault_cause())
+    }
+}
+
+impl Snapshot for Mpu {
+    fn save(&self, w: &mut StateWriter) {
+        w.u32(self.ctrl);
+        w.u32(self.index);
+        for region in &self.regions {
+            w.u32(region.base);
+            w.u32(region.size);
+            w.u32(region.attr);
+        }
+    }
+
+    fn restore(&mut self, r: &mut StateReader) -> io::Result<()> {
+        self.ctrl = r.u32()?;
+        self.index = r.u32()?;
+        if self.index as usize >= REGION_COUNT {
+            return Err(invalid(format!("invalid MPU region index {} in snapshot", self.index)));
+        }
+        for region in self.regions.iter_mut() {
+            region.base = r.u32()?;
+            region.size = r.u32()?;
+            region.attr = r.u32()?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,287 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::cpu::isa::{self, op_str};
+use crate::cpu::{Instruction, LINK_REGISTER};
+
+/// Shape of the modelled pipeline. The stages are IF, ID, EX, MEM and WB; conditional
+/// branches and register jumps resolve in EX, direct jumps in ID.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PipelineConfig {
+    /// Forward results from EX and MEM. Without forwarding a value can be read in ID once
+    /// the producer is in WB.
+    pub forwarding: bool,
+    /// Instructions flushed by a mispredicted branch or a register jump
+    pub branch_penalty: u32,
+    /// Instructions flushed by a direct jump (J, JAL)
+    pub jump_penalty: u32,
+    /// Instructions flushed by an exception, an interrupt or ERET
+    pub exception_penalty: u32,
+}
+
+impl Default for PipelineConfig {
+    /// Classic 5-stage pipeline with full forwarding. Branches are predicted not taken unless
+    /// the core has a branch predictor.
+    fn default() -> Self {
+        Self {
+            forwarding: true,
+            branch_penalty: 2,
+            jump_penalty: 1,
+            exception_penalty: 3,
+        }
+    }
+}
+
+/// Why the pipeline lost cycles
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HazardStats {
+    /// Instructions retired
+    pub instructions: u64,
+    /// Bubbles waiting for a load result
+    pub load_use_stalls: u64,
+    /// Bubbles waiting for any other result (only without full forwarding)
+    pub data_stalls: u64,
+    /// Conditional branches retired, and how many of them were taken
+    pub branches: u64,
+    pub taken_branches: u64,
+    /// Instructions flushed behind mispredicted branches and register jumps
+    pub branch_flushes: u64,
+    /// Instructions flushed behind direct jumps
+    pub jump_flushes: u64,
+    /// Instructions flushed by exceptions, interrupts and ERET
+    pub exception_flushes: u64,
+}
+
+impl HazardStats {
+    /// Cycles lost to hazards
+    pub fn lost_cycles(&self) -> u64 {
+        self.load_use_stalls + self.data_stalls + self.branch_flushes + self.jump_flushes + self.exception_flushes
+    }
+    /// Cycles per instruction, ignoring the cycles to fill the pipeline and memory latencies
+    pub fn cpi(&self) -> f64 {
+        if self.instructions == 0 {
+            return 0.0;
+        }
+        (self.instructions + self.lost_cycles()) as f64 / self.instructions as f64
+    }
+}
+
+/// Hazard cycles of a single instruction, summed over all its executions
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InstrProfile {
+    pub opcode: u8,
+    pub count: u64,
+    /// Bubbles inserted before the instruction could enter EX
+    pub stall_cycles: u64,
+    /// Instructions flushed behind it
+    pub flush_cycles: u64,
+}
+
+/// What happened to the instruction that just left the pipeline
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Retire {
+    /// Executed, the next instruction is at pc + 4
+    Sequential,
+    /// Executed, the next instruction comes from a branch or jump target
+    Redirected,
+    /// Interrupted or faulted, the pipeline restarts at the exception vector
+    Exception,
+}
+
+/// Timing model of an in-order 5-stage pipeline, fed with every instruction the core
+/// executes. Execution stays functional; the model works out when each instruction could
+/// enter EX and reports the bubbles and flushes in between as extra cycles.
+pub struct Pipeline {
+    config: PipelineConfig,
+    /// Cycle in which the next instruction can enter EX at the earliest
+    next_ex: u64,
+    /// First EX cycle in which a consumer can use each register
+    ready: [u64; 32],
+    /// Registers last written by a load
+    load_result: [bool; 32],
+    stats: HazardStats,
+    profile: BTreeMap<u32, InstrProfile>,
+}
+
+impl Pipeline {
+    pub fn new(config: PipelineConfig) -> Self {
+        Self {
+            config,
+            next_ex: 0,
+            ready: [0; 32],
+            load_result: [false; 32],
+            stats: HazardStats::default(),
+            profile: BTreeMap::new(),
+        }
+    }
+
+    pub fn config(&self) -> &PipelineConfig {
+        &self.config
+    }
+    pub fn stats(&self) -> &HazardStats {
+        &self.stats
+    }
+    /// Per-instruction hazard cycles, by address
+    pub fn profile(&self) -> &BTreeMap<u32, InstrProfile> {
+        &self.profile
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = HazardStats::default();
+        self.profile.clear();
+    }
+
+    /// Registers an instruction reads in EX, and the register a store writes to memory
+    /// (needed one stage later, in MEM)
+    fn sources(instr: &Instruction) -> ([usize; 2], Option<usize>) {
+        use isa::opcode::*;
+        match instr.opcode {
+            ADD | SUB | AND | OR | XOR | SLT | SLTU | SHL | SHR | SAR => ([instr.rs, instr.rt], None),
+            ADDI | ANDI | ORI | XORI | SLTI | SLTIU | LW | LB | JR | JALR | CACHE => ([instr.rs, 0], None),
+            SW | SB => ([instr.rs, 0], Some(instr.rd)),
+            BEQ | BNE | BLT | BGE => ([instr.rd, instr.rs], None),
+            MTSR => ([instr.rd, 0], None),
+            _ => ([0, 0], None),
+        }
+    }
+
+    /// Register the instruction writes, if any
+    fn dest(instr: &Instruction) -> Option<usize> {
+        use isa::opcode::*;
+        let rd = match instr.opcode {
+            ADD | SUB | AND | OR | XOR | SLT | SLTU | SHL | SHR | SAR => instr.rd,
+            ADDI | ANDI | ORI | XORI | SLTI | SLTIU | LUI | LW | LB | JALR | MFSR => instr.rd,
+            JAL => LINK_REGISTER,
+            _ => return None,
+        };
+        (rd != 0).then_some(rd)
+    }
+
+    /// Accounts for an instruction leaving the pipeline. `mispredicted` is the verdict of the
+    /// branch predictor on a conditional branch, None predicts it not taken. Returns the cycles
+    /// lost to hazards around it.
+    pub fn retire(&mut self, pc: u32, instr: &Instruction, retire: Retire, mispredicted: Option<bool>) -> u32 {
+        if retire == Retire::Exception {
+            // The faulting instruction and everything behind it are squashed
+            let flush = self.config.exception_penalty;
+            self.stats.exception_flushes += flush as u64;
+            self.next_ex += flush as u64;
+            let entry = self.profile.entry(pc).or_default();
+            if entry.count == 0 {
+                entry.opcode = instr.opcode;
+            }
+            entry.flush_cycles += flush as u64;
+            return flush;
+        }
+
+        // Earliest EX cycle once all operands can be forwarded (or read)
+        let (srcs, store_data) = Self::sources(instr);
+        // Without forwarding the store data is read in ID like any other operand
+        let (srcs, store_data) = match (self.config.forwarding, store_data) {
+            (false, Some(data)) => ([srcs[0], data], None),
+            _ => (srcs, store_data),
+        };
+        let mut ex = self.next_ex;
+        let mut waits_for_load = false;
+        for src in srcs.into_iter().filter(|&r| r != 0) {
+            if self.ready[src] > ex {
+                ex = self.ready[src];
+                waits_for_load |= self.load_result[src];
+            }
+        }
+        if let Some(src) = store_data.filter(|&r| r != 0)
+            && self.ready[src] > ex + 1
+        {
+            ex = self.ready[src] - 1;
+            waits_for_load |= self.load_result[src];
+        }
+
+        let stall = (ex - self.next_ex) as u32;
+        if waits_for_load && self.config.forwarding {
+            self.stats.load_use_stalls += stall as u64;
+        } else {
+            self.stats.data_stalls += stall as u64;
+        }
+
+        if let Some(rd) = Self::dest(instr) {
+            let is_load = matches!(instr.opcode, isa::opcode::LW | isa::opcode::LB);
+            self.ready[rd] = match (self.config.forwarding, is_load) {
+                (true, false) => ex + 1, // EX -> EX
+                (true, true) => ex + 2, // MEM -> EX
+                (false, _) => ex + 3, // WB -> ID
+            };
+            self.load_result[rd] = is_load;
+        }
+
+        let flush = self.flush(instr, retire, mispredicted);
+        self.next_ex = ex + 1 + flush as u64;
+
+        self.stats.instructions += 1;
+        let entry = self.profile.entry(pc).or_default();
+        entry.opcode = instr.opcode;
+        entry.count += 1;
+        entry.stall_cycles += stall as u64;
+        entry.flush_cycles += flush as u64;
+
+        stall + flush
+    }
+
+    /// Instructions flushed behind a control transfer
+    fn flush(&mut self, instr: &Instruction, retire: Retire, mispredicted: Option<bool>) -> u32 {
+        use isa::opcode::*;
+        let redirected = retire == Retire::Redirected;
+
+        match instr.opcode {
+            BEQ | BNE | BLT | BGE => {
+                self.stats.branches += 1;
+                self.stats.taken_branches += redirected as u64;
+                if !mispredicted.unwrap_or(redirected) {
+                    return 0;
+                }
+                self.stats.branch_flushes += self.config.branch_penalty as u64;
+                self.config.branch_penalty
+            }
+            JR | JALR => {
+                self.stats.branch_flushes += self.config.branch_penalty as u64;
+                self.config.branch_penalty
+            }
+            J | JAL => {
+                self.stats.jump_flushes += self.config.jump_penalty as u64;
+                self.config.jump_penalty
+            }
+            ERET => {
+                self.stats.exception_flushes += self.config.exception_penalty as u64;
+                self.config.exception_penalty
+            }
+            _ => 0,
+        }
+    }
+
+    /// Summary of the hazards, followed by the instructions that lost cycles
+    pub fn report(&self) -> String {
+        let s = &self.stats;
+        let mut out = String::new();
+
+        let _ = writeln!(out, "Pipeline: {} instructions, CPI {:.3}", s.instructions, s.cpi());
+        let _ = writeln!(out, "  load-use stalls:   {}", s.load_use_stalls);
+        let _ = writeln!(out, "  data stalls:       {}", s.data_stalls);
+        let _ = writeln!(out, "  branches:          {} ({} taken)", s.branches, s.taken_branches);
+        let _ = writeln!(out, "  branch flushes:    {}", s.branch_flushes);
+        let _ = writeln!(out, "  jump flushes:      {}", s.jump_flushes);
+        let _ = writeln!(out, "  exception flushes: {}", s.exception_flushes);
+
+        let _ = writeln!(out, "  PC        OP       COUNT     STALLS    FLUSHES");
+        for (pc, p) in self.profile.iter().filter(|(_, p)| p.stall_cycles + p.flush_cycles > 0) {
+            let _ = writeln!(
+                out,
+                "  {:08X}  {:<6} {:>8} {:>10} {:>10}",
+                pc,
+                op_str(p.opcode),
+                p.count,
+                p.stall_cycles,
+                p.flush_cycles
+            );
+        }
+        out
+    }
+}
@@ -1,6 +1,21 @@
+pub mod audio;
+pub mod block;
+pub mod display;
+pub mod dma;
 pub mod font;
+pub mod framebuffer;
+pub mod gpio;
+pub mod i2c;
+pub mod keyboard;
+pub mod net;
 pub mod ram;
+pub mod rng;
 pub mod rom;
+pub mod spi;
+pub mod rtc;
+pub mod smp;
+pub mod syscon;
 pub mod timer;
 pub mod uart;
 pub mod vram;
+pub mod watchdog;
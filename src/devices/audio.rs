@@ -0,0 +1,212 @@
+pub mod wav;
+
+use std::collections::VecDeque;
+use std::io;
+
+use crate::devices::rtc::DEFAULT_CYCLES_PER_SECOND;
+use crate::machine::snapshot::{invalid, Snapshot, StateReader, StateWriter};
+
+pub const ENABLED: u32 = 0x1; // 0 = stopped, 1 = playing samples from the FIFO
+pub const IRQ_ENABLED: u32 = 0x2; // 0 = no IRQ, 1 = IRQ while the FIFO is at most half full
+
+// Sample formats, as written to the data register
+pub const FORMAT_U8: u32 = 0; // Unsigned 8 bit mono in bits 0-7
+pub const FORMAT_S16: u32 = 1; // Signed 16 bit mono in bits 0-15
+pub const FORMAT_S16_STEREO: u32 = 2; // Signed 16 bit, left in bits 0-15, right in bits 16-31
+
+// Status register bits
+pub const STATUS_EMPTY: u32 = 0x1; // The FIFO is empty
+pub const STATUS_HALF_EMPTY: u32 = 0x2; // The FIFO is at most half full
+pub const STATUS_FULL: u32 = 0x4; // The FIFO is full, further samples are dropped
+pub const STATUS_UNDERRUN: u32 = 0x8; // Silence was played because the FIFO ran dry (W1C)
+
+/// FIFO depth in frames
+pub const FIFO_SIZE: usize = 1024;
+pub const DEFAULT_SAMPLE_RATE: u32 = 8000;
+
+/// Audio output. Samples are written into a FIFO (by the CPU, or by DMA to the data
+/// register) and played at the sample rate, timed against the machine's cycle counter.
+pub struct Audio {
+    /// Frames waiting to be played, as 16 bit stereo
+    fifo: VecDeque<(i16, i16)>,
+    /// Control register
+    ctrl: u32,
+    /// Sample rate in Hz
+    rate: u32,
+    /// Format of the samples written to the data register
+    format: u32,
+    /// Sticky status flags
+    status: u32,
+    /// Emulated cycles per second
+    cycles_per_second: u64,
+    /// Accumulates the sample rate every cycle, a frame is played when it reaches a second
+    phase: u64,
+}
+
+impl Default for Audio {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Audio {
+    pub fn new() -> Self {
+        Self {
+            fifo: VecDeque::with_capacity(FIFO_SIZE),
+            ctrl: 0,
+            rate: DEFAULT_SAMPLE_RATE,
+            format: FORMAT_S16,
+            status: 0,
+            cycles_per_second: DEFAULT_CYCLES_PER_SECOND,
+            phase: 0,
+        }
+    }
+
+    pub fn ctrl(&self) -> u32 {
+        self.ctrl
+    }
+    pub fn rate(&self) -> u32 {
+        self.rate
+    }
+    pub fn format(&self) -> u32 {
+        self.format
+    }
+    pub fn level(&self) -> u32 {
+        self.fifo.len() as u32
+    }
+    pub fn status(&self) -> u32 {
+        let mut status = self.status;
+        if self.fifo.is_empty() {
+            status |= STATUS_EMPTY;
+        }
+        if self.fifo.len() <= FIFO_SIZE / 2 {
+            status |= STATUS_HALF_EMPTY;
+        }
+        if self.fifo.len() >= FIFO_SIZE {
+            status |= STATUS_FULL;
+        }
+        status
+    }
+    pub fn irq(&self) -> bool {
+        self.ctrl & (ENABLED | IRQ_ENABLED) == ENABLED | IRQ_ENABLED
+            && self.fifo.len() <= FIFO_SIZE / 2
+    }
+
+    pub fn set_ctrl(&mut self, ctrl: u32) {
+        println!("[audio] ctrl={:08x}", ctrl);
+        self.ctrl = ctrl;
+    }
+
+    /// Sets the sample rate. A rate of 0 is ignored.
+    pub fn set_rate(&mut self, rate: u32) {
+        if rate > 0 {
+            println!("[audio] rate={}", rate);
+            self.rate = rate;
+        }
+    }
+
+    /// Sets the sample format. Unknown formats are ignored.
+    pub fn set_format(&mut self, format: u32) {
+        if matches!(format, FORMAT_U8 | FORMAT_S16 | FORMAT_S16_STEREO) {
+            println!("[audio] format={}", format);
+            self.format = format;
+        }
+    }
+
+    /// Stops playback and empties the FIFO. The emulated clock is kept.
+    pub fn reset(&mut self) {
+        *self = Self {
+            cycles_per_second: self.cycles_per_second,
+            ..Self::new()
+        };
+    }
+
+    /// Sets the emulated clock the sample rate is timed against
+    pub fn set_cycles_per_second(&mut self, cycles_per_second: u64) {
+        self.cycles_per_second = cycles_per_second.max(1);
+    }
+
+    /// Clears the status bits that are set in `value`
+    pub fn clear_status(&mut self, value: u32) {
+        self.status &= !(value & STATUS_UNDERRUN);
+    }
+
+    /// Queues a sample written to the data register, in the current format
+    pub fn write_data(&mut self, value: u32) {
+        if self.fifo.len() >= FIFO_SIZE {
+            return;
+        }
+
+        let frame = match self.format {
+            FORMAT_U8 => {
+                let s = ((value as u8 as i16) - 128) << 8;
+                (s, s)
+            }
+            FORMAT_S16 => {
+                let s = value as u16 as i16;
+                (s, s)
+            }
+            _ => (value as u16 as i16, (value >> 16) as u16 as i16),
+        };
+        self.fifo.push_back(frame);
+    }
+
+    /// Advances the playback clock. Returns the frame played during this cycle, if any.
+    /// Silence is played when the FIFO is empty.
+    pub fn tick(&mut self) -> Option<(i16, i16)> {
+        if self.ctrl & ENABLED == 0 {
+            return None;
+        }
+
+        self.phase += self.rate as u64;
+        if self.phase < self.cycles_per_second {
+            return None;
+        }
+        self.phase -= self.cycles_per_second;
+
+        match self.fifo.pop_front() {
+            Some(frame) => Some(frame),
+            None => {
+                self.status |= STATUS_UNDERRUN;
+                Some((0, 0))
+            }
+        }
+    }
+}
+
+/// The emulated clock is a machine setting and isn't restored
+impl Snapshot for Audio {
+    fn save(&self, w: &mut StateWriter) {
+        w.u32(self.fifo.len() as u32);
+        for &(left, right) in &self.fifo {
+            w.u16(left as u16);
+            w.u16(right as u16);
+        }
+        for value in [self.ctrl, self.rate, self.format, self.status] {
+            w.u32(value);
+        }
+        w.u64(self.phase);
+    }
+
+    fn restore(&mut self, r: &mut StateReader) -> io::Result<()> {
+        let frames = r.u32()? as usize;
+        if frames > FIFO_SIZE {
+            return Err(invalid(format!("snapshot has {frames} audio frames, at most {FIFO_SIZE} fit")));
+        }
+        self.fifo.clear();
+        for _ in 0..frames {
+            let left = r.u16()? as i16;
+            let right = r.u16()? as i16;
+            self.fifo.push_back((left, right));
+        }
+        self.ctrl = r.u32()?;
+        self.rate = r.u32()?;
+        self.format = r.u32()?;
+        self.status = r.u32()?;
+        self.phase = r.u64()?;
+        if self.rate == 0 || !matches!(self.format, FORMAT_U8 | FORMAT_S16 | FORMAT_S16_STEREO) {
+            return Err(invalid("invalid audio rate or format in snapshot"));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,73 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const HEADER_SIZE: u32 = 44;
+const CHANNELS: u16 = 2;
+const BITS_PER_SAMPLE: u16 = 16;
+const BYTES_PER_FRAME: u32 = CHANNELS as u32 * BITS_PER_SAMPLE as u32 / 8;
+
+/// Writes 16 bit stereo PCM to a WAV file. The header is filled in by `finish()`, with the
+/// sample rate of the first frame written.
+pub struct WavWriter {
+    out: BufWriter<File>,
+    /// Frames written so far
+    frames: u32,
+    /// Sample rate in Hz
+    sample_rate: u32,
+}
+
+impl WavWriter {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        // Placeholder, rewritten by finish()
+        out.write_all(&[0; HEADER_SIZE as usize])?;
+
+        Ok(Self {
+            out,
+            frames: 0,
+            sample_rate: 0,
+        })
+    }
+
+    /// Number of frames written so far
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    /// Appends a stereo frame
+    pub fn write_frame(&mut self, left: i16, right: i16, sample_rate: u32) -> io::Result<()> {
+        if self.frames == 0 {
+            self.sample_rate = sample_rate;
+        }
+        self.out.write_all(&left.to_le_bytes())?;
+        self.out.write_all(&right.to_le_bytes())?;
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Writes the header and flushes the file. Returns the number of frames written.
+    pub fn finish(mut self) -> io::Result<u32> {
+        let data_size = self.frames * BYTES_PER_FRAME;
+
+        let mut header = Vec::with_capacity(HEADER_SIZE as usize);
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&(HEADER_SIZE - 8 + data_size).to_le_bytes());
+        header.extend_from_slice(b"WAVEfmt ");
+        header.extend_from_slice(&16u32.to_le_bytes()); // fmt chunk size
+        header.extend_from_slice(&1u16.to_le_bytes()); // PCM
+        header.extend_from_slice(&CHANNELS.to_le_bytes());
+        header.extend_from_slice(&self.sample_rate.to_le_bytes());
+        header.extend_from_slice(&(self.sample_rate * BYTES_PER_FRAME).to_le_bytes()); // byte rate
+        header.extend_from_slice(&(BYTES_PER_FRAME as u16).to_le_bytes()); // block align
+        header.extend_from_slice(&BITS_PER_SAMPLE.to_le_bytes());
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&data_size.to_le_bytes());
+
+        self.out.seek(SeekFrom::Start(0))?;
+        self.out.write_all(&header)?;
+        self.out.flush()?;
+
+        Ok(self.frames)
+    }
+}
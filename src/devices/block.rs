@@ -0,0 +1,290 @@
+use crate::machine::snapshot::{invalid, Snapshot, StateReader, StateWriter};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+pub const SECTOR_SIZE: u32 = 512;
+
+pub const IRQ_ENABLED: u32 = 0x1; // 0 = no IRQ on completion, 1 = IRQ on completion
+
+// Commands
+pub const CMD_READ: u32 = 1; // Disk -> memory
+pub const CMD_WRITE: u32 = 2; // Memory -> disk
+pub const CMD_FLUSH: u32 = 3; // Flush the image to the host
+
+// Status register bits (DONE and ERROR are write 1 to clear)
+pub const STATUS_BUSY: u32 = 0x1; // A command is in progress
+pub const STATUS_DONE: u32 = 0x2; // The last command completed successfully
+pub const STATUS_ERROR: u32 = 0x4; // The last command failed, see the error register
+
+// Error codes
+pub const ERR_NONE: u32 = 0;
+pub const ERR_NO_MEDIA: u32 = 1; // No disk image attached
+pub const ERR_OUT_OF_RANGE: u32 = 2; // LBA + count beyond the end of the disk
+pub const ERR_IO: u32 = 3; // Host I/O error
+pub const ERR_BUS: u32 = 4; // Buffer address not accessible on the bus
+pub const ERR_BAD_COMMAND: u32 = 5; // Unknown command
+
+/// Emulated seek + transfer time per sector, in cycles
+pub const CYCLES_PER_SECTOR: u32 = 64;
+
+/// A transfer that has finished its emulated latency and has to be carried out on the bus
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockRequest {
+    pub cmd: u32,
+    pub lba: u32,
+    pub count: u32,
+    pub buf_addr: u32,
+}
+
+/// Block storage device backed by a host disk image
+pub struct BlockDevice {
+    /// Attached disk image
+    image: Option<File>,
+    /// Size of the image in sectors
+    capacity: u32,
+    /// Control register
+    ctrl: u32,
+    /// Status flags
+    status: u32,
+    /// Error code of the last failed command
+    error: u32,
+    /// First sector of the transfer
+    lba: u32,
+    /// Number of sectors to transfer
+    count: u32,
+    /// Address of the memory buffer
+    buf_addr: u32,
+    /// Command in progress and the cycles left until it completes
+    pending: Option<(BlockRequest, u32)>,
+}
+
+impl Default for BlockDevice {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BlockDevice {
+    /// Creates a block device without media
+    pub fn new() -> Self {
+        Self {
+            image: None,
+            capacity: 0,
+            ctrl: 0,
+            status: 0,
+            error: ERR_NONE,
+            lba: 0,
+            count: 0,
+            buf_addr: 0,
+            pending: None,
+        }
+    }
+
+    /// Attaches a disk image. Trailing bytes that don't fill a whole sector are ignored.
+    pub fn attach<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let len = file.metadata()?.len();
+
+        self.capacity = (len / SECTOR_SIZE as u64).min(u32::MAX as u64) as u32;
+        self.image = Some(file);
+        Ok(())
+    }
+
+    /// Detaches the disk image, if any
+    pub fn detach(&mut self) {
+        self.image = None;
+        self.capacity = 0;
+    }
+
+    /// Puts the registers back to their power-on state and drops a command in progress.
+    /// The disk image stays attached.
+    pub fn reset(&mut self) {
+        self.ctrl = 0;
+        self.status = 0;
+        self.error = ERR_NONE;
+        self.lba = 0;
+        self.count = 0;
+        self.buf_addr = 0;
+        self.pending = None;
+    }
+
+    pub fn ctrl(&self) -> u32 {
+        self.ctrl
+    }
+    pub fn status(&self) -> u32 {
+        self.status
+    }
+    pub fn error(&self) -> u32 {
+        self.error
+    }
+    pub fn lba(&self) -> u32 {
+        self.lba
+    }
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+    pub fn buf_addr(&self) -> u32 {
+        self.buf_addr
+    }
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+    pub fn irq(&self) -> bool {
+        self.ctrl & IRQ_ENABLED != 0 && self.status & (STATUS_DONE | STATUS_ERROR) != 0
+    }
+
+    pub fn set_ctrl(&mut self, ctrl: u32) {
+        self.ctrl = ctrl;
+    }
+    pub fn set_lba(&mut self, lba: u32) {
+        self.lba = lba;
+    }
+    pub fn set_count(&mut self, count: u32) {
+        self.count = count;
+    }
+    pub fn set_buf_addr(&mut self, addr: u32) {
+        self.buf_addr = addr;
+    }
+
+    /// Clears the DONE and ERROR bits that are set in `value`
+    pub fn clear_status(&mut self, value: u32) {
+        self.status &= !(value & (STATUS_DONE | STATUS_ERROR));
+    }
+
+    /// Starts a command. Validation errors are reported right away, the transfer itself
+    /// completes after the emulated latency. Commands issued while busy are ignored.
+    pub fn command(&mut self, cmd: u32) {
+        println!("[block] cmd={} lba={} count={} buf=0x{:08X}", cmd, self.lba, self.count, self.buf_addr);
+
+        if self.status & STATUS_BUSY != 0 {
+            println!("[block] busy, command ignored");
+            return;
+        }
+        self.status &= !(STATUS_DONE | STATUS_ERROR);
+
+        if !matches!(cmd, CMD_READ | CMD_WRITE | CMD_FLUSH) {
+            self.finish(Err(ERR_BAD_COMMAND));
+            return;
+        }
+        if self.image.is_none() {
+            self.finish(Err(ERR_NO_MEDIA));
+            return;
+        }
+        if cmd != CMD_FLUSH && self.lba as u64 + self.count as u64 > self.capacity as u64 {
+            self.finish(Err(ERR_OUT_OF_RANGE));
+            return;
+        }
+
+        let req = BlockRequest {
+            cmd,
+            lba: self.lba,
+            count: self.count,
+            buf_addr: self.buf_addr,
+        };
+        let latency = CYCLES_PER_SECTOR.saturating_mul(self.count.max(1));
+
+        self.status |= STATUS_BUSY;
+        self.pending = Some((req, latency));
+    }
+
+    /// Counts down the command in progress. Returns the request once it is ready to be
+    /// carried out; the caller must report the outcome with `finish()`.
+    pub fn tick(&mut self) -> Option<BlockRequest> {
+        let (req, cycles) = self.pending.as_mut()?;
+
+        if *cycles > 0 {
+            *cycles -= 1;
+            return None;
+        }
+
+        let req = *req;
+        self.pending = None;
+        Some(req)
+    }
+
+    /// Completes the current command with the given outcome (an error code on failure)
+    pub fn finish(&mut self, result: Result<(), u32>) {
+        self.status &= !STATUS_BUSY;
+
+        match result {
+            Ok(()) => {
+                self.error = ERR_NONE;
+                self.status |= STATUS_DONE;
+            }
+            Err(code) => {
+                println!("[block] error={}", code);
+                self.error = code;
+                self.status |= STATUS_ERROR;
+            }
+        }
+    }
+
+    fn image(&mut self) -> Result<&mut File, u32> {
+        self.image.as_mut().ok_or(ERR_NO_MEDIA)
+    }
+
+    /// Reads `count` sectors starting at `lba` from the image
+    pub fn read_sectors(&mut self, lba: u32, count: u32) -> Result<Vec<u8>, u32> {
+        let image = self.image()?;
+        let mut buf = vec![0; count as usize * SECTOR_SIZE as usize];
+
+        image
+            .seek(SeekFrom::Start(lba as u64 * SECTOR_SIZE as u64))
+            .and_then(|_| image.read_exact(&mut buf))
+            .map_err(|_| ERR_IO)?;
+
+        Ok(buf)
+    }
+
+    /// Writes whole sectors starting at `lba` to the image
+    pub fn write_sectors(&mut self, lba: u32, data: &[u8]) -> Result<(), u32> {
+        let image = self.image()?;
+
+        image
+            .seek(SeekFrom::Start(lba as u64 * SECTOR_SIZE as u64))
+            .and_then(|_| image.write_all(data))
+            .map_err(|_| ERR_IO)
+    }
+
+    /// Flushes pending writes to the host
+    pub fn flush(&mut self) -> Result<(), u32> {
+        self.image()?.sync_data().map_err(|_| ERR_IO)
+    }
+}
+
+/// The disk image is attached by the host and isn't part of the state
+impl Snapshot for BlockDevice {
+    fn save(&self, w: &mut StateWriter) {
+        for value in [self.ctrl, self.status, self.error, self.lba, self.count, self.buf_addr] {
+            w.u32(value);
+        }
+        let (req, cycles) = self.pending.unwrap_or((BlockRequest { cmd: 0, lba: 0, count: 0, buf_addr: 0 }, 0));
+        w.bool(self.pending.is_some());
+        for value in [req.cmd, req.lba, req.count, req.buf_addr, cycles] {
+            w.u32(value);
+        }
+    }
+
+    fn restore(&mut self, r: &mut StateReader) -> io::Result<()> {
+        self.ctrl = r.u32()?;
+        self.status = r.u32()?;
+        self.error = r.u32()?;
+        self.lba = r.u32()?;
+        self.count = r.u32()?;
+        self.buf_addr = r.u32()?;
+        let pending = r.bool()?;
+        let req = BlockRequest {
+            cmd: r.u32()?,
+            lba: r.u32()?,
+            count: r.u32()?,
+            buf_addr: r.u32()?,
+        };
+        let cycles = r.u32()?;
+        if pending && self.image.is_none() {
+            return Err(invalid("snapshot has a disk transfer in progress, but no disk is attached"));
+        }
+        self.pending = pending.then_some((req, cycles));
+        Ok(())
+    }
+}
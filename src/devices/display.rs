@@ -0,0 +1,234 @@
+pub mod frame;
+
+use crate::devices::display::frame::Frame;
+use crate::devices::font::FontRam;
+use crate::devices::vram::Vram;
+use crate::machine::snapshot::{Snapshot, StateReader, StateWriter};
+use std::io;
+
+// Display modes
+pub const MODE_OFF: u32 = 0; // Display blanked
+pub const MODE_TEXT_80X25: u32 = 1; // 80 columns, 25 rows
+pub const MODE_TEXT_40X25: u32 = 2; // 40 columns, 25 rows
+
+pub const CURSOR_VISIBLE: u32 = 0x1; // 0 = hidden, 1 = visible
+
+// Glyph geometry in FONT RAM: 16 bytes per glyph, one byte per row, MSB is the leftmost pixel
+pub const GLYPH_WIDTH: usize = 8;
+pub const GLYPH_HEIGHT: usize = 16;
+
+const PALETTE_SIZE: usize = 16;
+
+/// Default 16 color palette (CGA style), 0x00RRGGBB. The framebuffer uses it for its first
+/// 16 entries as well.
+pub const CGA_PALETTE: [u32; PALETTE_SIZE] = [
+    0x000000, 0x0000AA, 0x00AA00, 0x00AAAA, 0xAA0000, 0xAA00AA, 0xAA5500, 0xAAAAAA,
+    0x555555, 0x5555FF, 0x55FF55, 0x55FFFF, 0xFF5555, 0xFF55FF, 0xFFFF55, 0xFFFFFF,
+];
+
+/// Character cell display. VRAM holds 2 byte cells (character, attribute) where the low
+/// nibble of the attribute is the foreground color and the high nibble the background color.
+/// FONT RAM holds the 8x16 glyph bitmaps for all 256 characters.
+pub struct TextDisplay {
+    /// Current display mode
+    mode: u32,
+    /// Cursor position (x in bits 0-7, y in bits 8-15)
+    cursor: u32,
+    /// Cursor control
+    cursor_ctrl: u32,
+    /// Palette index used for the whole screen when the display is off
+    blank: u32,
+    /// Palette entry selected for PAL_DATA accesses
+    palette_index: u32,
+    /// Color palette, 0x00RRGGBB
+    palette: [u32; PALETTE_SIZE],
+}
+
+impl Default for TextDisplay {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TextDisplay {
+    pub fn new() -> Self {
+        Self {
+            mode: MODE_TEXT_80X25,
+            cursor: 0,
+            cursor_ctrl: CURSOR_VISIBLE,
+            blank: 0,
+            palette_index: 0,
+            palette: CGA_PALETTE,
+        }
+    }
+
+    pub fn mode(&self) -> u32 {
+        self.mode
+    }
+    pub fn cursor(&self) -> u32 {
+        self.cursor
+    }
+    pub fn cursor_ctrl(&self) -> u32 {
+        self.cursor_ctrl
+    }
+    pub fn blank(&self) -> u32 {
+        self.blank
+    }
+    pub fn palette_index(&self) -> u32 {
+        self.palette_index
+    }
+    pub fn palette_data(&self) -> u32 {
+        self.palette[self.palette_index as usize]
+    }
+
+    /// Returns the dimensions of the current mode as (columns, rows)
+    pub fn dimensions(&self) -> (usize, usize) {
+        match self.mode {
+            MODE_TEXT_40X25 => (40, 25),
+            _ => (80, 25),
+        }
+    }
+
+    /// Info register: columns in bits 0-7, rows in bits 8-15
+    pub fn info(&self) -> u32 {
+        let (cols, rows) = self.dimensions();
+        cols as u32 | (rows as u32) << 8
+    }
+
+    pub fn set_mode(&mut self, mode: u32) {
+        println!("[display] mode={}", mode);
+        self.mode = match mode {
+            MODE_OFF | MODE_TEXT_80X25 | MODE_TEXT_40X25 => mode,
+            _ => MODE_OFF,
+        };
+    }
+
+    pub fn set_cursor(&mut self, cursor: u32) {
+        self.cursor = cursor & 0xFFFF;
+    }
+
+    pub fn set_cursor_ctrl(&mut self, ctrl: u32) {
+        self.cursor_ctrl = ctrl;
+    }
+
+    pub fn set_blank(&mut self, index: u32) {
+        self.blank = index % PALETTE_SIZE as u32;
+    }
+
+    pub fn set_palette_index(&mut self, index: u32) {
+        self.palette_index = index % PALETTE_SIZE as u32;
+    }
+
+    pub fn set_palette_data(&mut self, rgb: u32) {
+        self.palette[self.palette_index as usize] = rgb & 0x00FF_FFFF;
+    }
+
+    fn cursor_at(&self, col: usize, row: usize) -> bool {
+        self.cursor_ctrl & CURSOR_VISIBLE != 0
+            && (self.cursor & 0xFF) as usize == col
+            && (self.cursor >> 8 & 0xFF) as usize == row
+    }
+
+    /// Returns the (character, attribute) pair of a cell
+    fn cell(vram: &Vram, cols: usize, col: usize, row: usize) -> (u8, u8) {
+        let off = ((row * cols + col) * 2) as u32;
+        let ch = vram.read8(off).unwrap_or(0);
+        let attr = vram.read8(off + 1).unwrap_or(0);
+        (ch, attr)
+    }
+
+    /// Renders the screen into an RGB frame
+    pub fn render(&self, vram: &Vram, font: &FontRam) -> Frame {
+        let (cols, rows) = self.dimensions();
+        let mut frame = Frame::new(cols * GLYPH_WIDTH, rows * GLYPH_HEIGHT);
+
+        if self.mode == MODE_OFF {
+            frame.fill(self.palette[self.blank as usize]);
+            return frame;
+        }
+
+        for row in 0..rows {
+            for col in 0..cols {
+                let (ch, attr) = Self::cell(vram, cols, col, row);
+                let fg = self.palette[(attr & 0x0F) as usize];
+                let bg = self.palette[(attr >> 4) as usize];
+                let cursor = self.cursor_at(col, row);
+
+                for y in 0..GLYPH_HEIGHT {
+                    let glyph_off = (ch as usize * GLYPH_HEIGHT + y) as u32;
+                    let mut bits = font.read8(glyph_off).unwrap_or(0);
+
+                    // Underline cursor on the last two scanlines
+                    if cursor && y >= GLYPH_HEIGHT - 2 {
+                        bits = 0xFF;
+                    }
+
+                    for x in 0..GLYPH_WIDTH {
+                        let on = bits & (0x80 >> x) != 0;
+                        frame.set_pixel(
+                            col * GLYPH_WIDTH + x,
+                            row * GLYPH_HEIGHT + y,
+                            if on { fg } else { bg },
+                        );
+                    }
+                }
+            }
+        }
+
+        frame
+    }
+
+    /// Renders the screen as text with ANSI 24-bit color escape sequences
+    pub fn render_ansi(&self, vram: &Vram) -> String {
+        let (cols, rows) = self.dimensions();
+        let mut out = String::new();
+
+        if self.mode == MODE_OFF {
+            return out;
+        }
+
+        for row in 0..rows {
+            for col in 0..cols {
+                let (ch, attr) = Self::cell(vram, cols, col, row);
+                let [_, fr, fg, fb] = self.palette[(attr & 0x0F) as usize].to_be_bytes();
+                let [_, br, bg, bb] = self.palette[(attr >> 4) as usize].to_be_bytes();
+
+                if self.cursor_at(col, row) {
+                    out.push_str("\x1b[7m");
+                }
+                out.push_str(&format!("\x1b[38;2;{fr};{fg};{fb}m\x1b[48;2;{br};{bg};{bb}m"));
+                out.push(if ch.is_ascii_graphic() { ch as char } else { ' ' });
+                out.push_str("\x1b[0m");
+            }
+            out.push('\n');
+        }
+
+        out
+    }
+}
+
+impl Snapshot for TextDisplay {
+    fn save(&self, w: &mut StateWriter) {
+        for value in [self.mode, self.cursor, self.cursor_ctrl, self.blank, self.palette_index] {
+            w.u32(value);
+        }
+        for &rgb in &self.palette {
+            w.u32(rgb);
+        }
+    }
+
+    fn restore(&mut self, r: &mut StateReader) -> io::Result<()> {
+        self.mode = match r.u32()? {
+            mode @ (MODE_OFF | MODE_TEXT_80X25 | MODE_TEXT_40X25) => mode,
+            _ => MODE_OFF,
+        };
+        self.cursor = r.u32()? & 0xFFFF;
+        self.cursor_ctrl = r.u32()?;
+        self.blank = r.u32()? % PALETTE_SIZE as u32;
+        self.palette_index = r.u32()? % PALETTE_SIZE as u32;
+        for rgb in self.palette.iter_mut() {
+            *rgb = r.u32()?;
+        }
+        Ok(())
+    }
+}
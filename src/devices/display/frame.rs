@@ -0,0 +1,174 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A rendered RGB888 frame
+pub struct Frame {
+    pub width: usize,
+    pub height: usize,
+    /// Pixels in row-major order, 3 bytes (R, G, B) per pixel
+    pub pixels: Vec<u8>,
+}
+
+impl Frame {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![0; width * height * 3],
+        }
+    }
+
+    /// Fills the whole frame with the given 0x00RRGGBB color
+    pub fn fill(&mut self, rgb: u32) {
+        for px in self.pixels.chunks_exact_mut(3) {
+            px.copy_from_slice(&rgb_bytes(rgb));
+        }
+    }
+
+    /// Sets a pixel to the given 0x00RRGGBB color. Out of range pixels are ignored.
+    pub fn set_pixel(&mut self, x: usize, y: usize, rgb: u32) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+        let off = (y * self.width + x) * 3;
+        self.pixels[off..off + 3].copy_from_slice(&rgb_bytes(rgb));
+    }
+
+    /// Returns the 0x00RRGGBB color of a pixel
+    pub fn pixel(&self, x: usize, y: usize) -> u32 {
+        let off = (y * self.width + x) * 3;
+        u32::from_be_bytes([0, self.pixels[off], self.pixels[off + 1], self.pixels[off + 2]])
+    }
+
+    /// Encodes the frame as a binary PPM (P6) image
+    pub fn to_ppm(&self) -> Vec<u8> {
+        let mut out = format!("P6\n{} {}\n255\n", self.width, self.height).into_bytes();
+        out.extend_from_slice(&self.pixels);
+        out
+    }
+
+    /// Encodes the frame as an (uncompressed) PNG image. PNG has no empty images, so a frame
+    /// without pixels is an `InvalidInput` error.
+    pub fn to_png(&self) -> io::Result<Vec<u8>> {
+        if self.width == 0 || self.height == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("cannot encode a {}x{} frame as PNG", self.width, self.height),
+            ));
+        }
+
+        let mut out = b"\x89PNG\r\n\x1a\n".to_vec();
+
+        let mut ihdr = Vec::with_capacity(13);
+        ihdr.extend_from_slice(&(self.width as u32).to_be_bytes());
+        ihdr.extend_from_slice(&(self.height as u32).to_be_bytes());
+        ihdr.extend_from_slice(&[8, 2, 0, 0, 0]); // 8 bit depth, truecolor, no interlace
+        png_chunk(&mut out, b"IHDR", &ihdr);
+
+        // Each scanline is prefixed with filter type 0 (none)
+        let mut raw = Vec::with_capacity((self.width * 3 + 1) * self.height);
+        for row in self.pixels.chunks_exact(self.width * 3) {
+            raw.push(0);
+            raw.extend_from_slice(row);
+        }
+        png_chunk(&mut out, b"IDAT", &zlib_stored(&raw));
+        png_chunk(&mut out, b"IEND", &[]);
+
+        Ok(out)
+    }
+
+    pub fn write_ppm<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_ppm())
+    }
+
+    pub fn write_png<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_png()?)
+    }
+}
+
+/// Writes frames as numbered PNG files (frame_00000.png, frame_00001.png, ...) into a directory
+pub struct FrameCapture {
+    dir: PathBuf,
+    next: u32,
+}
+
+impl FrameCapture {
+    /// Creates the capture directory if needed
+    pub fn new<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        fs::create_dir_all(dir.as_ref())?;
+
+        Ok(Self {
+            dir: dir.as_ref().to_path_buf(),
+            next: 0,
+        })
+    }
+
+    /// Number of frames written so far
+    pub fn count(&self) -> u32 {
+        self.next
+    }
+
+    /// Writes the next numbered frame and returns its path
+    pub fn write(&mut self, frame: &Frame) -> io::Result<PathBuf> {
+        let path = self.dir.join(format!("frame_{:05}.png", self.next));
+        frame.write_png(&path)?;
+        self.next += 1;
+        Ok(path)
+    }
+}
+
+fn rgb_bytes(rgb: u32) -> [u8; 3] {
+    let [_, r, g, b] = rgb.to_be_bytes();
+    [r, g, b]
+}
+
+fn png_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = crc32(&out[start..]);
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// Wraps data in a zlib stream made of stored (uncompressed) deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+
+    let mut blocks = data.chunks(0xFFFF).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        out.push(last as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
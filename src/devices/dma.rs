@@ -0,0 +1,270 @@
+use crate::machine::snapshot::{invalid, Snapshot, StateReader, StateWriter};
+use std::io;
+
+pub const CHANNEL_COUNT: usize = 4;
+
+// Channel control register
+pub const START: u32 = 0x1; // Write 1 to start the transfer, reads 1 while busy
+pub const IRQ_ENABLED: u32 = 0x2; // 0 = no IRQ on completion, 1 = IRQ on completion
+pub const WIDTH32: u32 = 0x4; // 0 = byte transfers, 1 = word transfers
+pub const SRC_MODE_SHIFT: u32 = 4; // Address mode of the source (2 bits)
+pub const DST_MODE_SHIFT: u32 = 6; // Address mode of the destination (2 bits)
+
+// Address modes
+pub const MODE_FIXED: u32 = 0; // Address stays the same (e.g. a device register)
+pub const MODE_INCREMENT: u32 = 1; // Address moves up after every unit
+pub const MODE_DECREMENT: u32 = 2; // Address moves down after every unit
+
+// Status register bits (DONE and ERROR are write 1 to clear)
+pub const STATUS_BUSY: u32 = 0x1; // The channel is transferring
+pub const STATUS_DONE: u32 = 0x2; // The last transfer completed
+pub const STATUS_ERROR: u32 = 0x4; // The last transfer hit a bus error and was aborted
+
+/// A single unit to be moved over the bus
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DmaTransfer {
+    pub channel: usize,
+    pub src: u32,
+    pub dst: u32,
+    /// Word (true) or byte (false) transfer
+    pub word: bool,
+}
+
+#[derive(Default)]
+pub struct DmaChannel {
+    /// Source address (advances during the transfer)
+    src: u32,
+    /// Destination address (advances during the transfer)
+    dst: u32,
+    /// Number of units to transfer
+    len: u32,
+    /// Control register
+    ctrl: u32,
+    /// Status flags
+    status: u32,
+    /// Units left in the current transfer
+    remaining: u32,
+}
+
+impl DmaChannel {
+    pub fn src(&self) -> u32 {
+        self.src
+    }
+    pub fn dst(&self) -> u32 {
+        self.dst
+    }
+    pub fn length(&self) -> u32 {
+        self.len
+    }
+    pub fn ctrl(&self) -> u32 {
+        if self.busy() {
+            self.ctrl | START
+        } else {
+            self.ctrl & !START
+        }
+    }
+    pub fn status(&self) -> u32 {
+        self.status
+    }
+    pub fn remaining(&self) -> u32 {
+        self.remaining
+    }
+    pub fn busy(&self) -> bool {
+        self.status & STATUS_BUSY != 0
+    }
+    pub fn irq(&self) -> bool {
+        self.ctrl & IRQ_ENABLED != 0 && self.status & (STATUS_DONE | STATUS_ERROR) != 0
+    }
+
+    pub fn set_src(&mut self, src: u32) {
+        self.src = src;
+    }
+    pub fn set_dst(&mut self, dst: u32) {
+        self.dst = dst;
+    }
+    pub fn set_length(&mut self, len: u32) {
+        self.len = len;
+    }
+
+    /// Writes the control register. Setting START on an idle channel starts a transfer.
+    pub fn set_ctrl(&mut self, ctrl: u32) {
+        println!("[dma] ctrl={:08x}", ctrl);
+        self.ctrl = ctrl & !START;
+
+        if ctrl & START == 0 || self.busy() {
+            return;
+        }
+
+        self.status &= !(STATUS_DONE | STATUS_ERROR);
+        if self.len == 0 {
+            self.status |= STATUS_DONE;
+            return;
+        }
+
+        self.remaining = self.len;
+        self.status |= STATUS_BUSY;
+    }
+
+    /// Clears the DONE and ERROR bits that are set in `value`
+    pub fn clear_status(&mut self, value: u32) {
+        self.status &= !(value & (STATUS_DONE | STATUS_ERROR));
+    }
+
+    fn unit_size(&self) -> u32 {
+        if self.ctrl & WIDTH32 != 0 { 4 } else { 1 }
+    }
+
+    fn advance(addr: u32, mode: u32, size: u32) -> u32 {
+        match mode {
+            MODE_INCREMENT => addr.wrapping_add(size),
+            MODE_DECREMENT => addr.wrapping_sub(size),
+            _ => addr,
+        }
+    }
+
+    /// Moves the addresses on after a unit was transferred
+    fn complete_unit(&mut self, ok: bool) {
+        if !ok {
+            self.status = (self.status & !STATUS_BUSY) | STATUS_ERROR;
+            return;
+        }
+
+        let size = self.unit_size();
+        self.src = Self::advance(self.src, (self.ctrl >> SRC_MODE_SHIFT) & 3, size);
+        self.dst = Self::advance(self.dst, (self.ctrl >> DST_MODE_SHIFT) & 3, size);
+
+        self.remaining -= 1;
+        if self.remaining == 0 {
+            self.status = (self.status & !STATUS_BUSY) | STATUS_DONE;
+        }
+    }
+}
+
+/// Bus cycles per stolen cycle by default: transfers and the cores take turns on the bus
+pub const DEFAULT_STEAL_INTERVAL: u32 = 2;
+
+/// Multi channel DMA controller. Every transfer unit takes one bus cycle, which is stolen
+/// from the CPU, and at most one bus cycle in every `steal_interval` is stolen. An interval
+/// of 1 is burst mode: the cores stop until the transfer is done. Busy channels are served
+/// round-robin.
+pub struct DmaController {
+    pub channels: [DmaChannel; CHANNEL_COUNT],
+    /// Channel to look at first on the next cycle
+    next_channel: usize,
+    /// Bus cycles taken from the CPU so far
+    stolen_cycles: u64,
+    /// One bus cycle in every `steal_interval` may be stolen
+    steal_interval: u32,
+    /// Cycles to leave to the cores before the next unit
+    cooldown: u32,
+}
+
+impl Default for DmaController {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DmaController {
+    pub fn new() -> Self {
+        Self {
+            channels: Default::default(),
+            next_channel: 0,
+            stolen_cycles: 0,
+            steal_interval: DEFAULT_STEAL_INTERVAL,
+            cooldown: 0,
+        }
+    }
+
+    /// Stops all channels. The steal interval is kept.
+    pub fn reset(&mut self) {
+        *self = Self {
+            steal_interval: self.steal_interval,
+            ..Self::new()
+        };
+    }
+
+    pub fn irq(&self) -> bool {
+        self.channels.iter().any(|c| c.irq())
+    }
+
+    /// Number of bus cycles the DMA controller has taken from the CPU
+    pub fn stolen_cycles(&self) -> u64 {
+        self.stolen_cycles
+    }
+    pub fn steal_interval(&self) -> u32 {
+        self.steal_interval
+    }
+
+    /// Lets transfers take one bus cycle in every `interval` (at least 1)
+    pub fn set_steal_interval(&mut self, interval: u32) {
+        self.steal_interval = interval.max(1);
+        self.cooldown = self.cooldown.min(self.steal_interval - 1);
+    }
+
+    /// Picks the next unit to move, if any channel is busy and this bus cycle may be stolen.
+    /// The caller performs the transfer on the bus and reports back with `complete()`.
+    pub fn next_transfer(&mut self) -> Option<DmaTransfer> {
+        if self.cooldown > 0 {
+            self.cooldown -= 1;
+            return None;
+        }
+
+        for i in 0..CHANNEL_COUNT {
+            let idx = (self.next_channel + i) % CHANNEL_COUNT;
+            let ch = &self.channels[idx];
+            if !ch.busy() {
+                continue;
+            }
+
+            self.next_channel = (idx + 1) % CHANNEL_COUNT;
+            return Some(DmaTransfer {
+                channel: idx,
+                src: ch.src,
+                dst: ch.dst,
+                word: ch.ctrl & WIDTH32 != 0,
+            });
+        }
+
+        None
+    }
+
+    /// Completes a unit returned by `next_transfer()`. A failed unit aborts the channel.
+    pub fn complete(&mut self, xfer: DmaTransfer, ok: bool) {
+        self.stolen_cycles += 1;
+        self.cooldown = self.steal_interval - 1;
+        self.channels[xfer.channel].complete_unit(ok);
+    }
+}
+
+/// The steal interval is a machine setting and isn't restored
+impl Snapshot for DmaController {
+    fn save(&self, w: &mut StateWriter) {
+        for ch in &self.channels {
+            for value in [ch.src, ch.dst, ch.len, ch.ctrl, ch.status, ch.remaining] {
+                w.u32(value);
+            }
+        }
+        w.u32(self.next_channel as u32);
+        w.u64(self.stolen_cycles);
+        w.u32(self.cooldown);
+    }
+
+    fn restore(&mut self, r: &mut StateReader) -> io::Result<()> {
+        for ch in self.channels.iter_mut() {
+            ch.src = r.u32()?;
+            ch.dst = r.u32()?;
+            ch.len = r.u32()?;
+            ch.ctrl = r.u32()?;
+            ch.status = r.u32()?;
+            ch.remaining = r.u32()?;
+            if ch.busy() && ch.remaining == 0 {
+                return Err(invalid("busy DMA channel with nothing left"));
+            }
+        }
+        self.next_channel = r.u32()? as usize % CHANNEL_COUNT;
+        self.stolen_cycles = r.u64()?;
+        self.cooldown = r.u32()?.min(self.steal_interval - 1);
+        Ok(())
+    }
+}
@@ -1,4 +1,11 @@
+pub mod builtin;
+
 use crate::bus::BusError;
+use crate::machine::snapshot::{Snapshot, StateReader, StateWriter};
+use std::io;
+
+/// Bytes per glyph in FONT RAM (8x16, one byte per row)
+pub const GLYPH_BYTES: usize = 16;
 
 pub struct FontRam {
     pub data: Vec<u8>,
@@ -10,6 +17,24 @@ impl FontRam {
         }
     }
 
+    /// Loads the built-in font. Each 8x8 glyph is stretched to 8x16 by doubling its rows,
+    /// characters without a built-in glyph are left blank.
+    pub fn load_builtin_font(&mut self) {
+        self.data.fill(0);
+
+        for (i, glyph) in builtin::GLYPHS.iter().enumerate() {
+            let base = (builtin::FIRST_CHAR as usize + i) * GLYPH_BYTES;
+            if base + GLYPH_BYTES > self.data.len() {
+                break;
+            }
+
+            for (row, &bits) in glyph.iter().enumerate() {
+                self.data[base + row * 2] = bits;
+                self.data[base + row * 2 + 1] = bits;
+            }
+        }
+    }
+
     #[inline]
     fn check_range(&self, offset: u32, size: usize) -> Result<usize, BusError> {
         let off = offset as usize;
@@ -43,3 +68,13 @@ impl FontRam {
         Ok(u32::from_le_bytes(bytes))
     }
 }
+
+impl Snapshot for FontRam {
+    fn save(&self, w: &mut StateWriter) {
+        w.bytes(&self.data);
+    }
+
+    fn restore(&mut self, r: &mut StateReader) -> io::Result<()> {
+        r.bytes_into("FONT RAM", &mut self.data)
+    }
+}
@@ -0,0 +1,307 @@
+use crate::bus::BusError;
+use crate::devices::display::CGA_PALETTE;
+use crate::devices::display::frame::Frame;
+use crate::machine::snapshot::{invalid, Snapshot, StateReader, StateWriter};
+use std::io;
+
+pub const ENABLED: u32 = 0x1; // 0 = display off, 1 = scanning out
+pub const VSYNC_IRQ_ENABLED: u32 = 0x2; // 0 = no IRQ on vsync, 1 = IRQ on vsync
+
+// Pixel formats
+pub const FORMAT_INDEXED8: u32 = 0; // One byte per pixel, index into the palette
+pub const FORMAT_RGB565: u32 = 1; // Two bytes per pixel (little endian), 5:6:5 RGB
+
+// Status register bits (write 1 to clear)
+pub const STATUS_VSYNC: u32 = 0x1; // A vertical sync happened
+
+pub const DEFAULT_WIDTH: u32 = 320;
+pub const DEFAULT_HEIGHT: u32 = 240;
+/// Cycles between two vsyncs, 0 disables vsync
+pub const DEFAULT_VSYNC_PERIOD: u32 = 100_000;
+
+const PALETTE_SIZE: usize = 256;
+
+/// Pixel framebuffer, backed by its own RAM region
+pub struct Framebuffer {
+    /// Pixel memory
+    data: Vec<u8>,
+    /// Control register
+    ctrl: u32,
+    width: u32,
+    height: u32,
+    format: u32,
+    /// Cycles between two vsyncs
+    vsync_period: u32,
+    /// Cycles since the last vsync
+    vsync_counter: u32,
+    /// Number of vsyncs so far
+    frame: u32,
+    /// Status flags
+    status: u32,
+    /// Palette entry selected for PAL_DATA accesses
+    palette_index: u32,
+    /// Palette for the indexed format, 0x00RRGGBB
+    palette: [u32; PALETTE_SIZE],
+}
+
+impl Framebuffer {
+    pub fn new(size: usize) -> Self {
+        Self {
+            data: vec![0; size],
+            ctrl: 0,
+            width: DEFAULT_WIDTH,
+            height: DEFAULT_HEIGHT,
+            format: FORMAT_INDEXED8,
+            vsync_period: DEFAULT_VSYNC_PERIOD,
+            vsync_counter: 0,
+            frame: 0,
+            status: 0,
+            palette_index: 0,
+            palette: default_palette(),
+        }
+    }
+
+    #[inline]
+    fn check_range(&self, offset: u32, size: usize) -> Result<usize, BusError> {
+        let off = offset as usize;
+        if off + size > self.data.len() {
+            return Err(BusError::OutOfBounds(offset));
+        }
+        Ok(off)
+    }
+
+    pub fn write8(&mut self, offset: u32, value: u8) -> Result<(), BusError> {
+        let off = self.check_range(offset, 1)?;
+        self.data[off] = value;
+        Ok(())
+    }
+
+    pub fn read8(&self, offset: u32) -> Result<u8, BusError> {
+        let off = self.check_range(offset, 1)?;
+        Ok(self.data[off])
+    }
+
+    pub fn write32(&mut self, offset: u32, value: u32) -> Result<(), BusError> {
+        let off = self.check_range(offset, 4)?;
+        let bytes = value.to_le_bytes();
+        self.data[off..off + 4].copy_from_slice(&bytes);
+        Ok(())
+    }
+
+    pub fn read32(&self, offset: u32) -> Result<u32, BusError> {
+        let off = self.check_range(offset, 4)?;
+        let bytes = <[u8; 4]>::try_from(&self.data[off..off + 4]).unwrap();
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    pub fn ctrl(&self) -> u32 {
+        self.ctrl
+    }
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+    pub fn format(&self) -> u32 {
+        self.format
+    }
+    pub fn vsync_period(&self) -> u32 {
+        self.vsync_period
+    }
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+    pub fn status(&self) -> u32 {
+        self.status
+    }
+    pub fn palette_index(&self) -> u32 {
+        self.palette_index
+    }
+    pub fn palette_data(&self) -> u32 {
+        self.palette[self.palette_index as usize]
+    }
+    pub fn irq(&self) -> bool {
+        self.ctrl & VSYNC_IRQ_ENABLED != 0 && self.status & STATUS_VSYNC != 0
+    }
+
+    fn bytes_per_pixel(format: u32) -> usize {
+        match format {
+            FORMAT_RGB565 => 2,
+            _ => 1,
+        }
+    }
+
+    /// Returns true when a mode with the given geometry isn't empty and fits into the pixel
+    /// memory
+    fn fits(&self, width: u32, height: u32, format: u32) -> bool {
+        width != 0 && height != 0 && width as usize * height as usize * Self::bytes_per_pixel(format) <= self.data.len()
+    }
+
+    pub fn set_ctrl(&mut self, ctrl: u32) {
+        println!("[fb] ctrl={:08x}", ctrl);
+        self.ctrl = ctrl;
+    }
+
+    /// Sets the width. A width of 0 or one that would not fit in the pixel memory is ignored.
+    pub fn set_width(&mut self, width: u32) {
+        if self.fits(width, self.height, self.format) {
+            println!("[fb] width={}", width);
+            self.width = width;
+        }
+    }
+
+    /// Sets the height. A height of 0 or one that would not fit in the pixel memory is ignored.
+    pub fn set_height(&mut self, height: u32) {
+        if self.fits(self.width, height, self.format) {
+            println!("[fb] height={}", height);
+            self.height = height;
+        }
+    }
+
+    /// Sets the pixel format. Unknown formats or formats that would not fit are ignored.
+    pub fn set_format(&mut self, format: u32) {
+        if matches!(format, FORMAT_INDEXED8 | FORMAT_RGB565) && self.fits(self.width, self.height, format) {
+            println!("[fb] format={}", format);
+            self.format = format;
+        }
+    }
+
+    pub fn set_vsync_period(&mut self, period: u32) {
+        println!("[fb] vsync_period={}", period);
+        self.vsync_period = period;
+        self.vsync_counter = 0;
+    }
+
+    /// Clears the status bits that are set in `value`
+    pub fn clear_status(&mut self, value: u32) {
+        self.status &= !value;
+    }
+
+    pub fn set_palette_index(&mut self, index: u32) {
+        self.palette_index = index % PALETTE_SIZE as u32;
+    }
+
+    pub fn set_palette_data(&mut self, rgb: u32) {
+        self.palette[self.palette_index as usize] = rgb & 0x00FF_FFFF;
+    }
+
+    /// Advances the vsync counter. Returns true when a vsync happened.
+    pub fn tick(&mut self) -> bool {
+        if self.ctrl & ENABLED == 0 || self.vsync_period == 0 {
+            return false;
+        }
+
+        self.vsync_counter += 1;
+        if self.vsync_counter < self.vsync_period {
+            return false;
+        }
+
+        self.vsync_counter = 0;
+        self.frame = self.frame.wrapping_add(1);
+        self.status |= STATUS_VSYNC;
+
+        true
+    }
+
+    /// Renders the framebuffer into an RGB frame
+    pub fn render(&self) -> Frame {
+        let (width, height) = (self.width as usize, self.height as usize);
+        let mut frame = Frame::new(width, height);
+
+        if self.ctrl & ENABLED == 0 {
+            return frame;
+        }
+
+        for y in 0..height {
+            for x in 0..width {
+                let pixel = y * width + x;
+                let rgb = match self.format {
+                    FORMAT_RGB565 => {
+                        let off = pixel * 2;
+                        rgb565_to_rgb(u16::from_le_bytes([self.data[off], self.data[off + 1]]))
+                    }
+                    _ => self.palette[self.data[pixel] as usize],
+                };
+                frame.set_pixel(x, y, rgb);
+            }
+        }
+
+        frame
+    }
+}
+
+fn rgb565_to_rgb(px: u16) -> u32 {
+    let r = ((px >> 11) & 0x1F) as u32;
+    let g = ((px >> 5) & 0x3F) as u32;
+    let b = (px & 0x1F) as u32;
+
+    // Scale up to 8 bits, replicating the high bits into the low bits
+    let r = (r << 3) | (r >> 2);
+    let g = (g << 2) | (g >> 4);
+    let b = (b << 3) | (b >> 2);
+
+    (r << 16) | (g << 8) | b
+}
+
+/// xterm style palette: 16 base colors, a 6x6x6 color cube and 24 grays
+fn default_palette() -> [u32; PALETTE_SIZE] {
+    const LEVELS: [u32; 6] = [0x00, 0x5F, 0x87, 0xAF, 0xD7, 0xFF];
+
+    let mut palette = [0; PALETTE_SIZE];
+    palette[..16].copy_from_slice(&CGA_PALETTE);
+
+    for i in 0..216 {
+        let (r, g, b) = (LEVELS[i / 36], LEVELS[(i / 6) % 6], LEVELS[i % 6]);
+        palette[16 + i] = (r << 16) | (g << 8) | b;
+    }
+
+    for i in 0..24 {
+        let level = 8 + i as u32 * 10;
+        palette[232 + i] = (level << 16) | (level << 8) | level;
+    }
+
+    palette
+}
+
+impl Snapshot for Framebuffer {
+    fn save(&self, w: &mut StateWriter) {
+        w.bytes(&self.data);
+        for value in [
+            self.ctrl,
+            self.width,
+            self.height,
+            self.format,
+            self.vsync_period,
+            self.vsync_counter,
+            self.frame,
+            self.status,
+            self.palette_index,
+        ] {
+            w.u32(value);
+        }
+        for &rgb in &self.palette {
+            w.u32(rgb);
+        }
+    }
+
+    fn restore(&mut self, r: &mut StateReader) -> io::Result<()> {
+        r.bytes_into("framebuffer memory", &mut self.data)?;
+        self.ctrl = r.u32()?;
+        self.width = r.u32()?;
+        self.height = r.u32()?;
+        self.format = r.u32()?;
+        self.vsync_period = r.u32()?;
+        self.vsync_counter = r.u32()?;
+        self.frame = r.u32()?;
+        self.status = r.u32()?;
+        self.palette_index = r.u32()? % PALETTE_SIZE as u32;
+        for rgb in self.palette.iter_mut() {
+            *rgb = r.u32()?;
+        }
+        if !matches!(self.format, FORMAT_INDEXED8 | FORMAT_RGB565) || !self.fits(self.width, self.height, self.format) {
+            return Err(invalid("invalid framebuffer mode in snapshot"));
+        }
+        Ok(())
+    }
+}
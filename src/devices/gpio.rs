@@ -0,0 +1,197 @@
+pub mod stimulus;
+
+use crate::machine::snapshot::{Snapshot, StateReader, StateWriter};
+use std::io;
+
+pub const PIN_COUNT: u32 = 32;
+
+/// 32 pin GPIO block. Every register holds one bit per pin.
+pub struct Gpio {
+    /// Pin direction (0 = input, 1 = output)
+    dir: u32,
+    /// Output latch
+    out: u32,
+    /// Levels driven onto the input pins from outside
+    inputs: u32,
+    /// Pins that can raise an interrupt
+    irq_enable: u32,
+    /// Interrupt type (0 = level, 1 = edge)
+    irq_type: u32,
+    /// Interrupt polarity (level: 0 = low, 1 = high, edge: 0 = falling, 1 = rising)
+    irq_polarity: u32,
+    /// Edge interrupts trigger on both edges, polarity is ignored
+    irq_any_edge: u32,
+    /// Pending interrupts
+    irq_status: u32,
+}
+
+impl Default for Gpio {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Gpio {
+    pub fn new() -> Self {
+        Self {
+            dir: 0,
+            out: 0,
+            inputs: 0,
+            irq_enable: 0,
+            irq_type: 0,
+            irq_polarity: 0,
+            irq_any_edge: 0,
+            irq_status: 0,
+        }
+    }
+
+    /// Puts the registers back to their power-on state. Input levels are driven from the
+    /// outside and are kept.
+    pub fn reset(&mut self) {
+        *self = Self {
+            inputs: self.inputs,
+            ..Self::new()
+        };
+    }
+
+    pub fn dir(&self) -> u32 {
+        self.dir
+    }
+    pub fn out(&self) -> u32 {
+        self.out
+    }
+    pub fn irq_enable(&self) -> u32 {
+        self.irq_enable
+    }
+    pub fn irq_type(&self) -> u32 {
+        self.irq_type
+    }
+    pub fn irq_polarity(&self) -> u32 {
+        self.irq_polarity
+    }
+    pub fn irq_any_edge(&self) -> u32 {
+        self.irq_any_edge
+    }
+    pub fn irq_status(&self) -> u32 {
+        self.irq_status
+    }
+    pub fn irq(&self) -> bool {
+        self.irq_status != 0
+    }
+
+    /// Pin levels as seen by firmware: output pins read back the output latch
+    pub fn levels(&self) -> u32 {
+        (self.inputs & !self.dir) | (self.out & self.dir)
+    }
+
+    /// Levels driven on output pins (input pins read as 0)
+    pub fn outputs(&self) -> u32 {
+        self.out & self.dir
+    }
+
+    pub fn set_dir(&mut self, dir: u32) {
+        self.dir = dir;
+    }
+
+    pub fn set_out(&mut self, out: u32) {
+        self.out = out;
+    }
+
+    pub fn set_out_bits(&mut self, mask: u32) {
+        self.out |= mask;
+    }
+
+    pub fn clear_out_bits(&mut self, mask: u32) {
+        self.out &= !mask;
+    }
+
+    pub fn toggle_out_bits(&mut self, mask: u32) {
+        self.out ^= mask;
+    }
+
+    pub fn set_irq_enable(&mut self, mask: u32) {
+        self.irq_enable = mask;
+    }
+
+    pub fn set_irq_type(&mut self, mask: u32) {
+        self.irq_type = mask;
+    }
+
+    pub fn set_irq_polarity(&mut self, mask: u32) {
+        self.irq_polarity = mask;
+    }
+
+    pub fn set_irq_any_edge(&mut self, mask: u32) {
+        self.irq_any_edge = mask;
+    }
+
+    /// Clears the pending interrupts that are set in `value`
+    pub fn clear_irq_status(&mut self, value: u32) {
+        self.irq_status &= !value;
+    }
+
+    /// Drives all input pins at once from the host side
+    pub fn set_inputs(&mut self, inputs: u32) {
+        let old = self.inputs & !self.dir;
+        let new = inputs & !self.dir;
+        let rising = !old & new;
+        let falling = old & !new;
+
+        let edge = self.irq_enable & self.irq_type;
+        let wanted = (rising & (self.irq_polarity | self.irq_any_edge))
+            | (falling & (!self.irq_polarity | self.irq_any_edge));
+        self.irq_status |= edge & wanted;
+
+        self.inputs = inputs;
+    }
+
+    /// Drives a single input pin from the host side. Pins past `PIN_COUNT` don't exist and are
+    /// ignored.
+    pub fn set_input(&mut self, pin: u32, high: bool) {
+        let Some(mask) = 1u32.checked_shl(pin) else {
+            return;
+        };
+        let inputs = if high {
+            self.inputs | mask
+        } else {
+            self.inputs & !mask
+        };
+        self.set_inputs(inputs);
+    }
+
+    pub fn tick(&mut self) {
+        // Level interrupts stay pending for as long as the level is active
+        let level = self.irq_enable & !self.irq_type & !self.dir;
+        let active = !(self.inputs ^ self.irq_polarity);
+        self.irq_status |= level & active;
+    }
+}
+
+impl Snapshot for Gpio {
+    fn save(&self, w: &mut StateWriter) {
+        for value in [
+            self.dir,
+            self.out,
+            self.inputs,
+            self.irq_enable,
+            self.irq_type,
+            self.irq_polarity,
+            self.irq_any_edge,
+            self.irq_status,
+        ] {
+            w.u32(value);
+        }
+    }
+
+    fn restore(&mut self, r: &mut StateReader) -> io::Result<()> {
+        self.dir = r.u32()?;
+        self.out = r.u32()?;
+        self.inputs = r.u32()?;
+        self.irq_enable = r.u32()?;
+        self.irq_type = r.u32()?;
+        self.irq_polarity = r.u32()?;
+        self.irq_any_edge = r.u32()?;
+        self.irq_status = r.u32()?;
+        Ok(())
+    }
+}
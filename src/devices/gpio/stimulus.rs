@@ -0,0 +1,102 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::devices::gpio::{Gpio, PIN_COUNT};
+
+/// A single input change scheduled at a given cycle
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StimulusEvent {
+    pub cycle: u64,
+    pub pin: u32,
+    pub high: bool,
+}
+
+/// Scripted GPIO input changes.
+///
+/// The file format has one event per line: `<cycle> <pin> <level>`, where level is 0 or 1.
+/// Empty lines and everything after a `#` are ignored.
+///
+/// ```text
+/// # press button on pin 3 for 500 cycles
+/// 1000  3  1
+/// 1500  3  0
+/// ```
+pub struct GpioStimulus {
+    /// Events, sorted by cycle
+    events: Vec<StimulusEvent>,
+    /// Index of the next event to apply
+    next: usize,
+}
+
+impl GpioStimulus {
+    /// Events on pins past `PIN_COUNT` are ignored when applied; `parse` rejects them
+    pub fn new(mut events: Vec<StimulusEvent>) -> Self {
+        events.sort_by_key(|e| e.cycle);
+        Self { events, next: 0 }
+    }
+
+    pub fn parse(src: &str) -> Result<Self, String> {
+        let mut events = Vec::new();
+
+        for (lineno, line) in src.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let err = |msg: &str| format!("line {}: {msg}: '{line}'", lineno + 1);
+
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            let [cycle, pin, level] = fields[..] else {
+                return Err(err("expected <cycle> <pin> <level>"));
+            };
+
+            let cycle = cycle.parse::<u64>().map_err(|_| err("invalid cycle"))?;
+            let pin = pin.parse::<u32>().map_err(|_| err("invalid pin"))?;
+            if pin >= PIN_COUNT {
+                return Err(err("pin out of range"));
+            }
+            let high = match level {
+                "0" => false,
+                "1" => true,
+                _ => return Err(err("level must be 0 or 1")),
+            };
+
+            events.push(StimulusEvent { cycle, pin, high });
+        }
+
+        Ok(Self::new(events))
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let src = fs::read_to_string(path)?;
+        Self::parse(&src).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Returns true when all events have been applied
+    pub fn finished(&self) -> bool {
+        self.next >= self.events.len()
+    }
+
+    /// Index of the next event to apply
+    pub fn position(&self) -> usize {
+        self.next
+    }
+
+    /// Continues from the event at `next`, e.g. after the machine went back in time
+    pub fn set_position(&mut self, next: usize) {
+        self.next = next.min(self.events.len());
+    }
+
+    /// Applies all events scheduled up to and including `cycle`
+    pub fn apply(&mut self, cycle: u64, gpio: &mut Gpio) {
+        while let Some(event) = self.events.get(self.next) {
+            if event.cycle > cycle {
+                break;
+            }
+            gpio.set_input(event.pin, event.high);
+            self.next += 1;
+        }
+    }
+}
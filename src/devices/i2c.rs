@@ -0,0 +1,206 @@
+pub mod eeprom;
+pub mod temp_sensor;
+
+use crate::machine::snapshot::{invalid, Snapshot, StateReader, StateWriter};
+use std::io;
+
+pub const ENABLED: u32 = 0x1; // 0 = controller off, commands are ignored
+
+// Command register bits. Set bits are carried out in this order: START, WRITE or READ, STOP.
+pub const CMD_START: u32 = 0x01; // (Repeated) start condition, then send the address byte in DATA
+pub const CMD_STOP: u32 = 0x02; // Stop condition
+pub const CMD_WRITE: u32 = 0x04; // Send the byte in DATA
+pub const CMD_READ: u32 = 0x08; // Receive a byte into DATA
+pub const CMD_NACK: u32 = 0x10; // With READ: answer the byte with NACK, ending the read
+
+// Status register bits
+pub const STATUS_BUS_ACTIVE: u32 = 0x1; // Between a start and a stop condition
+pub const STATUS_NACK: u32 = 0x2; // The slave did not acknowledge the last address or data byte
+
+/// A simulated I2C slave
+pub trait I2cDevice: Send {
+    /// Called when the device is addressed after a (repeated) start condition
+    fn start(&mut self, read: bool) {
+        let _ = read;
+    }
+    /// Called on the stop condition
+    fn stop(&mut self) {}
+    /// Receives a byte from the master. Returns false to NACK it.
+    fn write(&mut self, byte: u8) -> bool;
+    /// Sends a byte to the master
+    fn read(&mut self) -> u8;
+}
+
+/// I2C master controller. Slaves are attached at their 7 bit address; every command
+/// completes immediately.
+pub struct I2cController {
+    /// Attached slaves and their addresses
+    devices: Vec<(u8, Box<dyn I2cDevice>)>,
+    /// Control register
+    ctrl: u32,
+    /// Data register, byte to send or the last received byte
+    data: u8,
+    /// Status flags
+    status: u32,
+    /// Index of the addressed device
+    current: Option<usize>,
+    /// The last byte read was answered with NACK: the slave sends nothing more until the
+    /// next start condition
+    read_ended: bool,
+}
+
+impl Default for I2cController {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl I2cController {
+    pub fn new() -> Self {
+        Self {
+            devices: Vec::new(),
+            ctrl: 0,
+            data: 0,
+            status: 0,
+            current: None,
+            read_ended: false,
+        }
+    }
+
+    /// Attaches a device at a 7 bit address, replacing the one that was there
+    pub fn attach(&mut self, addr: u8, device: Box<dyn I2cDevice>) {
+        let addr = addr & 0x7F;
+        self.devices.retain(|(a, _)| *a != addr);
+        self.devices.push((addr, device));
+        self.current = None;
+    }
+
+    /// Detaches the device at a 7 bit address
+    pub fn detach(&mut self, addr: u8) -> Option<Box<dyn I2cDevice>> {
+        let idx = self.devices.iter().position(|(a, _)| *a == addr & 0x7F)?;
+        self.current = None;
+        Some(self.devices.remove(idx).1)
+    }
+
+    /// True when any device is attached
+    pub fn has_devices(&self) -> bool {
+        !self.devices.is_empty()
+    }
+
+    /// Puts the registers back to their power-on state. Attached devices stay attached.
+    pub fn reset(&mut self) {
+        self.stop();
+        self.ctrl = 0;
+        self.data = 0;
+        self.status = 0;
+    }
+
+    pub fn ctrl(&self) -> u32 {
+        self.ctrl
+    }
+    pub fn data(&self) -> u32 {
+        self.data as u32
+    }
+    pub fn status(&self) -> u32 {
+        self.status
+    }
+
+    pub fn set_ctrl(&mut self, ctrl: u32) {
+        println!("[i2c] ctrl={:08x}", ctrl);
+        self.ctrl = ctrl;
+    }
+
+    pub fn set_data(&mut self, value: u32) {
+        self.data = value as u8;
+    }
+
+    /// Carries out a command, see the CMD_* bits. A READ after a NACKed byte reads 0xFF, the
+    /// released bus, until the next start condition.
+    pub fn command(&mut self, cmd: u32) {
+        if self.ctrl & ENABLED == 0 {
+            return;
+        }
+
+        if cmd & CMD_START != 0 {
+            self.start();
+        }
+
+        if cmd & CMD_WRITE != 0 {
+            let ack = match self.current {
+                Some(idx) => self.devices[idx].1.write(self.data),
+                None => false,
+            };
+            self.set_nack(!ack);
+        } else if cmd & CMD_READ != 0 {
+            self.data = match self.current {
+                Some(idx) if !self.read_ended => self.devices[idx].1.read(),
+                _ => 0xFF,
+            };
+            self.read_ended |= cmd & CMD_NACK != 0;
+        }
+
+        if cmd & CMD_STOP != 0 {
+            self.stop();
+        }
+    }
+
+    /// Start condition followed by the address byte in the data register
+    fn start(&mut self) {
+        let addr = self.data >> 1;
+        let read = self.data & 1 != 0;
+
+        self.status |= STATUS_BUS_ACTIVE;
+        self.read_ended = false;
+        self.current = self.devices.iter().position(|(a, _)| *a == addr);
+        match self.current {
+            Some(idx) => self.devices[idx].1.start(read),
+            None => println!("[i2c] no device at 0x{:02X}", addr),
+        }
+        self.set_nack(self.current.is_none());
+    }
+
+    fn stop(&mut self) {
+        if let Some(idx) = self.current.take() {
+            self.devices[idx].1.stop();
+        }
+        self.status &= !STATUS_BUS_ACTIVE;
+        self.read_ended = false;
+    }
+
+    fn set_nack(&mut self, nack: bool) {
+        if nack {
+            self.status |= STATUS_NACK;
+        } else {
+            self.status &= !STATUS_NACK;
+        }
+    }
+}
+
+/// Attached slaves aren't part of the state. The addressed slave is saved by address and
+/// must be attached.
+impl Snapshot for I2cController {
+    fn save(&self, w: &mut StateWriter) {
+        w.u32(self.ctrl);
+        w.u8(self.data);
+        w.u32(self.status);
+        w.bool(self.current.is_some());
+        w.u8(self.current.map_or(0, |idx| self.devices[idx].0));
+        w.bool(self.read_ended);
+    }
+
+    fn restore(&mut self, r: &mut StateReader) -> io::Result<()> {
+        self.ctrl = r.u32()?;
+        self.data = r.u8()?;
+        self.status = r.u32()?;
+        let addressed = r.bool()?;
+        let addr = r.u8()?;
+        self.current = if addressed {
+            let idx = self.devices.iter().position(|(a, _)| *a == addr);
+            Some(idx.ok_or_else(|| invalid(format!("snapshot addresses I2C device 0x{addr:02X}, which isn't attached")))?)
+        } else {
+            None
+        };
+        self.read_ended = r.bool()?;
+        Ok(())
+    }
+}
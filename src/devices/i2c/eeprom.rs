@@ -0,0 +1,74 @@
+use crate::devices::i2c::I2cDevice;
+
+/// 24-series I2C EEPROM. Parts up to 256 bytes take a one byte word address, larger parts
+/// take two bytes (high byte first). Writes wrap around within a page.
+pub struct I2cEeprom {
+    data: Vec<u8>,
+    page_size: usize,
+    /// Current word address
+    addr: usize,
+    /// Address bytes still expected after a write start
+    addr_bytes_left: u8,
+}
+
+impl I2cEeprom {
+    /// Creates an erased EEPROM (all bytes 0xFF)
+    pub fn new(size: usize, page_size: usize) -> Self {
+        Self::from_bytes(vec![0xFF; size], page_size)
+    }
+
+    /// Creates an EEPROM with the given contents
+    pub fn from_bytes(data: Vec<u8>, page_size: usize) -> Self {
+        Self {
+            data,
+            page_size: page_size.max(1),
+            addr: 0,
+            addr_bytes_left: 0,
+        }
+    }
+
+    pub fn contents(&self) -> &[u8] {
+        &self.data
+    }
+
+    fn addr_bytes(&self) -> u8 {
+        if self.data.len() > 256 { 2 } else { 1 }
+    }
+}
+
+impl I2cDevice for I2cEeprom {
+    fn start(&mut self, read: bool) {
+        // A write transfer starts with the word address, a read continues at the current one
+        if !read {
+            self.addr_bytes_left = self.addr_bytes();
+            self.addr = 0;
+        }
+    }
+
+    fn write(&mut self, byte: u8) -> bool {
+        if self.data.is_empty() {
+            return false;
+        }
+
+        if self.addr_bytes_left > 0 {
+            self.addr = ((self.addr << 8) | byte as usize) % self.data.len();
+            self.addr_bytes_left -= 1;
+            return true;
+        }
+
+        self.data[self.addr] = byte;
+        let page = self.addr - self.addr % self.page_size;
+        self.addr = (page + (self.addr + 1) % self.page_size) % self.data.len();
+        true
+    }
+
+    fn read(&mut self) -> u8 {
+        if self.data.is_empty() {
+            return 0xFF;
+        }
+
+        let byte = self.data[self.addr];
+        self.addr = (self.addr + 1) % self.data.len();
+        byte
+    }
+}
@@ -0,0 +1,99 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicI32, Ordering};
+
+use crate::devices::i2c::I2cDevice;
+
+// Register pointers
+pub const REG_TEMP: u8 = 0x00; // R    - Temperature, 9 bit two's complement in 0.5 degree steps
+pub const REG_CONFIG: u8 = 0x01; // R/W  - Configuration, stored but not interpreted
+pub const REG_THYST: u8 = 0x02; // R/W  - Hysteresis
+pub const REG_TOS: u8 = 0x03; // R/W  - Overtemperature shutdown
+
+/// LM75 style temperature sensor. The first byte written after a start selects the register,
+/// further bytes are written into it (high byte first). Reads return the selected register.
+///
+/// The temperature is set from the host through the handle returned by `handle()`.
+pub struct TempSensor {
+    /// Temperature in millidegrees Celsius, shared with the host
+    temperature: Arc<AtomicI32>,
+    /// Selected register
+    pointer: u8,
+    config: u8,
+    thyst: u16,
+    tos: u16,
+    /// Byte index within the selected register
+    index: usize,
+    /// True while the next written byte is the register pointer
+    expect_pointer: bool,
+}
+
+impl TempSensor {
+    pub fn new(millicelsius: i32) -> Self {
+        Self {
+            temperature: Arc::new(AtomicI32::new(millicelsius)),
+            pointer: REG_TEMP,
+            config: 0,
+            thyst: 75 << 8,
+            tos: 80 << 8,
+            index: 0,
+            expect_pointer: false,
+        }
+    }
+
+    /// Returns a handle to change the measured temperature (in millidegrees Celsius)
+    pub fn handle(&self) -> Arc<AtomicI32> {
+        self.temperature.clone()
+    }
+
+    /// Temperature register value: 0.5 degree steps, left aligned in 16 bits
+    fn temp_register(&self) -> u16 {
+        let half_degrees = self.temperature.load(Ordering::Relaxed) / 500;
+        ((half_degrees as i16) << 7) as u16
+    }
+
+    fn register(&self) -> [u8; 2] {
+        match self.pointer {
+            REG_TEMP => self.temp_register().to_be_bytes(),
+            REG_CONFIG => [self.config, self.config],
+            REG_THYST => self.thyst.to_be_bytes(),
+            _ => self.tos.to_be_bytes(),
+        }
+    }
+}
+
+impl I2cDevice for TempSensor {
+    fn start(&mut self, read: bool) {
+        self.index = 0;
+        self.expect_pointer = !read;
+    }
+
+    fn write(&mut self, byte: u8) -> bool {
+        if self.expect_pointer {
+            self.pointer = byte & 0x3;
+            self.expect_pointer = false;
+            return true;
+        }
+
+        match (self.pointer, self.index) {
+            (REG_CONFIG, 0) => self.config = byte,
+            (REG_THYST, i) if i < 2 => self.thyst = set_byte(self.thyst, i, byte),
+            (REG_TOS, i) if i < 2 => self.tos = set_byte(self.tos, i, byte),
+            _ => {}
+        }
+        self.index += 1;
+        true
+    }
+
+    fn read(&mut self) -> u8 {
+        let byte = self.register()[self.index % 2];
+        self.index += 1;
+        byte
+    }
+}
+
+/// Replaces byte `index` of a big endian register
+fn set_byte(reg: u16, index: usize, byte: u8) -> u16 {
+    let mut bytes = reg.to_be_bytes();
+    bytes[index] = byte;
+    u16::from_be_bytes(bytes)
+}
@@ -0,0 +1,158 @@
+pub mod host;
+pub mod scancode;
+pub mod script;
+
+use crate::machine::snapshot::{Snapshot, StateReader, StateWriter};
+use std::collections::VecDeque;
+use std::io;
+
+pub const ENABLED: u32 = 0x1; // 0 = keyboard off, key events are dropped
+pub const IRQ_ENABLED: u32 = 0x2; // 0 = no IRQ, 1 = IRQ while scan codes are waiting
+
+// Status register bits
+pub const STATUS_DATA: u32 = 0x1; // Scan codes are waiting in the FIFO
+pub const STATUS_OVERFLOW: u32 = 0x2; // Key events were dropped because the buffers were full (W1C)
+
+/// Depth of the scan code FIFO
+pub const FIFO_SIZE: usize = 16;
+/// Scan codes the keyboard itself buffers while the FIFO is full
+pub const TYPEAHEAD_SIZE: usize = 1024;
+
+/// PS/2 style keyboard controller. Key events are turned into scan code set 2 sequences,
+/// buffered in the keyboard and moved into the controller FIFO one byte per cycle, for the
+/// program to read one byte at a time.
+pub struct Keyboard {
+    /// Scan codes waiting to be read
+    fifo: VecDeque<u8>,
+    /// Scan codes buffered in the keyboard, waiting for room in the FIFO
+    typeahead: VecDeque<u8>,
+    /// Control register
+    ctrl: u32,
+    /// Sticky status flags
+    status: u32,
+}
+
+impl Default for Keyboard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Keyboard {
+    pub fn new() -> Self {
+        Self {
+            fifo: VecDeque::with_capacity(FIFO_SIZE),
+            typeahead: VecDeque::new(),
+            ctrl: 0,
+            status: 0,
+        }
+    }
+
+    pub fn ctrl(&self) -> u32 {
+        self.ctrl
+    }
+    pub fn status(&self) -> u32 {
+        let data = if self.fifo.is_empty() { 0 } else { STATUS_DATA };
+        self.status | data
+    }
+    pub fn irq(&self) -> bool {
+        self.ctrl & IRQ_ENABLED != 0 && !self.fifo.is_empty()
+    }
+
+    pub fn set_ctrl(&mut self, ctrl: u32) {
+        println!("[kbd] ctrl={:08x}", ctrl);
+        self.ctrl = ctrl;
+        if ctrl & ENABLED == 0 {
+            self.fifo.clear();
+            self.typeahead.clear();
+        }
+    }
+
+    /// Clears the status bits that are set in `value`
+    pub fn clear_status(&mut self, value: u32) {
+        self.status &= !(value & STATUS_OVERFLOW);
+    }
+
+    /// Pops the next scan code, 0 when the FIFO is empty
+    pub fn read_data(&mut self) -> u32 {
+        self.fifo.pop_front().unwrap_or(0) as u32
+    }
+
+    /// Queues raw scan codes. A sequence that doesn't fit is dropped as a whole.
+    pub fn push_codes(&mut self, codes: &[u8]) {
+        if self.ctrl & ENABLED == 0 {
+            return;
+        }
+
+        if self.typeahead.len() + codes.len() > TYPEAHEAD_SIZE {
+            self.status |= STATUS_OVERFLOW;
+            return;
+        }
+        self.typeahead.extend(codes);
+    }
+
+    /// Moves a buffered scan code into the FIFO when there is room
+    pub fn tick(&mut self) {
+        if self.fifo.len() < FIFO_SIZE
+            && let Some(code) = self.typeahead.pop_front()
+        {
+            self.fifo.push_back(code);
+        }
+    }
+
+    /// Presses a key, by name (see `scancode`). Returns false for unknown keys.
+    pub fn press(&mut self, key: &str) -> bool {
+        let Some(code) = scancode::make_code(key) else {
+            return false;
+        };
+        self.push_codes(code);
+        true
+    }
+
+    /// Releases a key, by name. Returns false for unknown keys.
+    pub fn release(&mut self, key: &str) -> bool {
+        let Some(code) = scancode::break_code(key) else {
+            return false;
+        };
+        self.push_codes(&code);
+        true
+    }
+
+    /// Presses and releases the keys that type `c`, holding shift when needed. Returns false
+    /// when the character can't be typed.
+    pub fn type_char(&mut self, c: char) -> bool {
+        let Some((key, shift)) = scancode::key_for_char(c) else {
+            return false;
+        };
+
+        if shift {
+            self.press("shift");
+        }
+        self.press(key);
+        self.release(key);
+        if shift {
+            self.release("shift");
+        }
+        true
+    }
+}
+
+impl Snapshot for Keyboard {
+    fn save(&self, w: &mut StateWriter) {
+        for queue in [&self.fifo, &self.typeahead] {
+            w.bytes(&queue.iter().copied().collect::<Vec<_>>());
+        }
+        w.u32(self.ctrl);
+        w.u32(self.status);
+    }
+
+    fn restore(&mut self, r: &mut StateReader) -> io::Result<()> {
+        let fifo = r.bytes("keyboard FIFO", FIFO_SIZE)?;
+        self.fifo = fifo.iter().copied().collect();
+        let typeahead = r.bytes("keyboard typeahead", TYPEAHEAD_SIZE)?;
+        self.typeahead = typeahead.iter().copied().collect();
+        self.ctrl = r.u32()?;
+        self.status = r.u32()?;
+        Ok(())
+    }
+}
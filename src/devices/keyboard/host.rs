@@ -0,0 +1,100 @@
+use std::io;
+use std::os::unix::io::BorrowedFd;
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
+use nix::sys::termios::{self, LocalFlags, SetArg, Termios};
+use nix::unistd::read;
+use crate::devices::keyboard::Keyboard;
+
+/// Feeds the keyboard from the host terminal. Puts stdin into non-blocking, non-canonical
+/// mode without echo; the original settings are restored on drop.
+pub struct HostKeyboard {
+    /// Terminal settings to restore, None when stdin is not a terminal
+    saved_termios: Option<Termios>,
+    /// File status flags to restore
+    saved_flags: OFlag,
+}
+
+impl HostKeyboard {
+    pub fn new() -> io::Result<Self> {
+        let stdin = Self::stdin();
+
+        let saved_termios = match termios::tcgetattr(stdin) {
+            Ok(saved) => {
+                let mut raw = saved.clone();
+                raw.local_flags &= !(LocalFlags::ICANON | LocalFlags::ECHO);
+                termios::tcsetattr(stdin, SetArg::TCSANOW, &raw).map_err(io::Error::from)?;
+                Some(saved)
+            }
+            // Not a terminal (e.g. a pipe), read it as it is
+            Err(_) => None,
+        };
+
+        let flags_raw = fcntl(stdin, FcntlArg::F_GETFL).map_err(io::Error::from)?;
+        let saved_flags = OFlag::from_bits_truncate(flags_raw);
+        fcntl(stdin, FcntlArg::F_SETFL(saved_flags | OFlag::O_NONBLOCK)).map_err(io::Error::from)?;
+
+        Ok(Self {
+            saved_termios,
+            saved_flags,
+        })
+    }
+
+    fn stdin() -> BorrowedFd<'static> {
+        // Stdin stays open for the lifetime of the process
+        unsafe { BorrowedFd::borrow_raw(0) }
+    }
+
+    fn read_byte(&self) -> Option<u8> {
+        let mut buf = [0u8; 1];
+        match read(Self::stdin(), &mut buf) {
+            Ok(1) => Some(buf[0]),
+            _ => None, // EOF, no data (EAGAIN) or an error
+        }
+    }
+
+    /// Turns everything typed since the last poll into key events
+    pub fn poll(&mut self, keyboard: &mut Keyboard) {
+        type_input(keyboard, &self.read_input());
+    }
+
+    /// Returns everything typed since the last call
+    pub fn read_input(&mut self) -> Vec<u8> {
+        let mut input = Vec::new();
+        while let Some(byte) = self.read_byte() {
+            input.push(byte);
+        }
+        input
+    }
+}
+
+/// Turns bytes read from the terminal into key events
+pub fn type_input(keyboard: &mut Keyboard, input: &[u8]) {
+    let mut bytes = input.iter().copied();
+    while let Some(byte) = bytes.next() {
+        if byte != 0x1B {
+            keyboard.type_char(byte as char);
+            continue;
+        }
+
+        // Cursor keys arrive as ESC [ A..D, a lone ESC is the escape key
+        let key = match (bytes.next(), bytes.next()) {
+            (Some(b'['), Some(b'A')) => "up",
+            (Some(b'['), Some(b'B')) => "down",
+            (Some(b'['), Some(b'C')) => "right",
+            (Some(b'['), Some(b'D')) => "left",
+            _ => "escape",
+        };
+        keyboard.press(key);
+        keyboard.release(key);
+    }
+}
+
+impl Drop for HostKeyboard {
+    fn drop(&mut self) {
+        let stdin = Self::stdin();
+        let _ = fcntl(stdin, FcntlArg::F_SETFL(self.saved_flags));
+        if let Some(saved) = &self.saved_termios {
+            let _ = termios::tcsetattr(stdin, SetArg::TCSANOW, saved);
+        }
+    }
+}
@@ -0,0 +1,76 @@
+/// Prefix of extended scan codes
+pub const EXTENDED: u8 = 0xE0;
+/// Prefix of break (key release) codes
+pub const BREAK: u8 = 0xF0;
+
+/// Scan code set 2 make codes, by key name
+const KEYS: &[(&str, &[u8])] = &[
+    ("a", &[0x1C]), ("b", &[0x32]), ("c", &[0x21]), ("d", &[0x23]), ("e", &[0x24]),
+    ("f", &[0x2B]), ("g", &[0x34]), ("h", &[0x33]), ("i", &[0x43]), ("j", &[0x3B]),
+    ("k", &[0x42]), ("l", &[0x4B]), ("m", &[0x3A]), ("n", &[0x31]), ("o", &[0x44]),
+    ("p", &[0x4D]), ("q", &[0x15]), ("r", &[0x2D]), ("s", &[0x1B]), ("t", &[0x2C]),
+    ("u", &[0x3C]), ("v", &[0x2A]), ("w", &[0x1D]), ("x", &[0x22]), ("y", &[0x35]),
+    ("z", &[0x1A]),
+    ("0", &[0x45]), ("1", &[0x16]), ("2", &[0x1E]), ("3", &[0x26]), ("4", &[0x25]),
+    ("5", &[0x2E]), ("6", &[0x36]), ("7", &[0x3D]), ("8", &[0x3E]), ("9", &[0x46]),
+    ("`", &[0x0E]), ("-", &[0x4E]), ("=", &[0x55]), ("[", &[0x54]), ("]", &[0x5B]),
+    ("\\", &[0x5D]), (";", &[0x4C]), ("'", &[0x52]), (",", &[0x41]), (".", &[0x49]),
+    ("/", &[0x4A]),
+    ("space", &[0x29]), ("enter", &[0x5A]), ("backspace", &[0x66]), ("tab", &[0x0D]),
+    ("escape", &[0x76]), ("capslock", &[0x58]),
+    ("shift", &[0x12]), ("rshift", &[0x59]), ("ctrl", &[0x14]), ("alt", &[0x11]),
+    ("rctrl", &[EXTENDED, 0x14]), ("ralt", &[EXTENDED, 0x11]),
+    ("f1", &[0x05]), ("f2", &[0x06]), ("f3", &[0x04]), ("f4", &[0x0C]), ("f5", &[0x03]),
+    ("f6", &[0x0B]), ("f7", &[0x83]), ("f8", &[0x0A]), ("f9", &[0x01]), ("f10", &[0x09]),
+    ("f11", &[0x78]), ("f12", &[0x07]),
+    ("up", &[EXTENDED, 0x75]), ("down", &[EXTENDED, 0x72]), ("left", &[EXTENDED, 0x6B]),
+    ("right", &[EXTENDED, 0x74]), ("home", &[EXTENDED, 0x6C]), ("end", &[EXTENDED, 0x69]),
+    ("pageup", &[EXTENDED, 0x7D]), ("pagedown", &[EXTENDED, 0x7A]),
+    ("insert", &[EXTENDED, 0x70]), ("delete", &[EXTENDED, 0x71]),
+];
+
+/// Characters that are typed with shift held, and the key they are on
+const SHIFTED: &[(char, &str)] = &[
+    ('~', "`"), ('!', "1"), ('@', "2"), ('#', "3"), ('$', "4"), ('%', "5"), ('^', "6"),
+    ('&', "7"), ('*', "8"), ('(', "9"), (')', "0"), ('_', "-"), ('+', "="), ('{', "["),
+    ('}', "]"), ('|', "\\"), (':', ";"), ('"', "'"), ('<', ","), ('>', "."), ('?', "/"),
+];
+
+/// Returns the make code of a key, by name (case insensitive)
+pub fn make_code(key: &str) -> Option<&'static [u8]> {
+    let key = key.to_ascii_lowercase();
+    KEYS.iter().find(|(name, _)| *name == key).map(|(_, code)| *code)
+}
+
+/// Returns the break code of a key, by name (case insensitive)
+pub fn break_code(key: &str) -> Option<Vec<u8>> {
+    let code = make_code(key)?;
+    Some(match code {
+        [EXTENDED, rest @ ..] => [&[EXTENDED, BREAK], rest].concat(),
+        _ => [&[BREAK], code].concat(),
+    })
+}
+
+/// Returns the key that types a character, and whether shift has to be held
+pub fn key_for_char(c: char) -> Option<(&'static str, bool)> {
+    if let Some((_, key)) = SHIFTED.iter().find(|(ch, _)| *ch == c) {
+        return Some((key, true));
+    }
+
+    let shift = c.is_ascii_uppercase();
+    let lower = c.to_ascii_lowercase();
+    let name = match lower {
+        ' ' => "space",
+        '\n' | '\r' => "enter",
+        '\t' => "tab",
+        '\x08' | '\x7F' => "backspace",
+        '\x1B' => "escape",
+        _ => {
+            let mut buf = [0u8; 4];
+            let s = lower.encode_utf8(&mut buf);
+            KEYS.iter().find(|(name, _)| *name == s)?.0
+        }
+    };
+
+    Some((name, shift))
+}
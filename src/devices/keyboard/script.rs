@@ -0,0 +1,131 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::devices::keyboard::{Keyboard, scancode};
+
+/// What happens to the keyboard at a scripted cycle
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyAction {
+    Down(String),
+    Up(String),
+    Type(String),
+}
+
+/// A key action scheduled at a given cycle
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub cycle: u64,
+    pub action: KeyAction,
+}
+
+/// Scripted key events for headless runs.
+///
+/// The file format has one event per line: `<cycle> <key> <down|up>` presses or releases a
+/// key by name (see `scancode`), `<cycle> type <text>` types the rest of the line. Empty lines
+/// and lines starting with `#` are ignored.
+///
+/// ```text
+/// # log in
+/// 1000  type  root
+/// 1200  enter down
+/// 1210  enter up
+/// ```
+pub struct KeyScript {
+    /// Events, sorted by cycle
+    events: Vec<KeyEvent>,
+    /// Index of the next event to apply
+    next: usize,
+}
+
+impl KeyScript {
+    pub fn new(mut events: Vec<KeyEvent>) -> Self {
+        events.sort_by_key(|e| e.cycle);
+        Self { events, next: 0 }
+    }
+
+    pub fn parse(src: &str) -> Result<Self, String> {
+        let mut events = Vec::new();
+
+        for (lineno, line) in src.lines().enumerate() {
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+
+            let err = |msg: &str| format!("line {}: {msg}: '{trimmed}'", lineno + 1);
+
+            let (cycle, rest) = trimmed.split_once(char::is_whitespace).ok_or_else(|| err("expected <cycle> <key> <down|up>"))?;
+            let cycle = cycle.parse::<u64>().map_err(|_| err("invalid cycle"))?;
+            let rest = rest.trim_start();
+
+            let action = match rest.split_once(char::is_whitespace) {
+                Some(("type", text)) => {
+                    let text = text.trim_start();
+                    if let Some(c) = text.chars().find(|&c| scancode::key_for_char(c).is_none()) {
+                        return Err(err(&format!("can't type '{}'", c.escape_default())));
+                    }
+                    KeyAction::Type(text.to_string())
+                }
+                Some((key, state)) => {
+                    if scancode::make_code(key).is_none() {
+                        return Err(err("unknown key"));
+                    }
+                    match state.trim() {
+                        "down" => KeyAction::Down(key.to_string()),
+                        "up" => KeyAction::Up(key.to_string()),
+                        _ => return Err(err("state must be down or up")),
+                    }
+                }
+                None => return Err(err("expected <cycle> <key> <down|up>")),
+            };
+
+            events.push(KeyEvent { cycle, action });
+        }
+
+        Ok(Self::new(events))
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let src = fs::read_to_string(path)?;
+        Self::parse(&src).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Returns true when all events have been applied
+    pub fn finished(&self) -> bool {
+        self.next >= self.events.len()
+    }
+
+    /// Index of the next event to apply
+    pub fn position(&self) -> usize {
+        self.next
+    }
+
+    /// Continues from the event at `next`, e.g. after the machine went back in time
+    pub fn set_position(&mut self, next: usize) {
+        self.next = next.min(self.events.len());
+    }
+
+    /// Applies all events scheduled up to and including `cycle`
+    pub fn apply(&mut self, cycle: u64, keyboard: &mut Keyboard) {
+        while let Some(event) = self.events.get(self.next) {
+            if event.cycle > cycle {
+                break;
+            }
+            match &event.action {
+                KeyAction::Down(key) => {
+                    keyboard.press(key);
+                }
+                KeyAction::Up(key) => {
+                    keyboard.release(key);
+                }
+                KeyAction::Type(text) => {
+                    for c in text.chars() {
+                        keyboard.type_char(c);
+                    }
+                }
+            }
+            self.next += 1;
+        }
+    }
+}
@@ -0,0 +1,267 @@
+pub mod pcap;
+pub mod socket;
+
+use std::collections::VecDeque;
+use std::io;
+
+use crate::machine::snapshot::{Snapshot, StateReader, StateWriter};
+
+pub const ENABLED: u32 = 0x1; // 0 = NIC off, received frames are dropped
+pub const RX_IRQ_ENABLED: u32 = 0x2; // 0 = no IRQ, 1 = IRQ when a frame was received
+pub const TX_IRQ_ENABLED: u32 = 0x4; // 0 = no IRQ, 1 = IRQ when a frame was sent
+
+// Status register bits (all write 1 to clear)
+pub const STATUS_RX_DONE: u32 = 0x1; // A frame was written into the RX ring
+pub const STATUS_TX_DONE: u32 = 0x2; // A frame from the TX ring was sent
+pub const STATUS_RX_DROPPED: u32 = 0x4; // A frame was dropped, no RX descriptor was free
+pub const STATUS_ERROR: u32 = 0x8; // A descriptor or buffer wasn't accessible on the bus
+
+// Descriptors are two words: the buffer address, then the flags and length word
+pub const DESC_SIZE: u32 = 8;
+pub const DESC_OWN: u32 = 0x8000_0000; // Set by the program to hand the descriptor to the NIC
+pub const DESC_ERROR: u32 = 0x4000_0000; // Frame truncated (RX) or too large (TX)
+pub const DESC_LEN_MASK: u32 = 0xFFFF; // TX: frame length, RX: buffer size, then frame length
+
+/// Largest frame the NIC sends or receives (Ethernet without FCS)
+pub const MAX_FRAME_SIZE: usize = 1514;
+pub const DEFAULT_MAC: [u8; 6] = [0x02, 0x4E, 0x56, 0x32, 0x01, 0x00];
+
+/// Host side of the NIC. Frames are whole Ethernet frames without FCS.
+pub trait NetBackend: Send {
+    /// Sends a frame from the emulated machine
+    fn send(&mut self, frame: &[u8]);
+    /// Returns the next frame for the emulated machine, if one is waiting
+    fn recv(&mut self) -> Option<Vec<u8>>;
+}
+
+/// Backend that hands every frame sent straight back to the NIC
+#[derive(Default)]
+pub struct Loopback {
+    frames: VecDeque<Vec<u8>>,
+}
+
+impl Loopback {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl NetBackend for Loopback {
+    fn send(&mut self, frame: &[u8]) {
+        self.frames.push_back(frame.to_vec());
+    }
+
+    fn recv(&mut self) -> Option<Vec<u8>> {
+        self.frames.pop_front()
+    }
+}
+
+/// A descriptor ring in memory
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Ring {
+    /// Address of the first descriptor
+    pub base: u32,
+    /// Number of descriptors, 0 disables the ring
+    pub count: u32,
+    /// Descriptor the NIC looks at next
+    pub index: u32,
+}
+
+impl Ring {
+    /// Address of the current descriptor, None when the ring isn't set up
+    pub fn desc_addr(&self) -> Option<u32> {
+        if self.count == 0 {
+            return None;
+        }
+        Some(self.base.wrapping_add(self.index * DESC_SIZE))
+    }
+
+    pub fn advance(&mut self) {
+        self.index = (self.index + 1) % self.count.max(1);
+    }
+}
+
+/// Network interface. Frames are moved between descriptor rings in RAM and a host backend.
+///
+/// To send, the program fills a TX descriptor (buffer address, frame length), sets
+/// `DESC_OWN` and writes the TX poll register; the NIC sends every descriptor it owns and
+/// clears `DESC_OWN`. For receiving, the program hands RX descriptors with the buffer size
+/// to the NIC; received frames are written into the next owned descriptor, whose length is
+/// replaced by the frame length before `DESC_OWN` is cleared.
+pub struct Nic {
+    /// Host side, None when no cable is plugged in
+    backend: Option<Box<dyn NetBackend>>,
+    /// Station address
+    mac: [u8; 6],
+    /// Control register
+    ctrl: u32,
+    /// Sticky status flags
+    status: u32,
+    pub tx: Ring,
+    pub rx: Ring,
+    /// Set by a write to the TX poll register, cleared once the TX ring was processed
+    tx_poll: bool,
+    /// Frames dropped because no RX descriptor was free
+    rx_dropped: u32,
+}
+
+impl Default for Nic {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Nic {
+    pub fn new() -> Self {
+        Self {
+            backend: None,
+            mac: DEFAULT_MAC,
+            ctrl: 0,
+            status: 0,
+            tx: Ring::default(),
+            rx: Ring::default(),
+            tx_poll: false,
+            rx_dropped: 0,
+        }
+    }
+
+    /// Connects the host side, replacing the previous backend
+    pub fn attach(&mut self, backend: Box<dyn NetBackend>) {
+        self.backend = Some(backend);
+    }
+
+    /// Disconnects the host side. Frames sent afterwards are dropped.
+    pub fn detach(&mut self) -> Option<Box<dyn NetBackend>> {
+        self.backend.take()
+    }
+
+    /// Puts the registers back to their power-on state. The backend and the station address
+    /// are kept.
+    pub fn reset(&mut self) {
+        self.ctrl = 0;
+        self.status = 0;
+        self.tx = Ring::default();
+        self.rx = Ring::default();
+        self.tx_poll = false;
+        self.rx_dropped = 0;
+    }
+
+    pub fn ctrl(&self) -> u32 {
+        self.ctrl
+    }
+    pub fn status(&self) -> u32 {
+        self.status
+    }
+    pub fn rx_dropped(&self) -> u32 {
+        self.rx_dropped
+    }
+    pub fn mac(&self) -> [u8; 6] {
+        self.mac
+    }
+    /// Low four bytes of the station address, first byte in bits 0-7
+    pub fn mac_lo(&self) -> u32 {
+        u32::from_le_bytes([self.mac[0], self.mac[1], self.mac[2], self.mac[3]])
+    }
+    /// High two bytes of the station address
+    pub fn mac_hi(&self) -> u32 {
+        u32::from_le_bytes([self.mac[4], self.mac[5], 0, 0])
+    }
+    pub fn enabled(&self) -> bool {
+        self.ctrl & ENABLED != 0
+    }
+    pub fn irq(&self) -> bool {
+        (self.ctrl & RX_IRQ_ENABLED != 0 && self.status & STATUS_RX_DONE != 0)
+            || (self.ctrl & TX_IRQ_ENABLED != 0 && self.status & STATUS_TX_DONE != 0)
+    }
+
+    pub fn set_ctrl(&mut self, ctrl: u32) {
+        println!("[net] ctrl={:08x}", ctrl);
+        self.ctrl = ctrl;
+    }
+    pub fn set_mac(&mut self, mac: [u8; 6]) {
+        self.mac = mac;
+    }
+
+    /// Sets up the TX ring and starts at its first descriptor
+    pub fn set_tx_ring(&mut self, base: u32, count: u32) {
+        self.tx = Ring { base, count, index: 0 };
+    }
+
+    /// Sets up the RX ring and starts at its first descriptor
+    pub fn set_rx_ring(&mut self, base: u32, count: u32) {
+        self.rx = Ring { base, count, index: 0 };
+    }
+
+    /// Clears the status bits that are set in `value`
+    pub fn clear_status(&mut self, value: u32) {
+        self.status &= !value;
+    }
+
+    /// Sets status bits, used by the bus while it processes the rings
+    pub fn raise_status(&mut self, bits: u32) {
+        self.status |= bits;
+    }
+
+    /// Asks the NIC to go through the TX ring
+    pub fn poll_tx(&mut self) {
+        self.tx_poll = true;
+    }
+
+    /// Returns true once after the TX poll register was written, while the NIC is enabled
+    pub fn take_tx_poll(&mut self) -> bool {
+        let poll = self.tx_poll && self.enabled();
+        self.tx_poll = false;
+        poll
+    }
+
+    /// Sends a frame to the backend, dropping it when none is attached
+    pub fn send(&mut self, frame: &[u8]) {
+        if let Some(backend) = self.backend.as_mut() {
+            backend.send(frame);
+        }
+    }
+
+    /// Returns the next frame from the backend while the NIC is enabled
+    pub fn recv(&mut self) -> Option<Vec<u8>> {
+        if !self.enabled() {
+            return None;
+        }
+        self.backend.as_mut()?.recv()
+    }
+
+    /// Counts a received frame that didn't fit anywhere
+    pub fn drop_rx(&mut self) {
+        self.rx_dropped = self.rx_dropped.wrapping_add(1);
+        self.status |= STATUS_RX_DROPPED;
+    }
+}
+
+/// The backend is attached by the host and isn't part of the state, nor are frames it holds
+impl Snapshot for Nic {
+    fn save(&self, w: &mut StateWriter) {
+        w.raw(&self.mac);
+        w.u32(self.ctrl);
+        w.u32(self.status);
+        for ring in [&self.tx, &self.rx] {
+            for value in [ring.base, ring.count, ring.index] {
+                w.u32(value);
+            }
+        }
+        w.bool(self.tx_poll);
+        w.u32(self.rx_dropped);
+    }
+
+    fn restore(&mut self, r: &mut StateReader) -> io::Result<()> {
+        self.mac.copy_from_slice(r.raw(6)?);
+        self.ctrl = r.u32()?;
+        self.status = r.u32()?;
+        for ring in [&mut self.tx, &mut self.rx] {
+            ring.base = r.u32()?;
+            ring.count = r.u32()?;
+            ring.index = r.u32()? % ring.count.max(1);
+        }
+        self.tx_poll = r.bool()?;
+        self.rx_dropped = r.u32()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,155 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::devices::net::{MAX_FRAME_SIZE, NetBackend};
+
+const MAGIC: u32 = 0xA1B2_C3D4; // Microsecond timestamps
+const MAGIC_SWAPPED: u32 = 0xD4C3_B2A1;
+const VERSION_MAJOR: u16 = 2;
+const VERSION_MINOR: u16 = 4;
+const LINKTYPE_ETHERNET: u32 = 1;
+const SNAPLEN: u32 = 65535;
+
+/// Reads the frames of a classic (libpcap) capture file
+pub struct PcapReader {
+    input: BufReader<File>,
+    /// The file was written on a host with the other byte order
+    swapped: bool,
+}
+
+impl PcapReader {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut input = BufReader::new(File::open(path)?);
+
+        let mut header = [0u8; 24];
+        input.read_exact(&mut header)?;
+        let swapped = match u32::from_le_bytes(header[0..4].try_into().unwrap()) {
+            MAGIC => false,
+            MAGIC_SWAPPED => true,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "not a pcap file")),
+        };
+
+        let reader = Self { input, swapped };
+        let linktype = reader.word(&header[20..24]);
+        if linktype != LINKTYPE_ETHERNET {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported link type {linktype}, expected Ethernet"),
+            ));
+        }
+        Ok(reader)
+    }
+
+    fn word(&self, bytes: &[u8]) -> u32 {
+        let bytes = bytes.try_into().unwrap();
+        if self.swapped {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    }
+
+    /// Returns the next frame, None at the end of the file
+    pub fn next_frame(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut header = [0u8; 16];
+        match self.input.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+
+        let len = self.word(&header[8..12]) as usize;
+        let mut frame = vec![0u8; len];
+        self.input.read_exact(&mut frame)?;
+        Ok(Some(frame))
+    }
+}
+
+/// Writes frames to a classic (libpcap) capture file, timestamped with the host clock
+pub struct PcapWriter {
+    output: BufWriter<File>,
+}
+
+impl PcapWriter {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut output = BufWriter::new(File::create(path)?);
+
+        output.write_all(&MAGIC.to_le_bytes())?;
+        output.write_all(&VERSION_MAJOR.to_le_bytes())?;
+        output.write_all(&VERSION_MINOR.to_le_bytes())?;
+        output.write_all(&0u32.to_le_bytes())?; // GMT offset
+        output.write_all(&0u32.to_le_bytes())?; // Timestamp accuracy
+        output.write_all(&SNAPLEN.to_le_bytes())?;
+        output.write_all(&LINKTYPE_ETHERNET.to_le_bytes())?;
+        output.flush()?;
+
+        Ok(Self { output })
+    }
+
+    pub fn write_frame(&mut self, frame: &[u8]) -> io::Result<()> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+
+        self.output.write_all(&(now.as_secs() as u32).to_le_bytes())?;
+        self.output.write_all(&now.subsec_micros().to_le_bytes())?;
+        self.output.write_all(&(frame.len() as u32).to_le_bytes())?;
+        self.output.write_all(&(frame.len() as u32).to_le_bytes())?;
+        self.output.write_all(frame)?;
+        // Keep the file readable while the machine runs
+        self.output.flush()
+    }
+}
+
+/// Backend that replays the frames of a capture file to the machine, as fast as the RX ring
+/// takes them, and/or records the frames the machine sends.
+pub struct PcapBackend {
+    replay: Option<PcapReader>,
+    capture: Option<PcapWriter>,
+}
+
+impl PcapBackend {
+    /// Either file is optional: without a replay file nothing is received, without a
+    /// capture file sent frames are dropped.
+    pub fn new(replay: Option<PcapReader>, capture: Option<PcapWriter>) -> Self {
+        Self { replay, capture }
+    }
+
+    pub fn open<P: AsRef<Path>, Q: AsRef<Path>>(replay: Option<P>, capture: Option<Q>) -> io::Result<Self> {
+        let replay = replay.map(PcapReader::open).transpose()?;
+        let capture = capture.map(PcapWriter::create).transpose()?;
+        Ok(Self::new(replay, capture))
+    }
+}
+
+impl NetBackend for PcapBackend {
+    fn send(&mut self, frame: &[u8]) {
+        if let Some(capture) = self.capture.as_mut()
+            && let Err(e) = capture.write_frame(frame)
+        {
+            eprintln!("[net] pcap capture failed, stopping capture: {e}");
+            self.capture = None;
+        }
+    }
+
+    fn recv(&mut self) -> Option<Vec<u8>> {
+        loop {
+            let replay = self.replay.as_mut()?;
+            match replay.next_frame() {
+                Ok(Some(frame)) if frame.len() > MAX_FRAME_SIZE => {
+                    eprintln!("[net] skipping {} byte frame in pcap file", frame.len());
+                }
+                Ok(Some(frame)) => return Some(frame),
+                Ok(None) => {
+                    self.replay = None;
+                    return None;
+                }
+                Err(e) => {
+                    eprintln!("[net] pcap replay failed, stopping replay: {e}");
+                    self.replay = None;
+                    return None;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use std::fs;
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+
+use crate::devices::net::{MAX_FRAME_SIZE, NetBackend};
+
+/// Backend that links two NICs through a Unix datagram socket, one frame per datagram.
+/// Use `pair()` for two machines in the same process, or `bind()` on both ends with each
+/// other's path for machines in separate processes.
+pub struct SocketBackend {
+    socket: UnixDatagram,
+    /// Socket file of the peer, None when the socket is connected
+    peer: Option<PathBuf>,
+    /// Our socket file, removed on drop
+    path: Option<PathBuf>,
+}
+
+impl SocketBackend {
+    fn new(socket: UnixDatagram, path: Option<PathBuf>, peer: Option<PathBuf>) -> io::Result<Self> {
+        socket.set_nonblocking(true)?;
+        Ok(Self { socket, peer, path })
+    }
+
+    /// Creates two connected backends
+    pub fn pair() -> io::Result<(Self, Self)> {
+        let (a, b) = UnixDatagram::pair()?;
+        Ok((Self::new(a, None, None)?, Self::new(b, None, None)?))
+    }
+
+    /// Binds a socket at `path` that sends to the socket at `peer`. A stale socket file at
+    /// `path` is replaced. The peer doesn't have to exist yet, frames sent before it does
+    /// are dropped.
+    pub fn bind<P: AsRef<Path>, Q: AsRef<Path>>(path: P, peer: Q) -> io::Result<Self> {
+        let path = path.as_ref();
+        let _ = fs::remove_file(path);
+
+        let socket = UnixDatagram::bind(path)?;
+        Self::new(socket, Some(path.to_path_buf()), Some(peer.as_ref().to_path_buf()))
+    }
+}
+
+impl NetBackend for SocketBackend {
+    fn send(&mut self, frame: &[u8]) {
+        let result = match &self.peer {
+            Some(peer) => self.socket.send_to(frame, peer),
+            None => self.socket.send(frame),
+        };
+        // Like a cable without a link partner: a missing peer or a full queue drops the frame
+        if let Err(e) = result {
+            println!("[net] frame dropped: {e}");
+        }
+    }
+
+    fn recv(&mut self) -> Option<Vec<u8>> {
+        // One spare byte to tell oversized datagrams apart
+        let mut buf = [0u8; MAX_FRAME_SIZE + 1];
+        loop {
+            let len = self.socket.recv(&mut buf).ok()?;
+            if len <= MAX_FRAME_SIZE {
+                return Some(buf[..len].to_vec());
+            }
+            eprintln!("[net] oversized datagram dropped");
+        }
+    }
+}
+
+impl Drop for SocketBackend {
+    fn drop(&mut self) {
+        if let Some(path) = &self.path {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
@@ -1,4 +1,6 @@
 use crate::bus::BusError;
+use crate::machine::snapshot::{Snapshot, StateReader, StateWriter};
+use std::io;
 
 pub struct Ram {
     data: Vec<u8>,
@@ -44,3 +46,13 @@ impl Ram {
         Ok(u32::from_le_bytes(bytes))
     }
 }
+
+impl Snapshot for Ram {
+    fn save(&self, w: &mut StateWriter) {
+        w.bytes(&self.data);
+    }
+
+    fn restore(&mut self, r: &mut StateReader) -> io::Result<()> {
+        r.bytes_into("RAM", &mut self.data)
+    }
+}
@@ -0,0 +1,203 @@
+use crate::machine::snapshot::{Snapshot, StateReader, StateWriter};
+use std::fs::File;
+use std::io::{self, Read};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Status register bits
+pub const STATUS_READY: u32 = 0x1; // A fresh word is waiting in the data register
+
+/// Seed used when nothing else is configured
+pub const DEFAULT_SEED: u64 = 0x4E6F_7661_3332_3031;
+
+/// Cycles needed to gather a new word after the data register was read
+pub const CYCLES_PER_WORD: u32 = 32;
+
+/// Where the RNG gets its randomness from
+pub enum RngSource {
+    /// Pseudo random sequence from a fixed seed. Runs are fully deterministic.
+    Seeded(u64),
+    /// The host's random source (/dev/urandom)
+    Host,
+}
+
+/// Hardware random number generator. Reading the data register consumes the word; the next
+/// one is ready after `CYCLES_PER_WORD` cycles. Reads before that return 0.
+pub struct Rng {
+    /// Randomness source
+    source: RngSource,
+    /// Generator state (seeded source)
+    state: u64,
+    /// Host random source, opened on first use
+    host: Option<File>,
+    /// Word waiting in the data register
+    data: u32,
+    /// Status flags
+    status: u32,
+    /// Cycles left until the next word is ready
+    countdown: u32,
+}
+
+impl Default for Rng {
+    fn default() -> Self {
+        Self::new(RngSource::Seeded(DEFAULT_SEED))
+    }
+}
+
+impl Rng {
+    pub fn new(source: RngSource) -> Self {
+        let state = match source {
+            RngSource::Seeded(seed) => seed,
+            RngSource::Host => 0,
+        };
+
+        let mut rng = Self {
+            source,
+            state,
+            host: None,
+            data: 0,
+            status: 0,
+            countdown: 0,
+        };
+        rng.refill();
+        rng
+    }
+
+    pub fn status(&self) -> u32 {
+        self.status
+    }
+    pub fn ready(&self) -> bool {
+        self.status & STATUS_READY != 0
+    }
+    /// True when the words come from the host, so they differ from run to run
+    pub fn host_source(&self) -> bool {
+        matches!(self.source, RngSource::Host)
+    }
+
+    /// Restarts the pseudo random sequence from `seed`. This also switches a host backed
+    /// RNG over to the seeded source.
+    pub fn seed(&mut self, seed: u64) {
+        println!("[rng] seed=0x{:016X}", seed);
+        self.source = RngSource::Seeded(seed);
+        self.state = seed;
+        self.countdown = 0;
+        self.refill();
+    }
+
+    /// Reads the data register, consuming the word
+    pub fn read_data(&mut self) -> u32 {
+        if !self.ready() {
+            return 0;
+        }
+
+        let value = self.data;
+        self.data = 0;
+        self.status &= !STATUS_READY;
+        self.countdown = CYCLES_PER_WORD;
+        value
+    }
+
+    /// Gathers the next word once the previous one was consumed
+    pub fn tick(&mut self) {
+        if self.advance() {
+            self.refill();
+        }
+    }
+
+    /// Counts down towards the next word. Returns true when a new word is due.
+    pub fn advance(&mut self) -> bool {
+        if self.ready() {
+            return false;
+        }
+
+        if self.countdown > 0 {
+            self.countdown -= 1;
+            return false;
+        }
+        true
+    }
+
+    /// Draws a new word from the source
+    pub fn generate(&mut self) -> u32 {
+        match self.source {
+            RngSource::Seeded(_) => self.next_seeded(),
+            RngSource::Host => self.next_host(),
+        }
+    }
+
+    /// Puts `word` in the data register, e.g. a host word recorded earlier
+    pub fn fill(&mut self, word: u32) {
+        self.data = word;
+        self.status |= STATUS_READY;
+    }
+
+    fn refill(&mut self) {
+        let word = self.generate();
+        self.fill(word);
+    }
+
+    /// splitmix64
+    fn next_seeded(&mut self) -> u32 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        (z ^ (z >> 31)) as u32
+    }
+
+    /// Reads a word from the host. Falls back to the seeded generator, seeded from the
+    /// wall clock, when the host source isn't available.
+    fn next_host(&mut self) -> u32 {
+        if self.host.is_none() {
+            match File::open("/dev/urandom") {
+                Ok(file) => self.host = Some(file),
+                Err(e) => {
+                    eprintln!("[rng] host random source unavailable, using the clock: {e}");
+                    let seed = SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .map(|d| d.as_nanos() as u64)
+                        .unwrap_or(DEFAULT_SEED);
+                    self.source = RngSource::Seeded(seed);
+                    self.state = seed;
+                    return self.next_seeded();
+                }
+            }
+        }
+
+        let mut buf = [0u8; 4];
+        match self.host.as_mut().map(|f| f.read_exact(&mut buf)) {
+            Some(Ok(())) => u32::from_le_bytes(buf),
+            _ => self.next_seeded(),
+        }
+    }
+}
+
+/// A host source stays a host source; a seeded one continues its sequence
+impl Snapshot for Rng {
+    fn save(&self, w: &mut StateWriter) {
+        match self.source {
+            RngSource::Seeded(seed) => {
+                w.bool(false);
+                w.u64(seed);
+            }
+            RngSource::Host => {
+                w.bool(true);
+                w.u64(0);
+            }
+        }
+        w.u64(self.state);
+        for value in [self.data, self.status, self.countdown] {
+            w.u32(value);
+        }
+    }
+
+    fn restore(&mut self, r: &mut StateReader) -> io::Result<()> {
+        let host = r.bool()?;
+        let seed = r.u64()?;
+        self.source = if host { RngSource::Host } else { RngSource::Seeded(seed) };
+        self.state = r.u64()?;
+        self.data = r.u32()?;
+        self.status = r.u32()?;
+        self.countdown = r.u32()?;
+        Ok(())
+    }
+}
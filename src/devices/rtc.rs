@@ -0,0 +1,242 @@
+use crate::machine::snapshot::{Snapshot, StateReader, StateWriter};
+use std::io;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+pub const ALARM_ENABLED: u32 = 0x1; // 0 = alarm disabled, 1 = alarm armed
+pub const IRQ_ENABLED: u32 = 0x2; // 0 = no IRQ on alarm, 1 = IRQ on alarm
+
+// Status register bits (write 1 to clear)
+pub const STATUS_ALARM: u32 = 0x1; // The alarm went off
+
+/// Cycles per emulated second when using the virtual clock
+pub const DEFAULT_CYCLES_PER_SECOND: u64 = 1_000_000;
+
+const MICROS_PER_SECOND: u64 = 1_000_000;
+
+/// Cycles between two alarm checks against the host clock. Reading the host clock is a system
+/// call, and the alarm only has a resolution of one second anyway.
+const HOST_ALARM_POLL_CYCLES: u64 = 1024;
+
+/// Where the RTC gets its notion of time from
+pub enum RtcClock {
+    /// Driven by emulated cycles. Runs are fully deterministic.
+    Virtual { cycles_per_second: u64 },
+    /// Driven by the host wall clock
+    Host,
+}
+
+pub struct Rtc {
+    /// Clock source
+    clock: RtcClock,
+    /// Cycles seen so far (virtual clock)
+    cycles: u64,
+    /// Moment the RTC was created (host clock)
+    started: Instant,
+    /// Offset in microseconds between the clock source and the RTC time
+    offset_us: i64,
+    /// Sub-second part latched when the seconds register is read
+    latched_us: u32,
+    /// Alarm time in seconds
+    alarm: u32,
+    /// Control register
+    ctrl: u32,
+    /// Status flags
+    status: u32,
+    /// Cycle at which the alarm is checked next, `u64::MAX` when it isn't armed
+    alarm_check: u64,
+}
+
+impl Default for Rtc {
+    fn default() -> Self {
+        Self::new(RtcClock::Virtual {
+            cycles_per_second: DEFAULT_CYCLES_PER_SECOND,
+        })
+    }
+}
+
+impl Rtc {
+    /// Creates a new RTC. The virtual clock starts at 0, the host clock starts at the
+    /// current Unix time.
+    pub fn new(clock: RtcClock) -> Self {
+        let offset_us = match clock {
+            RtcClock::Virtual { .. } => 0,
+            RtcClock::Host => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_micros() as i64)
+                .unwrap_or(0),
+        };
+
+        Self {
+            clock,
+            cycles: 0,
+            started: Instant::now(),
+            offset_us,
+            latched_us: 0,
+            alarm: 0,
+            ctrl: 0,
+            status: 0,
+            alarm_check: u64::MAX,
+        }
+    }
+
+    /// Microseconds elapsed according to the clock source
+    fn elapsed_us(&self) -> u64 {
+        match self.clock {
+            RtcClock::Virtual { cycles_per_second } => {
+                (self.cycles as u128 * MICROS_PER_SECOND as u128 / cycles_per_second.max(1) as u128) as u64
+            }
+            RtcClock::Host => self.started.elapsed().as_micros() as u64,
+        }
+    }
+
+    /// Current RTC time in microseconds
+    pub fn now_us(&self) -> u64 {
+        (self.elapsed_us() as i64).wrapping_add(self.offset_us).max(0) as u64
+    }
+
+    pub fn seconds(&self) -> u32 {
+        (self.now_us() / MICROS_PER_SECOND) as u32
+    }
+    pub fn subseconds(&self) -> u32 {
+        (self.now_us() % MICROS_PER_SECOND) as u32
+    }
+    pub fn alarm(&self) -> u32 {
+        self.alarm
+    }
+    pub fn ctrl(&self) -> u32 {
+        self.ctrl
+    }
+    pub fn status(&self) -> u32 {
+        self.status
+    }
+    pub fn irq(&self) -> bool {
+        self.ctrl & IRQ_ENABLED != 0 && self.status & STATUS_ALARM != 0
+    }
+    /// True when the time comes from the host, so it differs from run to run
+    pub fn host_clock(&self) -> bool {
+        matches!(self.clock, RtcClock::Host)
+    }
+
+    /// Reads the seconds and latches the matching sub-second value, so firmware can read both
+    /// registers without the time rolling over in between.
+    pub fn read_seconds(&mut self) -> u32 {
+        self.read_seconds_at(self.now_us())
+    }
+
+    /// Reads the seconds as if the current time was `now` (in microseconds), e.g. a host clock
+    /// reading recorded earlier
+    pub fn read_seconds_at(&mut self, now: u64) -> u32 {
+        self.latched_us = (now % MICROS_PER_SECOND) as u32;
+        (now / MICROS_PER_SECOND) as u32
+    }
+
+    /// Returns the sub-second value (in microseconds) latched by the last seconds read
+    pub fn latched_subseconds(&self) -> u32 {
+        self.latched_us
+    }
+
+    /// Sets the current time in whole seconds
+    pub fn set_seconds(&mut self, seconds: u32) {
+        println!("[rtc] seconds={}", seconds);
+        let target = seconds as i64 * MICROS_PER_SECOND as i64;
+        self.offset_us = target - self.elapsed_us() as i64;
+        self.schedule_alarm();
+    }
+
+    pub fn set_alarm(&mut self, alarm: u32) {
+        println!("[rtc] alarm={}", alarm);
+        self.alarm = alarm;
+        self.schedule_alarm();
+    }
+
+    pub fn set_ctrl(&mut self, ctrl: u32) {
+        println!("[rtc] ctrl={:08x}", ctrl);
+        self.ctrl = ctrl;
+        self.schedule_alarm();
+    }
+
+    /// Clears the status bits that are set in `value`
+    pub fn clear_status(&mut self, value: u32) {
+        self.status &= !value;
+    }
+
+    pub fn tick(&mut self) {
+        self.advance();
+        if self.poll_alarm() {
+            self.raise_alarm();
+        }
+    }
+
+    /// Works out when the alarm has to be checked. The virtual clock knows the cycle the alarm
+    /// is due at; the host clock is checked right away and then every `HOST_ALARM_POLL_CYCLES`.
+    fn schedule_alarm(&mut self) {
+        self.alarm_check = if self.ctrl & ALARM_ENABLED == 0 {
+            u64::MAX
+        } else {
+            match self.clock {
+                RtcClock::Virtual { cycles_per_second } => {
+                    let target_us = self.alarm as i64 * MICROS_PER_SECOND as i64 - self.offset_us;
+                    if target_us <= 0 {
+                        self.cycles
+                    } else {
+                        let cycle = (target_us as u128 * cycles_per_second.max(1) as u128)
+                            .div_ceil(MICROS_PER_SECOND as u128);
+                        cycle.min(u64::MAX as u128) as u64
+                    }
+                }
+                RtcClock::Host => self.cycles,
+            }
+        };
+    }
+
+    /// Advances the virtual clock by one cycle without checking the alarm
+    pub fn advance(&mut self) {
+        self.cycles = self.cycles.wrapping_add(1);
+    }
+
+    /// True when the alarm is armed and its time has come. The clock is only read on the
+    /// cycles scheduled by `schedule_alarm`.
+    pub fn poll_alarm(&mut self) -> bool {
+        if self.cycles < self.alarm_check {
+            return false;
+        }
+        if self.ctrl & ALARM_ENABLED != 0 && self.seconds() >= self.alarm {
+            return true;
+        }
+        let wait = if self.host_clock() { HOST_ALARM_POLL_CYCLES } else { 1 };
+        self.alarm_check = self.cycles.saturating_add(wait);
+        false
+    }
+
+    /// The alarm is one-shot: it disarms itself and firmware has to re-arm it
+    pub fn raise_alarm(&mut self) {
+        self.status |= STATUS_ALARM;
+        self.ctrl &= !ALARM_ENABLED;
+        self.alarm_check = u64::MAX;
+    }
+}
+
+/// A host clock keeps following the host: its offset isn't restored
+impl Snapshot for Rtc {
+    fn save(&self, w: &mut StateWriter) {
+        w.u64(self.cycles);
+        w.u64(self.offset_us as u64);
+        for value in [self.latched_us, self.alarm, self.ctrl, self.status] {
+            w.u32(value);
+        }
+    }
+
+    fn restore(&mut self, r: &mut StateReader) -> io::Result<()> {
+        self.cycles = r.u64()?;
+        let offset_us = r.u64()? as i64;
+        if !self.host_clock() {
+            self.offset_us = offset_us;
+        }
+        self.latched_us = r.u32()?;
+        self.alarm = r.u32()?;
+        self.ctrl = r.u32()?;
+        self.status = r.u32()?;
+        self.schedule_alarm();
+        Ok(())
+    }
+}
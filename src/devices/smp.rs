@@ -0,0 +1,104 @@
+use crate::machine::snapshot::{Snapshot, StateReader, StateWriter};
+use std::io;
+
+/// Most cores a machine can have, one bit per core in the IPI registers
+pub const MAX_HARTS: usize = 32;
+/// Number of hardware spinlocks
+pub const SPINLOCK_COUNT: usize = 8;
+
+/// Inter-processor interrupts and hardware spinlocks, shared by all cores.
+///
+/// A core interrupts others by setting their bits in the IPI pending mask; each core clears
+/// its own bit (its hart ID, see the HARTID special register) in the IPI handler. Reading a
+/// spinlock takes it and returns 1 when it was free, or returns 0 when another core holds it;
+/// any write releases it.
+pub struct Smp {
+    /// Number of cores
+    harts: u32,
+    /// Pending IPIs, bit n = hart n
+    pending: u32,
+    /// Held spinlocks, bit n = lock n
+    locks: u32,
+}
+
+impl Default for Smp {
+    fn default() -> Self {
+        Self::new(1)
+    }
+}
+
+impl Smp {
+    pub fn new(harts: usize) -> Self {
+        Self {
+            harts: harts.clamp(1, MAX_HARTS) as u32,
+            pending: 0,
+            locks: 0,
+        }
+    }
+
+    /// Drops pending IPIs and releases all spinlocks. The number of cores is kept.
+    pub fn reset(&mut self) {
+        self.pending = 0;
+        self.locks = 0;
+    }
+
+    pub fn harts(&self) -> u32 {
+        self.harts
+    }
+    pub fn pending(&self) -> u32 {
+        self.pending
+    }
+    pub fn locks(&self) -> u32 {
+        self.locks
+    }
+    pub fn ipi(&self, hart: usize) -> bool {
+        self.pending & (1 << hart) != 0
+    }
+
+    fn hart_mask(&self) -> u32 {
+        if self.harts as usize >= MAX_HARTS {
+            u32::MAX
+        } else {
+            (1 << self.harts) - 1
+        }
+    }
+
+    /// Raises an IPI on the harts whose bits are set in `mask`. Bits of missing harts are
+    /// ignored.
+    pub fn raise(&mut self, mask: u32) {
+        self.pending |= mask & self.hart_mask();
+    }
+
+    /// Acknowledges the IPIs whose bits are set in `mask`
+    pub fn clear(&mut self, mask: u32) {
+        self.pending &= !mask;
+    }
+
+    /// Takes a spinlock. Returns 1 when it was free, 0 when it is already held.
+    pub fn try_lock(&mut self, lock: usize) -> u32 {
+        let bit = 1 << (lock % SPINLOCK_COUNT);
+        if self.locks & bit != 0 {
+            return 0;
+        }
+        self.locks |= bit;
+        1
+    }
+
+    pub fn unlock(&mut self, lock: usize) {
+        self.locks &= !(1 << (lock % SPINLOCK_COUNT));
+    }
+}
+
+/// The number of cores follows the machine's cores and isn't restored
+impl Snapshot for Smp {
+    fn save(&self, w: &mut StateWriter) {
+        w.u32(self.pending);
+        w.u32(self.locks);
+    }
+
+    fn restore(&mut self, r: &mut StateReader) -> io::Result<()> {
+        self.pending = r.u32()?;
+        self.locks = r.u32()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,157 @@
+pub mod flash;
+
+use crate::machine::snapshot::{invalid, Snapshot, StateReader, StateWriter};
+use std::io;
+
+pub const ENABLED: u32 = 0x1; // 0 = controller off, transfers are ignored
+
+// Status register bits
+pub const STATUS_RX_VALID: u32 = 0x1; // A byte was received, cleared by reading the data register
+
+/// Number of chip select lines
+pub const CS_COUNT: usize = 4;
+/// Chip select value with no device selected
+pub const CS_NONE: u32 = 0xFF;
+
+/// A simulated SPI slave
+pub trait SpiDevice: Send {
+    /// Called when the chip select line goes active
+    fn select(&mut self) {}
+    /// Called when the chip select line goes inactive
+    fn deselect(&mut self) {}
+    /// Shifts one byte out to the device and returns the byte shifted in
+    fn transfer(&mut self, mosi: u8) -> u8;
+}
+
+/// SPI master controller with a chip select line per attached device. Transfers are one byte
+/// at a time and complete immediately.
+pub struct SpiController {
+    /// Attached slaves, indexed by chip select
+    devices: [Option<Box<dyn SpiDevice>>; CS_COUNT],
+    /// Control register
+    ctrl: u32,
+    /// Selected chip, CS_NONE when idle
+    cs: u32,
+    /// Last received byte
+    rx: u8,
+    /// Status flags
+    status: u32,
+}
+
+impl Default for SpiController {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SpiController {
+    pub fn new() -> Self {
+        Self {
+            devices: Default::default(),
+            ctrl: 0,
+            cs: CS_NONE,
+            rx: 0,
+            status: 0,
+        }
+    }
+
+    /// Attaches a device to a chip select line, replacing the one that was there
+    pub fn attach(&mut self, cs: usize, device: Box<dyn SpiDevice>) {
+        self.devices[cs] = Some(device);
+    }
+
+    /// Detaches the device on a chip select line
+    pub fn detach(&mut self, cs: usize) -> Option<Box<dyn SpiDevice>> {
+        if self.cs == cs as u32 {
+            self.set_cs(CS_NONE);
+        }
+        self.devices[cs].take()
+    }
+
+    /// Puts the registers back to their power-on state. Attached devices stay attached.
+    pub fn reset(&mut self) {
+        self.set_cs(CS_NONE);
+        self.ctrl = 0;
+        self.rx = 0;
+        self.status = 0;
+    }
+
+    /// True when a device is attached to any chip select line
+    pub fn has_devices(&self) -> bool {
+        self.devices.iter().any(|d| d.is_some())
+    }
+
+    pub fn ctrl(&self) -> u32 {
+        self.ctrl
+    }
+    pub fn cs(&self) -> u32 {
+        self.cs
+    }
+    pub fn status(&self) -> u32 {
+        self.status
+    }
+
+    pub fn set_ctrl(&mut self, ctrl: u32) {
+        println!("[spi] ctrl={:08x}", ctrl);
+        self.ctrl = ctrl;
+    }
+
+    /// Selects a chip. Any other value than 0..CS_COUNT deselects all chips.
+    pub fn set_cs(&mut self, cs: u32) {
+        if cs == self.cs {
+            return;
+        }
+
+        if let Some(dev) = self.selected() {
+            dev.deselect();
+        }
+        self.cs = if (cs as usize) < CS_COUNT { cs } else { CS_NONE };
+        if let Some(dev) = self.selected() {
+            dev.select();
+        }
+    }
+
+    fn selected(&mut self) -> Option<&mut Box<dyn SpiDevice>> {
+        self.devices.get_mut(self.cs as usize)?.as_mut()
+    }
+
+    /// Reads the received byte
+    pub fn read_data(&mut self) -> u32 {
+        self.status &= !STATUS_RX_VALID;
+        self.rx as u32
+    }
+
+    /// Sends a byte to the selected chip. Without a selected device the bus floats high.
+    pub fn write_data(&mut self, value: u32) {
+        if self.ctrl & ENABLED == 0 {
+            return;
+        }
+
+        self.rx = match self.selected() {
+            Some(dev) => dev.transfer(value as u8),
+            None => 0xFF,
+        };
+        self.status |= STATUS_RX_VALID;
+    }
+}
+
+/// Attached slaves aren't part of the state, the selected chip must be attached
+impl Snapshot for SpiController {
+    fn save(&self, w: &mut StateWriter) {
+        for value in [self.ctrl, self.cs, self.rx as u32, self.status] {
+            w.u32(value);
+        }
+    }
+
+    fn restore(&mut self, r: &mut StateReader) -> io::Result<()> {
+        self.ctrl = r.u32()?;
+        let cs = r.u32()?;
+        if cs != CS_NONE && self.devices.get(cs as usize).is_none_or(|d| d.is_none()) {
+            return Err(invalid(format!("snapshot selects SPI chip {cs}, which isn't attached")));
+        }
+        self.cs = cs;
+        self.rx = r.u32()? as u8;
+        self.status = r.u32()?;
+        Ok(())
+    }
+}
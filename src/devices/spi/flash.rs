@@ -0,0 +1,207 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::devices::spi::SpiDevice;
+
+// Commands
+pub const CMD_PAGE_PROGRAM: u8 = 0x02;
+pub const CMD_READ: u8 = 0x03;
+pub const CMD_WRITE_DISABLE: u8 = 0x04;
+pub const CMD_READ_STATUS: u8 = 0x05;
+pub const CMD_WRITE_ENABLE: u8 = 0x06;
+pub const CMD_SECTOR_ERASE: u8 = 0x20;
+pub const CMD_READ_ID: u8 = 0x9F;
+pub const CMD_CHIP_ERASE: u8 = 0xC7;
+
+// Status register bits
+pub const STATUS_WIP: u8 = 0x01; // Write in progress, always 0 as writes complete immediately
+pub const STATUS_WEL: u8 = 0x02; // Write enable latch
+
+pub const PAGE_SIZE: usize = 256;
+pub const SECTOR_SIZE: usize = 4096;
+
+/// JEDEC ID: manufacturer, memory type, capacity (filled in from the size)
+const JEDEC_MANUFACTURER: u8 = 0xEF;
+const JEDEC_TYPE: u8 = 0x40;
+
+enum State {
+    /// Waiting for the command byte
+    Command,
+    /// Collecting the 24 bit address of a command
+    Address { cmd: u8, addr: u32, left: u8 },
+    /// Streaming data bytes
+    Data { cmd: u8, addr: u32 },
+    /// Shifting out the status register
+    Status,
+    /// Shifting out the JEDEC ID
+    Id { index: usize },
+    /// Command done, ignore the rest until chip select goes inactive
+    Done,
+}
+
+/// 25-series SPI NOR flash, backed by a file. Programming can only clear bits, erasing sets
+/// them back to 1. Changes are written through to the file.
+pub struct SpiFlash {
+    data: Vec<u8>,
+    file: Option<File>,
+    state: State,
+    write_enabled: bool,
+}
+
+impl SpiFlash {
+    /// Creates an erased flash that lives in memory only
+    pub fn new(size: usize) -> Self {
+        Self {
+            data: vec![0xFF; size],
+            file: None,
+            state: State::Command,
+            write_enabled: false,
+        }
+    }
+
+    /// Opens a flash image. The flash is as large as the file.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+
+        Ok(Self {
+            data,
+            file: Some(file),
+            state: State::Command,
+            write_enabled: false,
+        })
+    }
+
+    pub fn size(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn contents(&self) -> &[u8] {
+        &self.data
+    }
+
+    fn status(&self) -> u8 {
+        if self.write_enabled { STATUS_WEL } else { 0 }
+    }
+
+    fn jedec_id(&self) -> [u8; 3] {
+        let capacity = self.data.len().max(1).next_power_of_two().trailing_zeros() as u8;
+        [JEDEC_MANUFACTURER, JEDEC_TYPE, capacity]
+    }
+
+    /// Writes a range of the flash back to the file
+    fn sync(&mut self, start: usize, len: usize) {
+        let Some(file) = self.file.as_mut() else {
+            return;
+        };
+
+        let result = file
+            .seek(SeekFrom::Start(start as u64))
+            .and_then(|_| file.write_all(&self.data[start..start + len]));
+        if let Err(e) = result {
+            eprintln!("[spi-flash] failed to write image: {e}");
+        }
+    }
+
+    fn erase(&mut self, start: usize, len: usize) {
+        let start = start.min(self.data.len());
+        let len = len.min(self.data.len() - start);
+        self.data[start..start + len].fill(0xFF);
+        self.sync(start, len);
+    }
+
+    /// Handles the command byte, returns the next state
+    fn command(&mut self, cmd: u8) -> State {
+        match cmd {
+            CMD_READ | CMD_PAGE_PROGRAM | CMD_SECTOR_ERASE => State::Address { cmd, addr: 0, left: 3 },
+            CMD_READ_STATUS => State::Status,
+            CMD_READ_ID => State::Id { index: 0 },
+            CMD_WRITE_ENABLE => {
+                self.write_enabled = true;
+                State::Done
+            }
+            CMD_WRITE_DISABLE => {
+                self.write_enabled = false;
+                State::Done
+            }
+            CMD_CHIP_ERASE => {
+                if self.write_enabled {
+                    self.erase(0, self.data.len());
+                    self.write_enabled = false;
+                }
+                State::Done
+            }
+            _ => State::Done,
+        }
+    }
+}
+
+impl SpiDevice for SpiFlash {
+    fn select(&mut self) {
+        self.state = State::Command;
+    }
+
+    fn deselect(&mut self) {
+        // A page program ends when chip select goes inactive
+        if let State::Data { cmd: CMD_PAGE_PROGRAM, .. } = self.state {
+            self.write_enabled = false;
+        }
+        self.state = State::Command;
+    }
+
+    fn transfer(&mut self, mosi: u8) -> u8 {
+        let mut miso = 0xFF;
+
+        self.state = match std::mem::replace(&mut self.state, State::Done) {
+            State::Command => self.command(mosi),
+            State::Address { cmd, addr, left } => {
+                let addr = (addr << 8) | mosi as u32;
+                if left > 1 {
+                    State::Address { cmd, addr, left: left - 1 }
+                } else if cmd == CMD_SECTOR_ERASE {
+                    if self.write_enabled {
+                        let start = addr as usize & !(SECTOR_SIZE - 1);
+                        self.erase(start, SECTOR_SIZE);
+                        self.write_enabled = false;
+                    }
+                    State::Done
+                } else {
+                    State::Data { cmd, addr }
+                }
+            }
+            State::Data { cmd, addr } => {
+                let size = self.data.len().max(1);
+                let idx = addr as usize % size;
+                match cmd {
+                    CMD_READ => {
+                        miso = self.data.get(idx).copied().unwrap_or(0xFF);
+                        State::Data { cmd, addr: addr.wrapping_add(1) }
+                    }
+                    _ => {
+                        // Page program, wraps around within the page
+                        if self.write_enabled && idx < self.data.len() {
+                            self.data[idx] &= mosi;
+                            self.sync(idx, 1);
+                        }
+                        let page = addr & !(PAGE_SIZE as u32 - 1);
+                        let next = page | (addr.wrapping_add(1) & (PAGE_SIZE as u32 - 1));
+                        State::Data { cmd, addr: next }
+                    }
+                }
+            }
+            State::Status => {
+                miso = self.status();
+                State::Status
+            }
+            State::Id { index } => {
+                miso = self.jedec_id().get(index).copied().unwrap_or(0xFF);
+                State::Id { index: index + 1 }
+            }
+            State::Done => State::Done,
+        };
+
+        miso
+    }
+}
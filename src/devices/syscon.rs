@@ -0,0 +1,44 @@
+/// Board identification, 'N' 'V' followed by the board number 0x3201
+pub const BOARD_ID: u32 = 0x4E56_3201;
+
+// Reset register bits
+pub const RESET_PRESERVE_RAM: u32 = 0x1; // 1 = keep RAM contents over the soft reset
+
+/// Action requested by the program, carried out by the machine at the end of the cycle
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SysRequest {
+    /// Soft reset
+    Reset { preserve_ram: bool },
+    /// Power off with the given exit status
+    PowerOff { status: u32 },
+}
+
+/// System control block: board information, soft reset and power off
+#[derive(Default)]
+pub struct SystemControl {
+    /// Pending request
+    request: Option<SysRequest>,
+}
+
+impl SystemControl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn reset(&mut self, value: u32) {
+        println!("[sys] reset value={:08x}", value);
+        self.request = Some(SysRequest::Reset {
+            preserve_ram: value & RESET_PRESERVE_RAM != 0,
+        });
+    }
+
+    pub fn power_off(&mut self, status: u32) {
+        println!("[sys] power off status={}", status);
+        self.request = Some(SysRequest::PowerOff { status });
+    }
+
+    /// Takes the pending request, if any
+    pub fn take_request(&mut self) -> Option<SysRequest> {
+        self.request.take()
+    }
+}
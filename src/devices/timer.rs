@@ -1,16 +1,33 @@
+use crate::machine::snapshot::{Snapshot, StateReader, StateWriter};
+use std::io;
+
 pub const ENABLED: u32 = 0x1; // 0 = not running, 1 = running
 pub const IRQ_ENABLED: u32 = 0x2; // 0 = no IRQ on timeout, 1 = IRQ on timeout
 pub const ONE_SHOT: u32 = 0x4; // 0 = periodic, 1 = one-shot
+pub const COUNT_DOWN: u32 = 0x8; // 0 = count up to period, 1 = count down from period to zero
+pub const CHAINED: u32 = 0x10; // 0 = count ticks, 1 = count overflows of the previous timer
+pub const COMPARE_ENABLED: u32 = 0x20; // 0 = compare disabled, 1 = flag when count == compare
+pub const IRQ_PENDING: u32 = 0x8000_0000; // Read-only: an IRQ is pending
+
+// Status register bits (write 1 to clear)
+pub const STATUS_OVERFLOW: u32 = 0x1; // Counter reached period (or zero when counting down)
+pub const STATUS_COMPARE: u32 = 0x2; // Counter matched the compare register
 
 pub struct Timer {
     /// Control register
     ctrl: u32,
     /// Current count
     counter: u32,
-    /// Period to count towards
+    /// Period to count towards (or reload value when counting down)
     period: u32,
-    /// IRQ flag
-    irq: bool,
+    /// Number of extra ticks to skip between counts
+    prescaler: u32,
+    /// Ticks seen since the last count
+    prescale_count: u32,
+    /// Compare value, independent of the period
+    compare: u32,
+    /// Status flags
+    status: u32,
 }
 
 impl Default for Timer {
@@ -25,7 +42,10 @@ impl Timer {
             ctrl: ENABLED | IRQ_ENABLED,
             counter: 0,
             period: 0,
-            irq: false,
+            prescaler: 0,
+            prescale_count: 0,
+            compare: 0,
+            status: 0,
         }
     }
 
@@ -33,63 +53,167 @@ impl Timer {
         self.period
     }
     pub fn ctrl(&self) -> u32 {
-        self.ctrl
+        if self.irq() {
+            self.ctrl | IRQ_PENDING
+        } else {
+            self.ctrl
+        }
     }
     pub fn count(&self) -> u32 {
         self.counter
     }
+    pub fn prescaler(&self) -> u32 {
+        self.prescaler
+    }
+    pub fn compare(&self) -> u32 {
+        self.compare
+    }
+    pub fn status(&self) -> u32 {
+        self.status
+    }
     pub fn irq(&self) -> bool {
-        self.irq
+        self.ctrl & IRQ_ENABLED != 0 && self.status != 0
     }
 
     pub fn reset(&mut self) {
         println!("[timer] reset");
-        self.counter = 0;
+        self.prescale_count = 0;
+        self.counter = if self.ctrl & COUNT_DOWN != 0 {
+            self.period
+        } else {
+            0
+        };
     }
 
+    /// Switching to count-down mode loads the counter from the period
     pub fn set_ctrl(&mut self, ctrl: u32) {
         println!("[timer] ctrl={:08x}", ctrl);
-        self.ctrl = ctrl;
+        let count_down_on = ctrl & COUNT_DOWN != 0 && self.ctrl & COUNT_DOWN == 0;
+        self.ctrl = ctrl & !IRQ_PENDING;
+        if count_down_on {
+            self.counter = self.period;
+        }
     }
 
+    /// Sets the period. Counting up, the counter is left alone; use `reset()` to restart the
+    /// count. Counting down, the counter is reloaded from the new period.
     pub fn set_period(&mut self, period: u32) {
         println!("[timer] period={}", period);
         self.period = period;
-        self.counter = 0;
+        if self.ctrl & COUNT_DOWN != 0 {
+            self.counter = period;
+        }
+    }
+
+    /// The counter advances once every `prescaler + 1` ticks
+    pub fn set_prescaler(&mut self, prescaler: u32) {
+        println!("[timer] prescaler={}", prescaler);
+        self.prescaler = prescaler;
+        self.prescale_count = 0;
     }
 
-    pub fn tick(&mut self) {
+    pub fn set_compare(&mut self, compare: u32) {
+        println!("[timer] compare={}", compare);
+        self.compare = compare;
+    }
+
+    /// Clears the status bits that are set in `value`
+    pub fn clear_status(&mut self, value: u32) {
+        self.status &= !value;
+    }
+
+    /// Advances the timer by one clock tick. Returns true when the timer overflowed.
+    pub fn tick(&mut self) -> bool {
+        // Chained timers only count overflows of the previous timer
+        if self.ctrl & CHAINED != 0 {
+            return false;
+        }
+
+        self.clock()
+    }
+
+    /// Feeds an overflow of the previous timer into this timer. Returns true when the timer
+    /// overflowed.
+    pub fn chain_tick(&mut self) -> bool {
+        if self.ctrl & CHAINED == 0 {
+            return false;
+        }
+
+        self.clock()
+    }
+
+    fn clock(&mut self) -> bool {
         // Nothing to count towards
         if self.period == 0 {
-            return;
+            return false;
         }
 
         // Timer not enabled
         if self.ctrl & ENABLED == 0 {
-            return;
+            return false;
+        }
+
+        // Divide the incoming clock by the prescaler
+        if self.prescale_count < self.prescaler {
+            self.prescale_count += 1;
+            return false;
+        }
+        self.prescale_count = 0;
+
+        let expired = if self.ctrl & COUNT_DOWN != 0 {
+            self.counter = self.counter.saturating_sub(1);
+            self.counter == 0
+        } else {
+            self.counter = self.counter.wrapping_add(1);
+            self.counter >= self.period
+        };
+
+        if self.ctrl & COMPARE_ENABLED != 0 && self.counter == self.compare {
+            self.status |= STATUS_COMPARE;
+        }
+
+        if !expired {
+            return false;
         }
 
-        self.counter = self.counter.wrapping_add(1);
-
-        if self.counter >= self.period {
-            // Raise IRQ if enabled
-            if self.ctrl & IRQ_ENABLED != 0 {
-                self.irq = true;
-            }
-
-            // Handle one-shot vs periodic
-            if self.ctrl & ONE_SHOT != 0 {
-                // Just stop the timer
-                self.ctrl &= !ENABLED;
-            } else {
-                // Automatically reset the counter
-                self.counter = 0;
-            }
+        self.status |= STATUS_OVERFLOW;
+
+        // Handle one-shot vs periodic
+        if self.ctrl & ONE_SHOT != 0 {
+            // Just stop the timer
+            self.ctrl &= !ENABLED;
+        } else if self.ctrl & COUNT_DOWN != 0 {
+            // Reload from the period
+            self.counter = self.period;
+        } else {
+            // Automatically reset the counter
+            self.counter = 0;
         }
+
+        true
     }
 
     pub fn ack_irq(&mut self) {
         println!("[timer] ack_irq");
-        self.irq = false;
+        self.status = 0;
+    }
+}
+
+impl Snapshot for Timer {
+    fn save(&self, w: &mut StateWriter) {
+        for value in [self.ctrl, self.counter, self.period, self.prescaler, self.prescale_count, self.compare, self.status] {
+            w.u32(value);
+        }
+    }
+
+    fn restore(&mut self, r: &mut StateReader) -> io::Result<()> {
+        self.ctrl = r.u32()?;
+        self.counter = r.u32()?;
+        self.period = r.u32()?;
+        self.prescaler = r.u32()?;
+        self.prescale_count = r.u32()?;
+        self.compare = r.u32()?;
+        self.status = r.u32()?;
+        Ok(())
     }
 }
@@ -1,5 +1,8 @@
 pub mod pty_backend;
 
+use crate::machine::snapshot::{Snapshot, StateReader, StateWriter};
+use std::io;
+
 pub const TX_READY: u32 = 1 << 0; // 1 = Uart ready to accept TX data
 pub const RX_AVAILABLE: u32 = 1 << 1; // 1 = RX data waiting
 pub const IRQ_ENABLE: u32 = 1 << 7; // 0 = IRQ disabled, 1 = IRQ enabled
@@ -9,12 +12,23 @@ pub trait UartBackend: Send {
     fn write_byte(&mut self, byte: u8);
 }
 
+impl UartBackend for Box<dyn UartBackend> {
+    fn read_byte(&mut self) -> Option<u8> {
+        (**self).read_byte()
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        (**self).write_byte(byte)
+    }
+}
 
 pub struct Uart<B: UartBackend> {
     backend: B,
     status: u32,
     rx_buffer: Option<u8>,
     irq: bool,
+    /// Drop transmitted bytes instead of passing them to the backend
+    muted: bool,
 }
 
 impl<B: UartBackend> Uart<B> {
@@ -24,9 +38,17 @@ impl<B: UartBackend> Uart<B> {
             status: TX_READY,
             rx_buffer: None,
             irq: false,
+            muted: false,
         }
     }
 
+    /// Puts the UART back to its power-on state, keeping the backend
+    pub fn reset(&mut self) {
+        self.status = TX_READY;
+        self.rx_buffer = None;
+        self.irq = false;
+    }
+
     pub fn tick(&mut self) {
         self.poll_rx();
     }
@@ -49,19 +71,35 @@ impl<B: UartBackend> Uart<B> {
         }
     }
 
+    /// While muted, transmitted bytes are dropped, e.g. when replaying output the host has
+    /// already seen
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+    }
+
     pub fn poll_rx(&mut self) {
+        if let Some(b) = self.poll_backend() {
+            self.receive(b);
+        }
+    }
+
+    /// Reads a byte from the backend when the RX buffer is free, without receiving it
+    pub fn poll_backend(&mut self) -> Option<u8> {
         // Already something in the buffer
         if self.rx_buffer.is_some() {
-            return;
+            return None;
         }
 
-        if let Some(b) = self.backend.read_byte() {
-            self.rx_buffer = Some(b);
-            self.status |= RX_AVAILABLE;
+        self.backend.read_byte()
+    }
 
-            if (self.status & IRQ_ENABLE) != 0 {
-                self.irq = true;
-            }
+    /// Puts a byte in the RX buffer
+    pub fn receive(&mut self, b: u8) {
+        self.rx_buffer = Some(b);
+        self.status |= RX_AVAILABLE;
+
+        if (self.status & IRQ_ENABLE) != 0 {
+            self.irq = true;
         }
     }
 
@@ -75,8 +113,29 @@ impl<B: UartBackend> Uart<B> {
     }
 
     pub fn write_tx(&mut self, b: u8) {
-        self.backend.write_byte(b);
+        if !self.muted {
+            self.backend.write_byte(b);
+        }
 
         self.status |= TX_READY;
     }
 }
+
+/// Only the registers are saved, the backend stays connected to the host
+impl<B: UartBackend> Snapshot for Uart<B> {
+    fn save(&self, w: &mut StateWriter) {
+        w.u32(self.status);
+        w.bool(self.rx_buffer.is_some());
+        w.u8(self.rx_buffer.unwrap_or(0));
+        w.bool(self.irq);
+    }
+
+    fn restore(&mut self, r: &mut StateReader) -> io::Result<()> {
+        self.status = r.u32()?;
+        let has_rx = r.bool()?;
+        let rx = r.u8()?;
+        self.rx_buffer = has_rx.then_some(rx);
+        self.irq = r.bool()?;
+        Ok(())
+    }
+}
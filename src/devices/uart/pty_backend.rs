@@ -17,28 +17,28 @@ impl PtyBackend {
     pub fn new() -> io::Result<(Self, String)> {
         // Create a new pty pair
         let pty = openpty(None, None)
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("Failed to open pty: {e}")))?;
+            .map_err(|e| io::Error::other(format!("Failed to open pty: {e}")))?;
 
         // Get the slave device path from the slave FD
         let slave_path = ttyname(&pty.slave)
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("Failed to get slave pty name: {e}")))?
+            .map_err(|e| io::Error::other(format!("Failed to get slave pty name: {e}")))?
             .to_string_lossy()
             .into_owned();
 
         // Close slave FD - we only need the master
         close(pty.slave)
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("Failed to close slave pty: {e}")))?;
+            .map_err(|e| io::Error::other(format!("Failed to close slave pty: {e}")))?;
 
         // Make master non-blocking (do this before extracting raw FD)
         let flags_raw = fcntl(&pty.master, FcntlArg::F_GETFL)
-            .map_err(|e| io::Error::from(e))?;
+            .map_err(io::Error::from)?;
         let flags = OFlag::from_bits_truncate(flags_raw);
 
         fcntl(
             &pty.master,
             FcntlArg::F_SETFL(flags | OFlag::O_NONBLOCK),
         )
-            .map_err(|e| io::Error::from(e))?;
+            .map_err(io::Error::from)?;
 
         // Extract the raw FD from the master before it gets dropped
         let master_fd = pty.master.as_raw_fd();
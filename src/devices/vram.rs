@@ -1,4 +1,6 @@
 use crate::bus::BusError;
+use crate::machine::snapshot::{Snapshot, StateReader, StateWriter};
+use std::io;
 
 pub struct Vram {
     pub data: Vec<u8>,
@@ -43,3 +45,13 @@ impl Vram {
         Ok(u32::from_le_bytes(bytes))
     }
 }
+
+impl Snapshot for Vram {
+    fn save(&self, w: &mut StateWriter) {
+        w.bytes(&self.data);
+    }
+
+    fn restore(&mut self, r: &mut StateReader) -> io::Result<()> {
+        r.bytes_into("VRAM", &mut self.data)
+    }
+}
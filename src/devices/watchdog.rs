@@ -0,0 +1,154 @@
+use crate::machine::snapshot::{Snapshot, StateReader, StateWriter};
+use std::io;
+
+pub const ENABLED: u32 = 0x1; // 0 = stopped, 1 = counting down
+pub const NMI_ENABLED: u32 = 0x2; // 1 = raise an NMI when the watchdog expires
+pub const RESET_ENABLED: u32 = 0x4; // 1 = reset the machine when the watchdog expires
+pub const PRESERVE_RAM: u32 = 0x8; // 1 = keep RAM contents over a watchdog reset
+
+// Status register bits (write 1 to clear)
+pub const STATUS_EXPIRED: u32 = 0x1; // The watchdog expired and raised an NMI
+
+/// Timeout after power on, in cycles
+pub const DEFAULT_TIMEOUT: u32 = 1_000_000;
+
+/// What happens when the watchdog expires
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchdogEvent {
+    /// Non-maskable interrupt
+    Nmi,
+    /// Machine reset
+    Reset { preserve_ram: bool },
+}
+
+/// Watchdog timer. Counts down while enabled and has to be kicked before it reaches zero.
+///
+/// With both NMI and reset enabled, the first expiry raises an NMI. If the NMI handler doesn't
+/// acknowledge it (by clearing STATUS_EXPIRED) before the next expiry, the machine is reset.
+/// The NMI stays pending until the core takes it, so it isn't lost while the core is stalled.
+pub struct Watchdog {
+    /// Control register
+    ctrl: u32,
+    /// Cycles between kicks
+    timeout: u32,
+    /// Cycles left until expiry
+    counter: u32,
+    /// Status flags
+    status: u32,
+    /// An NMI was raised and the core hasn't taken it yet
+    nmi_pending: bool,
+}
+
+impl Default for Watchdog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Watchdog {
+    pub fn new() -> Self {
+        Self {
+            ctrl: 0,
+            timeout: DEFAULT_TIMEOUT,
+            counter: DEFAULT_TIMEOUT,
+            status: 0,
+            nmi_pending: false,
+        }
+    }
+
+    pub fn ctrl(&self) -> u32 {
+        self.ctrl
+    }
+    pub fn timeout(&self) -> u32 {
+        self.timeout
+    }
+    pub fn count(&self) -> u32 {
+        self.counter
+    }
+    pub fn status(&self) -> u32 {
+        self.status
+    }
+    pub fn nmi(&self) -> bool {
+        self.nmi_pending
+    }
+
+    pub fn set_ctrl(&mut self, ctrl: u32) {
+        println!("[wdt] ctrl={:08x}", ctrl);
+        if self.ctrl & ENABLED == 0 && ctrl & ENABLED != 0 {
+            self.counter = self.timeout;
+        }
+        self.ctrl = ctrl;
+    }
+
+    /// Sets the timeout and restarts the countdown
+    pub fn set_timeout(&mut self, timeout: u32) {
+        println!("[wdt] timeout={}", timeout);
+        self.timeout = timeout;
+        self.counter = timeout;
+    }
+
+    /// Restarts the countdown
+    pub fn kick(&mut self) {
+        self.counter = self.timeout;
+    }
+
+    /// Clears the status bits that are set in `value`
+    pub fn clear_status(&mut self, value: u32) {
+        self.status &= !value;
+    }
+
+    /// Called when the core has taken the pending NMI
+    pub fn ack_nmi(&mut self) {
+        self.nmi_pending = false;
+    }
+
+    /// Counts down. Returns what should happen when the watchdog expired during this cycle.
+    pub fn tick(&mut self) -> Option<WatchdogEvent> {
+        if self.ctrl & ENABLED == 0 {
+            return None;
+        }
+
+        if self.counter > 0 {
+            self.counter -= 1;
+            return None;
+        }
+
+        println!("[wdt] expired");
+        self.counter = self.timeout;
+
+        let nmi = self.ctrl & NMI_ENABLED != 0;
+        let reset = self.ctrl & RESET_ENABLED != 0;
+
+        if reset && (!nmi || self.status & STATUS_EXPIRED != 0) {
+            return Some(WatchdogEvent::Reset {
+                preserve_ram: self.ctrl & PRESERVE_RAM != 0,
+            });
+        }
+
+        if nmi {
+            self.status |= STATUS_EXPIRED;
+            self.nmi_pending = true;
+            return Some(WatchdogEvent::Nmi);
+        }
+
+        None
+    }
+}
+
+impl Snapshot for Watchdog {
+    fn save(&self, w: &mut StateWriter) {
+        for value in [self.ctrl, self.timeout, self.counter, self.status] {
+            w.u32(value);
+        }
+        w.bool(self.nmi_pending);
+    }
+
+    fn restore(&mut self, r: &mut StateReader) -> io::Result<()> {
+        self.ctrl = r.u32()?;
+        self.timeout = r.u32()?;
+        self.counter = r.u32()?;
+        self.status = r.u32()?;
+        self.nmi_pending = r.bool()?;
+        Ok(())
+    }
+}
@@ -1,9 +1,62 @@
+pub mod history;
+pub mod snapshot;
+
 use crate::NovaBus;
+use crate::bus::{self, Bus, BusError, ProtectedRange, UartPort};
+use crate::devices::audio::wav::WavWriter;
+use crate::devices::display::frame::{Frame, FrameCapture};
+use crate::devices::gpio::PIN_COUNT;
+use crate::devices::gpio::stimulus::GpioStimulus;
+use crate::devices::i2c::I2cDevice;
+use crate::devices::keyboard::host::{self, HostKeyboard};
+use crate::devices::keyboard::script::KeyScript;
+use crate::devices::net::NetBackend;
+use crate::devices::rng::{Rng, RngSource};
+use crate::devices::rtc::{Rtc, RtcClock};
+use crate::devices::smp::{MAX_HARTS, Smp};
+use crate::devices::spi::SpiDevice;
+use crate::devices::syscon::SysRequest;
+use crate::devices::watchdog::WatchdogEvent;
 use crate::cpu::Cpu;
+use crate::cpu::branch::{BranchModel, PredictorKind};
+use crate::cpu::cache::{Cache, CacheConfig};
+use crate::cpu::pipeline::{Pipeline, PipelineConfig};
+use history::{History, Input, InputLog, Watch};
+use snapshot::{MAGIC, Snapshot, StateReader, StateWriter, VERSION, invalid};
+use std::fs;
+use std::io;
+use std::path::Path;
 
 pub struct Machine {
-    pub cpu: Cpu,
+    /// Cores sharing the bus, indexed by hart ID. Core 0 boots and takes all device IRQs.
+    pub cpus: Vec<Cpu>,
     pub bus: NovaBus,
+    /// Framebuffer capture, written on every vsync when set
+    frame_capture: Option<FrameCapture>,
+    /// Audio capture, written whenever the audio device plays a frame
+    audio_capture: Option<WavWriter>,
+    /// Scripted GPIO input changes
+    gpio_stimulus: Option<GpioStimulus>,
+    /// Scripted key events
+    key_script: Option<KeyScript>,
+    /// Host terminal feeding the keyboard
+    host_keyboard: Option<HostKeyboard>,
+    /// Number of cycles stepped so far
+    cycles: u64,
+    /// Loaded program segments, restored when a reset clears RAM
+    boot_image: Vec<(u32, Vec<u32>)>,
+    /// Exit status, set when the program powered the machine off
+    exit_status: Option<u32>,
+    /// Instruction cache of every core, None = no instruction cache
+    icache_config: Option<CacheConfig>,
+    /// Data cache of every core, None = no data cache
+    dcache_config: Option<CacheConfig>,
+    /// Pipeline model of every core, None = one instruction per cycle
+    pipeline_config: Option<PipelineConfig>,
+    /// Branch predictor of every core, None = no prediction
+    branch_predictor: Option<PredictorKind>,
+    /// Checkpoints for going back in time, None = no history
+    history: Option<History>,
 }
 
 impl Default for Machine {
@@ -14,63 +67,849 @@ impl Default for Machine {
 
 impl Machine {
     pub fn new() -> Self {
+        Self::with_uarts(vec![NovaBus::console_uart()])
+    }
+
+    /// Creates a machine with the given UARTs instead of the default PTY console
+    pub fn with_uarts(uarts: Vec<UartPort>) -> Self {
         Self {
-            cpu: Cpu::new(),
-            bus: NovaBus::new(),
+            cpus: vec![Cpu::new()],
+            bus: NovaBus::with_uarts(uarts),
+            frame_capture: None,
+            audio_capture: None,
+            gpio_stimulus: None,
+            key_script: None,
+            host_keyboard: None,
+            cycles: 0,
+            boot_image: Vec::new(),
+            exit_status: None,
+            icache_config: None,
+            dcache_config: None,
+            pipeline_config: None,
+            branch_predictor: None,
+            history: None,
         }
     }
 
+    /// Renders the text mode display from VRAM and FONT RAM
+    pub fn render_display(&self) -> Frame {
+        self.bus.display.render(&self.bus.vram, &self.bus.font_ram)
+    }
+
+    /// Renders the text mode display for an ANSI terminal
+    pub fn render_display_ansi(&self) -> String {
+        self.bus.display.render_ansi(&self.bus.vram)
+    }
+
+    /// Renders the pixel framebuffer
+    pub fn render_framebuffer(&self) -> Frame {
+        self.bus.framebuffer.render()
+    }
+
+    /// Starts writing the framebuffer as numbered PNG files into `dir` on every vsync
+    pub fn start_frame_capture<P: AsRef<Path>>(&mut self, dir: P) -> io::Result<()> {
+        self.frame_capture = Some(FrameCapture::new(dir)?);
+        Ok(())
+    }
+
+    /// Stops the frame capture. Returns the number of frames written.
+    pub fn stop_frame_capture(&mut self) -> u32 {
+        self.frame_capture.take().map(|c| c.count()).unwrap_or(0)
+    }
+
+    /// Starts rendering the audio output to a WAV file (16 bit stereo, at the sample rate
+    /// of the first frame played)
+    pub fn start_audio_capture<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        self.audio_capture = Some(WavWriter::create(path)?);
+        Ok(())
+    }
+
+    /// Stops the audio capture and finishes the WAV file. Returns the number of frames written.
+    pub fn stop_audio_capture(&mut self) -> io::Result<u32> {
+        match self.audio_capture.take() {
+            Some(capture) => capture.finish(),
+            None => Ok(0),
+        }
+    }
+
+    /// Attaches a disk image to the block device
+    pub fn attach_disk<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        self.bus.block.attach(path)
+    }
+
+    /// Connects the host side of the network interface, see `devices::net` for the backends
+    pub fn attach_net_backend(&mut self, backend: Box<dyn NetBackend>) {
+        self.bus.net.attach(backend);
+    }
+
+    /// Disconnects the host side of the network interface
+    pub fn detach_net_backend(&mut self) -> Option<Box<dyn NetBackend>> {
+        self.bus.net.detach()
+    }
+
+    /// Sets the station address of the network interface. Machines sharing a link need
+    /// different addresses.
+    pub fn set_mac_address(&mut self, mac: [u8; 6]) {
+        self.bus.net.set_mac(mac);
+    }
+
+    /// Replays the given key events on the keyboard. Event cycles are absolute machine cycles.
+    pub fn set_key_script(&mut self, script: KeyScript) {
+        self.key_script = Some(script);
+    }
+
+    /// Loads a key event script, see `KeyScript` for the format
+    pub fn load_key_script<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        self.key_script = Some(KeyScript::load(path)?);
+        Ok(())
+    }
+
+    /// Feeds the keyboard from the host terminal until `detach_host_keyboard()` is called
+    pub fn attach_host_keyboard(&mut self) -> io::Result<()> {
+        self.host_keyboard = Some(HostKeyboard::new()?);
+        Ok(())
+    }
+
+    /// Stops reading the host terminal and restores its settings
+    pub fn detach_host_keyboard(&mut self) {
+        self.host_keyboard = None;
+    }
+
+    /// Turns the machine into a multi-core machine with `count` cores (1 to `MAX_HARTS`), all
+    /// starting at the reset vector. Resets the cores; call it before loading a program.
+    pub fn set_core_count(&mut self, count: usize) {
+        let count = count.clamp(1, MAX_HARTS);
+        self.cpus = self.new_cpus(count);
+        self.bus.smp = Smp::new(count);
+    }
+
+    /// Creates cold cores with the configured caches
+    fn new_cpus(&self, count: usize) -> Vec<Cpu> {
+        (0..count as u32)
+            .map(|hart_id| {
+                let mut cpu = Cpu::with_hart_id(hart_id);
+                // The configurations were validated when they were set
+                cpu.icache = self.icache_config.and_then(|config| Cache::new(config).ok());
+                cpu.dcache = self.dcache_config.and_then(|config| Cache::new(config).ok());
+                cpu.pipeline = self.pipeline_config.map(Pipeline::new);
+                cpu.branch_predictor = self.branch_predictor.map(|kind| BranchModel::new(kind.build()));
+                cpu
+            })
+            .collect()
+    }
+
+    /// Gives every core an instruction cache, or removes it with None. The caches start
+    /// empty. Cache latencies stall the core, see `CacheConfig`.
+    pub fn set_icache(&mut self, config: Option<CacheConfig>) -> Result<(), String> {
+        self.check_timing_change()?;
+        if let Some(config) = &config {
+            config.validate()?;
+        }
+        self.icache_config = config;
+        for cpu in self.cpus.iter_mut() {
+            cpu.icache = config.and_then(|config| Cache::new(config).ok());
+        }
+        Ok(())
+    }
+
+    /// Times every core as a 5-stage pipeline, or goes back to one instruction per cycle with
+    /// None. Hazards stall the core; the per-core report is in `cpu.pipeline`.
+    pub fn set_pipeline(&mut self, config: Option<PipelineConfig>) -> Result<(), String> {
+        self.check_timing_change()?;
+        self.pipeline_config = config;
+        for cpu in self.cpus.iter_mut() {
+            cpu.pipeline = config.map(Pipeline::new);
+        }
+        Ok(())
+    }
+
+    /// Gives every core a branch predictor of the given kind, or removes it with None. With a
+    /// pipeline only mispredicted branches are flushed. The per-core misprediction report is in
+    /// `cpu.branch_predictor`; custom `BranchPredictor` models can be put there directly.
+    pub fn set_branch_predictor(&mut self, kind: Option<PredictorKind>) -> Result<(), String> {
+        self.check_timing_change()?;
+        self.branch_predictor = kind;
+        for cpu in self.cpus.iter_mut() {
+            cpu.branch_predictor = kind.map(|kind| BranchModel::new(kind.build()));
+        }
+        Ok(())
+    }
+
+    /// True when a core has a cache, pipeline or branch predictor. Their state decides how
+    /// long instructions stall, and it isn't part of the machine state.
+    fn has_timing_models(&self) -> bool {
+        self.cpus.iter().any(|cpu| {
+            cpu.icache.is_some() || cpu.dcache.is_some() || cpu.pipeline.is_some() || cpu.branch_predictor.is_some()
+        })
+    }
+
+    /// Timing models can't be changed while history is enabled, checkpoints don't hold them
+    fn check_timing_change(&self) -> Result<(), String> {
+        if self.history.is_some() {
+            return Err("timing models can't be changed while history is enabled".to_string());
+        }
+        Ok(())
+    }
+
+    /// Gives every core a data cache, or removes it with None. The caches start empty.
+    pub fn set_dcache(&mut self, config: Option<CacheConfig>) -> Result<(), String> {
+        self.check_timing_change()?;
+        if let Some(config) = &config {
+            config.validate()?;
+        }
+        self.dcache_config = config;
+        for cpu in self.cpus.iter_mut() {
+            cpu.dcache = config.and_then(|config| Cache::new(config).ok());
+        }
+        Ok(())
+    }
+
+    /// Lets DMA transfers steal one bus cycle in every `interval` from the cores. 1 is burst
+    /// mode, where the cores wait for the whole transfer. See `dma::DEFAULT_STEAL_INTERVAL`.
+    pub fn set_dma_steal_interval(&mut self, interval: u32) -> Result<(), String> {
+        if interval == 0 {
+            return Err("the DMA steal interval must be at least 1".to_string());
+        }
+        self.bus.dma.set_steal_interval(interval);
+        Ok(())
+    }
+
+    /// Returns true when every core is halted
+    pub fn halted(&self) -> bool {
+        self.cpus.iter().all(|cpu| cpu.halted)
+    }
+
+    /// Attaches a simulated SPI slave to a chip select line of the SPI controller. Not
+    /// possible while history is enabled, see `enable_history`.
+    pub fn attach_spi_device(&mut self, cs: usize, device: Box<dyn SpiDevice>) -> Result<(), String> {
+        if self.history.is_some() {
+            return Err("SPI devices can't be attached while history is enabled".to_string());
+        }
+        self.bus.spi.attach(cs, device);
+        Ok(())
+    }
+
+    /// Attaches a simulated I2C slave at a 7 bit address. Not possible while history is
+    /// enabled, see `enable_history`.
+    pub fn attach_i2c_device(&mut self, addr: u8, device: Box<dyn I2cDevice>) -> Result<(), String> {
+        if self.history.is_some() {
+            return Err("I2C devices can't be attached while history is enabled".to_string());
+        }
+        self.bus.i2c.attach(addr, device);
+        Ok(())
+    }
+
+    /// Selects the source of the random number generator. Use a fixed seed to keep runs
+    /// reproducible.
+    pub fn set_rng_source(&mut self, source: RngSource) {
+        self.bus.rng = Rng::new(source);
+    }
+
+    /// Selects the clock the RTC runs on. The virtual clock (the default) counts emulated
+    /// cycles and keeps runs reproducible, the host clock follows the wall clock.
+    pub fn set_rtc_clock(&mut self, clock: RtcClock) {
+        self.bus.rtc = Rtc::new(clock);
+    }
+
+    /// Exit status passed by the program when it powered off the machine, if it did
+    pub fn exit_status(&self) -> Option<u32> {
+        self.exit_status
+    }
+
+    /// Number of cycles stepped so far
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Drives a GPIO input pin
+    pub fn set_gpio_input(&mut self, pin: u32, high: bool) -> Result<(), String> {
+        if pin >= PIN_COUNT {
+            return Err(format!("GPIO pin {pin} out of range, there are {PIN_COUNT} pins"));
+        }
+        self.bus.gpio.set_input(pin, high);
+        Ok(())
+    }
+
+    /// Returns the level of a GPIO output pin (input pins and pins out of range read as low)
+    pub fn gpio_output(&self, pin: u32) -> bool {
+        pin < PIN_COUNT && self.bus.gpio.outputs() & (1 << pin) != 0
+    }
+
+    /// Returns the levels of all GPIO output pins, one bit per pin
+    pub fn gpio_outputs(&self) -> u32 {
+        self.bus.gpio.outputs()
+    }
+
+    /// Replays the given stimulus on the GPIO inputs. Event cycles are absolute machine cycles.
+    pub fn set_gpio_stimulus(&mut self, stimulus: GpioStimulus) {
+        self.gpio_stimulus = Some(stimulus);
+    }
+
+    /// Loads a GPIO stimulus file, see `GpioStimulus` for the format
+    pub fn load_gpio_stimulus<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        self.gpio_stimulus = Some(GpioStimulus::load(path)?);
+        Ok(())
+    }
+
     pub fn load_program(&mut self, base: u32, words: &[u32]) {
+        self.try_load_program(base, words)
+            .expect("Failed to load program into memory");
+    }
+
+    /// Writes a program segment into memory and remembers it, so it can be restored when a
+    /// reset clears RAM
+    pub fn try_load_program(&mut self, base: u32, words: &[u32]) -> Result<(), BusError> {
         for (i, &word) in words.iter().enumerate() {
-            self.bus
+            self.bus.write32(base + (i as u32) * 4, word)?;
+        }
+        self.boot_image.push((base, words.to_vec()));
+        Ok(())
+    }
+
+    /// Resets the cores and all devices. Unless `preserve_ram` is set, RAM is cleared and the
+    /// loaded program is written back. The reason can be read back by the program.
+    pub fn reset(&mut self, reason: u32, preserve_ram: bool) {
+        self.cpus = self.new_cpus(self.cpus.len());
+        self.bus.reset(reason, preserve_ram);
+
+        if !preserve_ram {
+            // Only segments in RAM are restored. Protected ranges survive the reset, so RAM
+            // is written directly.
+            for (base, words) in &self.boot_image {
+                for (i, &word) in words.iter().enumerate() {
+                    let _ = self.bus.ram.write32(base + (i as u32) * 4, word);
+                }
+            }
+        }
+    }
+
+    /// Writes a snapshot of the machine: the cycle count, the registers of every core, the
+    /// memories, the registers and buffers of every device, the reset reason, the protected
+    /// ranges and the last word written to each MMIO register. Timing models (caches,
+    /// pipeline, branch predictor), host connections and what is attached to the machine (disk
+    /// image, network backend, SPI and I2C slaves) aren't saved.
+    pub fn save_state<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut w = StateWriter::new();
+        w.raw(MAGIC);
+        w.u16(VERSION);
+        w.u16(0);
+        self.write_state(&mut w);
+
+        fs::write(path, w.into_inner())
+    }
+
+    fn write_state(&self, w: &mut StateWriter) {
+        w.u64(self.cycles);
+        w.u32(self.cpus.len() as u32);
+        for cpu in &self.cpus {
+            cpu.save(w);
+        }
+        self.bus.ram.save(w);
+        self.bus.vram.save(w);
+        self.bus.font_ram.save(w);
+        self.bus.timer1.save(w);
+        self.bus.timer2.save(w);
+        self.bus.rtc.save(w);
+        self.bus.rng.save(w);
+        w.u32(self.bus.uarts.len() as u32);
+        for port in &self.bus.uarts {
+            port.uart.save(w);
+        }
+        self.bus.watchdog.save(w);
+        self.bus.dma.save(w);
+        self.bus.block.save(w);
+        self.bus.net.save(w);
+        self.bus.keyboard.save(w);
+        self.bus.gpio.save(w);
+        self.bus.framebuffer.save(w);
+        self.bus.display.save(w);
+        self.bus.audio.save(w);
+        self.bus.spi.save(w);
+        self.bus.i2c.save(w);
+        self.bus.smp.save(w);
+        w.u32(self.bus.reset_reason);
+        w.u32(self.bus.protected.len() as u32);
+        for range in &self.bus.protected {
+            w.u32(range.base);
+            w.u32(range.size);
+            w.bool(range.read_only);
+            w.bool(range.no_exec);
+        }
+        w.u32(self.bus.mmio_written.len() as u32);
+        for (&addr, &value) in &self.bus.mmio_written {
+            w.u32(addr);
+            w.u32(value);
+        }
+    }
+
+    /// Resumes from a snapshot written by `save_state`. The program should be loaded first so
+    /// a later reset can restore it. The machine must have as many UARTs as the one that was
+    /// saved, and the slaves the snapshot was using must be attached; the number of cores
+    /// follows the snapshot. If the file is invalid the machine is left as it was. Any history
+    /// starts over from the snapshot.
+    pub fn load_state<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let data = fs::read(path)?;
+        let mut r = StateReader::new(&data);
+
+        if r.raw(4)? != MAGIC {
+            return Err(invalid("not a nova3201 snapshot"));
+        }
+        let version = r.u16()?;
+        if version != VERSION {
+            return Err(invalid(format!("unsupported snapshot version {version}")));
+        }
+        r.u16()?;
+
+        // Restoring overwrites the machine as it goes, so it is put back on failure
+        let mut backup = StateWriter::new();
+        self.write_state(&mut backup);
+        let restored = self.read_state(&mut r).and_then(|()| match r.remaining() {
+            0 => Ok(()),
+            _ => Err(invalid("trailing data after the snapshot")),
+        });
+        if let Err(e) = restored {
+            self.read_state(&mut StateReader::new(&backup.into_inner()))
+                .expect("the machine's own state can be restored");
+            return Err(e);
+        }
+
+        self.exit_status = None;
+        if let Some((interval, max_checkpoints)) = self.history.as_ref().map(|h| (h.interval(), h.max_checkpoints())) {
+            self.disable_history();
+            // Nothing that keeps history from being enabled can be added while it is, and a load
+            // doesn't add anything either
+            let _ = self.enable_history(interval, max_checkpoints);
+        }
+        Ok(())
+    }
+
+    /// Records a checkpoint every `interval` cycles, keeping the last `max_checkpoints`, and
+    /// logs the host inputs (UART RX bytes, host keyboard input, received network frames,
+    /// disk image reads and writes, host clock readings, host random words) so the machine can
+    /// go back to any cycle since the oldest checkpoint. Any previous history is dropped and
+    /// the first checkpoint is the current cycle.
+    ///
+    /// Checkpoints hold what `save_state` saves plus the position in the GPIO stimulus and key
+    /// script. Replayed cycles don't send anything to the host again: UART output, network
+    /// frames, disk writes and captures are dropped.
+    ///
+    /// Fails while a core has a cache, pipeline or branch predictor: checkpoints don't hold
+    /// their state, so replayed cycles could stall differently. The same goes for SPI and I2C
+    /// slaves, which would see the replayed transfers a second time. Neither can be set up
+    /// while history is enabled.
+    pub fn enable_history(&mut self, interval: u64, max_checkpoints: usize) -> Result<(), String> {
+        if self.has_timing_models() {
+            return Err("history can't be enabled while the cores have timing models".to_string());
+        }
+        if self.bus.spi.has_devices() || self.bus.i2c.has_devices() {
+            return Err("history can't be enabled while SPI or I2C devices are attached".to_string());
+        }
+        self.history = Some(History::new(interval, max_checkpoints));
+        self.bus.inputs = Some(InputLog::new());
+        self.checkpoint();
+        Ok(())
+    }
+
+    /// Drops the history and goes back to talking to the host directly
+    pub fn disable_history(&mut self) {
+        self.history = None;
+        self.bus.inputs = None;
+        for port in self.bus.uarts.iter_mut() {
+            port.uart.set_muted(false);
+        }
+    }
+
+    /// Cycles the machine can seek to: from the oldest checkpoint up to the first cycle that
+    /// hasn't run yet. None without history.
+    pub fn history_range(&self) -> Option<(u64, u64)> {
+        let oldest = self.history.as_ref()?.oldest()?;
+        let present = self.bus.inputs.as_ref().map_or(self.cycles, |log| log.present().max(self.cycles));
+        Some((oldest, present))
+    }
+
+    /// Saves the machine state, followed by how far the scripted inputs got
+    fn checkpoint(&mut self) {
+        let mut w = StateWriter::new();
+        self.write_state(&mut w);
+        w.u64(self.gpio_stimulus.as_ref().map_or(0, |s| s.position()) as u64);
+        w.u64(self.key_script.as_ref().map_or(0, |s| s.position()) as u64);
+        if let Some(history) = self.history.as_mut() {
+            let oldest = history.push(self.cycles, w.into_inner());
+            if let Some(log) = self.bus.inputs.as_mut() {
+                log.forget_before(oldest);
+            }
+        }
+    }
+
+    /// Moves the machine to `cycle`. Going back restores the closest checkpoint and runs
+    /// forward from there. Cycles that ran before are replayed with the recorded host inputs,
+    /// and what they transmit on the UARTs doesn't reach the host again.
+    pub fn seek(&mut self, cycle: u64) -> Result<(), String> {
+        if cycle < self.cycles {
+            let history = self.history.take().ok_or("history is not enabled")?;
+            let restored = match history.before(cycle) {
+                Some((start, state)) => self
+                    .restore_checkpoint(state)
+                    .map(|()| *start)
+                    .map_err(|e| format!("failed to restore the checkpoint at cycle {start}: {e}")),
+                None => Err(format!("cycle {cycle} is before the oldest checkpoint")),
+            };
+            self.history = Some(history);
+
+            let start = restored?;
+            if let Some(log) = self.bus.inputs.as_mut() {
+                log.rewind(start);
+            }
+            self.exit_status = None;
+        }
+
+        while self.cycles < cycle {
+            self.step();
+        }
+        Ok(())
+    }
+
+    fn restore_checkpoint(&mut self, state: &[u8]) -> io::Result<()> {
+        let mut r = StateReader::new(state);
+        self.read_state(&mut r)?;
+        let (stimulus, script) = (r.u64()? as usize, r.u64()? as usize);
+        if let Some(s) = self.gpio_stimulus.as_mut() {
+            s.set_position(stimulus);
+        }
+        if let Some(s) = self.key_script.as_mut() {
+            s.set_position(script);
+        }
+        Ok(())
+    }
+
+    /// Goes back one machine cycle
+    pub fn step_back(&mut self) -> Result<(), String> {
+        let cycle = self.cycles.checked_sub(1).ok_or("already at the first cycle")?;
+        self.seek(cycle)
+    }
+
+    /// Runs backwards to the last cycle that changed the watched register or word and stops
+    /// right before it, so the instruction responsible is about to execute. Returns that
+    /// cycle, or None when nothing changed it since the oldest checkpoint; the machine then
+    /// stays where it was. Writes of the value already there can't be told apart from no write.
+    pub fn run_back_to_write(&mut self, watch: Watch) -> Result<Option<u64>, String> {
+        if self.history.is_none() {
+            return Err("history is not enabled".to_string());
+        }
+        self.watched(watch)?;
+
+        let present = self.cycles;
+        let mut end = present;
+        // Replay the checkpoint intervals one by one, newest first
+        while end > 0 {
+            let Some(start) = self.history.as_ref().and_then(|h| h.before(end - 1)).map(|&(c, _)| c) else {
+                break;
+            };
+            self.seek(start)?;
+
+            let mut value = self.watched(watch)?;
+            let mut last_write = None;
+            while self.cycles < end {
+                self.step();
+                let new = self.watched(watch)?;
+                if new != value {
+                    last_write = Some(self.cycles - 1);
+                    value = new;
+                }
+            }
+            if let Some(cycle) = last_write {
+                self.seek(cycle)?;
+                return Ok(Some(cycle));
+            }
+            end = start;
+        }
+
+        self.seek(present)?;
+        Ok(None)
+    }
+
+    fn watched(&self, watch: Watch) -> Result<u32, String> {
+        match watch {
+            Watch::Register { hart, reg } => self
+                .cpus
+                .get(hart)
+                .and_then(|cpu| cpu.regs().get(reg).copied())
+                .ok_or_else(|| format!("hart {hart} has no register r{reg}")),
+            // RAM starts at address 0
+            Watch::Word(addr) => self
+                .bus
                 .ram
-                .write32(base + (i as u32) * 4, word)
-                .expect("Failed to load program into memory");
+                .read32(addr & !3)
+                .map_err(|_| format!("0x{addr:08X} is not in RAM")),
+        }
+    }
+
+    fn read_state(&mut self, r: &mut StateReader) -> io::Result<()> {
+        let cycles = r.u64()?;
+        let cores = r.u32()? as usize;
+        if cores == 0 || cores > MAX_HARTS {
+            return Err(invalid(format!("invalid core count {cores} in snapshot")));
+        }
+        if cores != self.cpus.len() {
+            self.set_core_count(cores);
+        }
+        for cpu in self.cpus.iter_mut() {
+            cpu.restore(r)?;
+        }
+        self.bus.ram.restore(r)?;
+        self.bus.vram.restore(r)?;
+        self.bus.font_ram.restore(r)?;
+        self.bus.timer1.restore(r)?;
+        self.bus.timer2.restore(r)?;
+        self.bus.rtc.restore(r)?;
+        self.bus.rng.restore(r)?;
+        let uarts = r.u32()? as usize;
+        if uarts != self.bus.uarts.len() {
+            return Err(invalid(format!(
+                "snapshot has {uarts} UARTs, the machine has {}",
+                self.bus.uarts.len()
+            )));
+        }
+        for port in self.bus.uarts.iter_mut() {
+            port.uart.restore(r)?;
+        }
+        self.bus.watchdog.restore(r)?;
+        self.bus.dma.restore(r)?;
+        self.bus.block.restore(r)?;
+        self.bus.net.restore(r)?;
+        self.bus.keyboard.restore(r)?;
+        self.bus.gpio.restore(r)?;
+        self.bus.framebuffer.restore(r)?;
+        self.bus.display.restore(r)?;
+        self.bus.audio.restore(r)?;
+        self.bus.spi.restore(r)?;
+        self.bus.i2c.restore(r)?;
+        self.bus.smp.restore(r)?;
+        self.bus.reset_reason = r.u32()?;
+        let ranges = r.u32()?;
+        self.bus.protected.clear();
+        for _ in 0..ranges {
+            self.bus.protected.push(ProtectedRange {
+                base: r.u32()?,
+                size: r.u32()?,
+                read_only: r.bool()?,
+                no_exec: r.bool()?,
+            });
         }
+        let registers = r.u32()?;
+        self.bus.mmio_written.clear();
+        for _ in 0..registers {
+            let addr = r.u32()?;
+            self.bus.mmio_written.insert(addr, r.u32()?);
+        }
+
+        self.cycles = cycles;
+        Ok(())
     }
 }
 
 /// Structure that holds the current state of IRQ lines
+#[derive(Default)]
 pub struct IrqLines {
     pub timer1: bool,
     pub timer2: bool,
-    pub uart: bool,
+    pub rtc: bool,
+    pub vsync: bool,
+    pub gpio: bool,
+    pub block: bool,
+    pub dma: bool,
+    pub keyboard: bool,
+    pub audio: bool,
+    pub net: bool,
+    /// Bitmask of asserted UART IRQ lines (bit n = line n)
+    pub uart: u32,
+    /// Non-maskable interrupt (watchdog), asserted until the core takes it
+    pub nmi: bool,
+    /// Inter-processor interrupt pending for this core
+    pub ipi: bool,
 }
 
 impl Machine {
+    /// Feeds the keyboard from the host terminal. What was typed is a host input: replayed
+    /// cycles type the logged bytes and leave the terminal alone.
+    fn host_keyboard_tick(&mut self) {
+        let input = match self.bus.inputs.as_mut() {
+            Some(log) if !log.live() => {
+                let mut input = Vec::new();
+                while let Some(byte) = log.replay(|input| match *input {
+                    Input::HostKey { byte } => Some(byte),
+                    _ => None,
+                }) {
+                    input.push(byte);
+                }
+                input
+            }
+            log => {
+                let Some(host) = self.host_keyboard.as_mut() else {
+                    return;
+                };
+                let input = host.read_input();
+                if let Some(log) = log {
+                    for &byte in &input {
+                        log.record(Input::HostKey { byte });
+                    }
+                }
+                input
+            }
+        };
+        host::type_input(&mut self.bus.keyboard, &input);
+    }
+
     pub fn step(&mut self) {
-        self.bus.timer1.tick();
+        // Cycles that already ran replay the recorded host inputs and stay quiet
+        let mut replaying = false;
+        if let Some(log) = self.bus.inputs.as_mut() {
+            log.begin_cycle(self.cycles);
+            replaying = !log.live();
+            for port in self.bus.uarts.iter_mut() {
+                port.uart.set_muted(replaying);
+            }
+        }
+        if self.history.as_ref().is_some_and(|h| h.due(self.cycles)) {
+            self.checkpoint();
+        }
+
+        if let Some(stimulus) = self.gpio_stimulus.as_mut() {
+            stimulus.apply(self.cycles, &mut self.bus.gpio);
+        }
+        self.bus.gpio.tick();
+
+        if let Some(script) = self.key_script.as_mut() {
+            script.apply(self.cycles, &mut self.bus.keyboard);
+        }
+        self.host_keyboard_tick();
+        self.bus.keyboard.tick();
+
+        // Timer2 can be chained to count timer1 overflows
+        let timer1_overflow = self.bus.timer1.tick();
         self.bus.timer2.tick();
-        self.bus.uart.tick();
+        if timer1_overflow {
+            self.bus.timer2.chain_tick();
+        }
+        self.bus.rtc_tick();
+        self.bus.rng_tick();
+
+        // An NMI stays pending in the watchdog until hart 0 takes it
+        if let Some(WatchdogEvent::Reset { preserve_ram }) = self.bus.watchdog.tick() {
+            self.reset(bus::RESET_WATCHDOG, preserve_ram);
+            self.cycles += 1;
+            return;
+        }
+
+        if let Some(req) = self.bus.block.tick() {
+            self.bus.block_transfer(req);
+        }
+
+        self.bus.net_tick();
+
+        let dma_busy = self.bus.dma_tick();
+
+        if self.bus.framebuffer.tick()
+            && !replaying
+            && let Some(capture) = self.frame_capture.as_mut()
+            && let Err(e) = capture.write(&self.bus.framebuffer.render())
+        {
+            eprintln!("[fb] frame capture failed, stopping capture: {e}");
+            self.frame_capture = None;
+        }
+
+        if let Some((left, right)) = self.bus.audio.tick()
+            && !replaying
+            && let Some(capture) = self.audio_capture.as_mut()
+            && let Err(e) = capture.write_frame(left, right, self.bus.audio.rate())
+        {
+            eprintln!("[audio] capture failed, stopping capture: {e}");
+            self.audio_capture = None;
+        }
+
+        let uart_irq = self.bus.uart_tick();
 
         let irq = IrqLines {
             timer1: self.bus.timer1.irq(),
             timer2: self.bus.timer2.irq(),
-            uart: self.bus.uart.irq(),
+            rtc: self.bus.rtc.irq(),
+            vsync: self.bus.framebuffer.irq(),
+            gpio: self.bus.gpio.irq(),
+            block: self.bus.block.irq(),
+            dma: self.bus.dma.irq(),
+            keyboard: self.bus.keyboard.irq(),
+            audio: self.bus.audio.irq(),
+            net: self.bus.net.irq(),
+            uart: uart_irq,
+            nmi: self.bus.watchdog.nmi(),
+            ipi: self.bus.smp.ipi(0),
         };
 
-        let _ = self.cpu.step(&mut self.bus, &irq);
+        // A DMA transfer steals this bus cycle from the cores, which then see the IRQ lines on
+        // the next cycle they run. Otherwise every core executes one instruction, starting
+        // with a different core each cycle so none of them always wins a race for the bus.
+        if !dma_busy {
+            let count = self.cpus.len();
+            for i in 0..count {
+                let hart = (self.cycles as usize + i) % count;
+                let _ = if hart == 0 {
+                    // A core that executes this cycle takes the NMI, a stalled one later
+                    if irq.nmi && self.cpus[0].ready() {
+                        self.bus.watchdog.ack_nmi();
+                    }
+                    self.cpus[0].step(&mut self.bus, &irq)
+                } else {
+                    let ipi = IrqLines {
+                        ipi: self.bus.smp.ipi(hart),
+                        ..IrqLines::default()
+                    };
+                    self.cpus[hart].step(&mut self.bus, &ipi)
+                };
+            }
+        }
+        self.cycles += 1;
+
+        match self.bus.syscon.take_request() {
+            Some(SysRequest::Reset { preserve_ram }) => self.reset(bus::RESET_SOFT, preserve_ram),
+            Some(SysRequest::PowerOff { status }) => {
+                self.exit_status = Some(status);
+                for cpu in self.cpus.iter_mut() {
+                    cpu.halted = true;
+                }
+            }
+            None => {}
+        }
     }
 
     // Copy this function to replace your current inspect() implementation
     // This is the recommended "Boxed, Clean Layout" version
 
     pub fn inspect(&self) {
+        for cpu in &self.cpus {
+            Self::inspect_cpu(cpu, self.cpus.len() > 1);
+        }
+    }
+
+    fn inspect_cpu(cpu: &Cpu, show_hart: bool) {
+        let title = if show_hart { format!("CPU {} State", cpu.hart_id()) } else { "CPU State".to_string() };
+
         println!("┌─────────────────────────────────────────────────────────────────┐");
-        println!("│ CPU State                                                       │");
+        println!("│ {:<64}│", title);
         println!("├─────────────────────────────────────────────────────────────────┤");
 
         // Program Counter and Status
         println!("│ PC:     0x{:08X}  SR:     0x{:08X}  Halted: {:5}        │",
-                 self.cpu.pc(), self.cpu.sr(), self.cpu.halted());
+                 cpu.pc(), cpu.sr(), cpu.halted());
         println!("│ EPC:    0x{:08X}  Cause:  0x{:08X}                      │",
-                 self.cpu.epc(), self.cpu.cause());
+                 cpu.epc(), cpu.cause());
+        println!("│ ESR:    0x{:08X}  BadVA:  0x{:08X}                      │",
+                 cpu.esr(), cpu.badvaddr());
 
         println!("├─────────────────────────────────────────────────────────────────┤");
         println!("│ Registers                                                       │");
         println!("├─────────────────────────────────────────────────────────────────┤");
 
-        let regs = self.cpu.regs();
+        let regs = cpu.regs();
 
         // Print registers in rows of 4
         for row in 0..8 {
@@ -0,0 +1,188 @@
+use std::collections::VecDeque;
+
+/// Input from the host that makes a run non-deterministic. Recorded while the machine runs
+/// forward and fed back in when the same cycles are replayed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Input {
+    /// Byte received by a UART, by index in `NovaBus::uarts`
+    UartRx { port: usize, byte: u8 },
+    /// Host clock reading (microseconds) seen through the RTC seconds register
+    HostClock { us: u64 },
+    /// The RTC alarm went off according to the host clock
+    RtcAlarm,
+    /// Word drawn from the host random source
+    HostRandom { word: u32 },
+    /// Byte typed on the host keyboard
+    HostKey { byte: u8 },
+    /// Frame handed to the NIC by its backend
+    NetRx { frame: Vec<u8> },
+    /// Sectors read from the disk image, or the error code
+    BlockRead { data: Result<Vec<u8>, u32> },
+    /// Outcome of a write or flush to the disk image
+    BlockDone { result: Result<(), u32> },
+}
+
+/// Inputs by cycle. Cycles before `present` have been executed already and replay the log;
+/// from `present` on inputs come from the host and are recorded.
+#[derive(Debug)]
+pub struct InputLog {
+    entries: VecDeque<(u64, Input)>,
+    /// Cycle being executed
+    cycle: u64,
+    /// First cycle that hasn't been executed yet
+    present: u64,
+    /// The current cycle runs for the first time
+    live: bool,
+    /// Next entry to replay
+    cursor: usize,
+    /// Logged inputs a replay passed without taking
+    missed: u64,
+}
+
+impl Default for InputLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InputLog {
+    pub fn new() -> Self {
+        Self {
+            entries: VecDeque::new(),
+            cycle: 0,
+            present: 0,
+            live: true,
+            cursor: 0,
+            missed: 0,
+        }
+    }
+
+    /// False while replaying cycles that already ran
+    pub fn live(&self) -> bool {
+        self.live
+    }
+    /// First cycle that hasn't been executed yet
+    pub fn present(&self) -> u64 {
+        self.present
+    }
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+    /// Logged inputs that replays skipped because nothing asked for them in their cycle. Not
+    /// zero means a replay went differently from the first run.
+    pub fn missed(&self) -> u64 {
+        self.missed
+    }
+
+    /// Called before the machine executes `cycle`
+    pub fn begin_cycle(&mut self, cycle: u64) {
+        self.cycle = cycle;
+        self.live = cycle >= self.present;
+        if self.live {
+            self.present = cycle + 1;
+            self.cursor = self.entries.len();
+        }
+        // Inputs of earlier cycles that weren't taken can't be used any more
+        while self.entries.get(self.cursor).is_some_and(|&(c, _)| c < cycle) {
+            self.cursor += 1;
+            self.missed += 1;
+        }
+    }
+
+    /// Replays from `cycle` on, after the machine was restored to that cycle
+    pub fn rewind(&mut self, cycle: u64) {
+        self.cursor = self.entries.partition_point(|&(c, _)| c < cycle);
+    }
+
+    /// Drops the inputs of cycles before `cycle`, which can't be replayed any more
+    pub fn forget_before(&mut self, cycle: u64) {
+        let count = self.entries.partition_point(|&(c, _)| c < cycle);
+        self.entries.drain(..count);
+        self.cursor = self.cursor.saturating_sub(count);
+    }
+
+    /// Latest host clock reading logged before the next entry to replay
+    pub fn last_clock(&self) -> Option<u64> {
+        self.entries.range(..self.cursor).rev().find_map(|(_, input)| match *input {
+            Input::HostClock { us } => Some(us),
+            _ => None,
+        })
+    }
+
+    /// Records an input of the current cycle
+    pub fn record(&mut self, input: Input) {
+        self.entries.push_back((self.cycle, input));
+    }
+
+    /// Takes the next logged input if it belongs to the current cycle and `f` accepts it
+    pub fn replay<T>(&mut self, f: impl FnOnce(&Input) -> Option<T>) -> Option<T> {
+        let (cycle, input) = self.entries.get(self.cursor)?;
+        if *cycle != self.cycle {
+            return None;
+        }
+        let value = f(input)?;
+        self.cursor += 1;
+        Some(value)
+    }
+}
+
+/// What `Machine::run_back_to_write` looks for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Watch {
+    /// General purpose register of a core
+    Register { hart: usize, reg: usize },
+    /// Aligned word of RAM
+    Word(u32),
+}
+
+/// Checkpoints taken every `interval` cycles. The oldest ones are dropped to keep at most
+/// `max_checkpoints`, which bounds how far back the machine can go.
+pub struct History {
+    interval: u64,
+    max_checkpoints: usize,
+    /// Cycle and machine state, oldest first
+    checkpoints: VecDeque<(u64, Vec<u8>)>,
+}
+
+impl History {
+    pub fn new(interval: u64, max_checkpoints: usize) -> Self {
+        Self {
+            interval: interval.max(1),
+            max_checkpoints: max_checkpoints.max(1),
+            checkpoints: VecDeque::new(),
+        }
+    }
+
+    pub fn interval(&self) -> u64 {
+        self.interval
+    }
+    pub fn max_checkpoints(&self) -> usize {
+        self.max_checkpoints
+    }
+    /// Oldest cycle the machine can go back to
+    pub fn oldest(&self) -> Option<u64> {
+        self.checkpoints.front().map(|&(cycle, _)| cycle)
+    }
+
+    /// True when a checkpoint should be taken before executing `cycle`
+    pub fn due(&self, cycle: u64) -> bool {
+        cycle.is_multiple_of(self.interval) && self.checkpoints.back().is_none_or(|&(last, _)| last < cycle)
+    }
+
+    /// Stores a checkpoint. Returns the cycle of the oldest checkpoint kept.
+    pub fn push(&mut self, cycle: u64, state: Vec<u8>) -> u64 {
+        if self.checkpoints.len() == self.max_checkpoints {
+            self.checkpoints.pop_front();
+        }
+        self.checkpoints.push_back((cycle, state));
+        self.checkpoints[0].0
+    }
+
+    /// Latest checkpoint at or before `cycle`
+    pub fn before(&self, cycle: u64) -> Option<&(u64, Vec<u8>)> {
+        self.checkpoints.iter().rev().find(|&&(c, _)| c <= cycle)
+    }
+}
@@ -0,0 +1,129 @@
+use std::io;
+
+/// Snapshot file header: magic, version (u16) and a reserved u16, followed by the state of
+/// each component in a fixed order (see `Machine::save_state`). All values are little endian.
+pub const MAGIC: &[u8; 4] = b"NVST";
+pub const VERSION: u16 = 1;
+
+pub(crate) fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+/// Component state that can be written to a snapshot and read back
+pub trait Snapshot {
+    fn save(&self, w: &mut StateWriter);
+    fn restore(&mut self, r: &mut StateReader) -> io::Result<()>;
+}
+
+/// Serializes state into a snapshot buffer
+#[derive(Default)]
+pub struct StateWriter {
+    buf: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.buf
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+    pub fn u16(&mut self, value: u16) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+    pub fn u32(&mut self, value: u32) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+    pub fn u64(&mut self, value: u64) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+    /// Raw bytes, without a length
+    pub fn raw(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+    /// Length prefixed bytes
+    pub fn bytes(&mut self, data: &[u8]) {
+        self.u32(data.len() as u32);
+        self.raw(data);
+    }
+}
+
+/// Reads state back from a snapshot buffer. Running past the end is an `UnexpectedEof` error.
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    /// Bytes not read yet
+    pub fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    pub fn raw(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.remaining() < len {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "snapshot is truncated"));
+        }
+        let data = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(data)
+    }
+
+    fn array<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        Ok(self.raw(N)?.try_into().unwrap())
+    }
+
+    pub fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.raw(1)?[0])
+    }
+    pub fn u16(&mut self) -> io::Result<u16> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+    pub fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+    pub fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+    pub fn bool(&mut self) -> io::Result<bool> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            b => Err(invalid(format!("invalid boolean {b} in snapshot"))),
+        }
+    }
+
+    /// Reads length prefixed bytes of at most `max` bytes
+    pub fn bytes(&mut self, what: &str, max: usize) -> io::Result<&'a [u8]> {
+        let len = self.u32()? as usize;
+        if len > max {
+            return Err(invalid(format!("snapshot has {len} bytes of {what}, at most {max} fit")));
+        }
+        self.raw(len)
+    }
+
+    /// Reads length prefixed bytes into `dest`, which must have the same length
+    pub fn bytes_into(&mut self, what: &str, dest: &mut [u8]) -> io::Result<()> {
+        let len = self.u32()? as usize;
+        if len != dest.len() {
+            return Err(invalid(format!(
+                "snapshot has {len} bytes of {what}, expected {}",
+                dest.len()
+            )));
+        }
+        dest.copy_from_slice(self.raw(len)?);
+        Ok(())
+    }
+}